@@ -0,0 +1,112 @@
+#[cfg(feature = "export_gltf")]
+mod write_gltf;
+mod write_model;
+mod write_palette;
+mod write_scene;
+
+use std::io::Write;
+
+use bevy::math::UVec3;
+use dot_vox::{DotVoxData, Frame, Layer, SceneNode, ShapeModel};
+use thiserror::Error;
+
+use crate::{VoxelData, VoxelModel, VoxelPalette};
+
+#[cfg(feature = "export_gltf")]
+pub use write_gltf::{write_glb_instances, write_glb_scene};
+pub use write_scene::write_vox_scene;
+
+/// The version number written to the header of exported `.vox` files
+const VOX_VERSION: u32 = 150;
+
+/// An error that occurred while exporting voxel data to a `.vox` file
+#[derive(Error, Debug)]
+pub enum VoxExportError {
+    /// Magica Voxel models cannot be larger than 256 voxels along any axis
+    #[error("model of size {0} exceeds the Magica Voxel limit of 256 voxels along each axis")]
+    ModelTooLarge(UVec3),
+    /// The hierarchy being exported does not contain any [`crate::VoxelModelInstance`]s
+    #[error("no voxel model instances were found in the hierarchy")]
+    NoModels,
+    /// A `.vox` file has a single palette, so all of the instances being exported must share the same [`crate::VoxelContext`]
+    #[error("the instances being exported use more than one voxel context")]
+    MultipleContexts,
+    /// A [`VoxelModel`] or [`crate::VoxelContext`] referenced by the hierarchy is not loaded
+    #[error("a voxel model or context referenced by the hierarchy is not loaded")]
+    AssetNotLoaded,
+    /// The writer returned an error
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl VoxelData {
+    /// Serializes the voxel data as a Magica Voxel `.vox` file containing a single model.
+    ///
+    /// Performs the inverse of the coordinate conversion applied when loading, from bevy's right-handed Y-up to Magica Voxel's
+    /// left-handed Z-up, and writes the colors and materials of `palette` back to Magica Voxel's palette and material chunks.
+    ///
+    /// ### Arguments
+    /// * `palette` - the palette used by the model. This would usually be the [`crate::VoxelContext::palette`] of the model's context.
+    /// * `writer` - the destination for the `.vox` bytes
+    pub fn write_vox<W: Write>(
+        &self,
+        palette: &VoxelPalette,
+        writer: &mut W,
+    ) -> Result<(), VoxExportError> {
+        let model = self.to_model()?;
+        let data = DotVoxData {
+            version: VOX_VERSION,
+            index_map: vec![],
+            models: vec![model],
+            palette: palette.to_dot_vox_colors(),
+            materials: palette.to_dot_vox_materials(&self.settings),
+            scenes: single_model_scene(),
+            layers: vec![Layer {
+                attributes: Default::default(),
+            }],
+        };
+        data.write_vox(writer)?;
+        Ok(())
+    }
+}
+
+impl VoxelModel {
+    /// Serializes the model as a Magica Voxel `.vox` file. See [`VoxelData::write_vox`].
+    pub fn write_vox<W: Write>(
+        &self,
+        palette: &VoxelPalette,
+        writer: &mut W,
+    ) -> Result<(), VoxExportError> {
+        self.data.write_vox(palette, writer)
+    }
+}
+
+/// The minimal scene graph that Magica Voxel writes for a file containing a single model:
+/// root transform -> group -> transform -> shape
+fn single_model_scene() -> Vec<SceneNode> {
+    vec![
+        SceneNode::Transform {
+            attributes: Default::default(),
+            frames: vec![Frame::default()],
+            child: 1,
+            layer_id: u32::MAX,
+        },
+        SceneNode::Group {
+            attributes: Default::default(),
+            children: vec![2],
+        },
+        SceneNode::Transform {
+            attributes: Default::default(),
+            frames: vec![Frame::default()],
+            child: 3,
+            layer_id: 0,
+        },
+        SceneNode::Shape {
+            attributes: Default::default(),
+            models: vec![ShapeModel {
+                model_id: 0,
+                attributes: Default::default(),
+            }],
+        },
+    ]
+}
@@ -0,0 +1,697 @@
+use std::io::{Cursor, Write};
+
+use bevy::{
+    asset::{AssetId, Assets},
+    color::{ColorToComponents, ColorToPacked, LinearRgba, Srgba},
+    core::Name,
+    ecs::{entity::Entity, world::World},
+    hierarchy::{Children, Parent},
+    image::Image,
+    pbr::StandardMaterial,
+    prelude::{AlphaMode, GlobalTransform, Mesh, Transform},
+    render::{
+        mesh::{Indices, MeshVertexAttribute, VertexAttributeValues},
+        render_resource::{PrimitiveTopology, TextureFormat},
+    },
+    scene::Scene,
+    utils::HashMap,
+};
+use serde_json::{json, Map, Value};
+
+use crate::{MeshColorMode, VoxelContext, VoxelModel, VoxelModelInstance, VoxelQueryable};
+
+use super::VoxExportError;
+
+/// The `glTF` magic number that starts a binary glTF file
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+const CHUNK_BIN: u32 = 0x004E_4942;
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const NEAREST: u32 = 9728;
+
+/// Serializes the hierarchy of a loaded voxel [`Scene`] as a binary glTF (`.glb`) file, with a node for each entity that
+/// contains [`VoxelModelInstance`]s, named after its [`Name`] without the names of its ancestors.
+///
+/// The meshes are written along with their [`StandardMaterial`]s, including the palette's color, emission,
+/// metallic-roughness and transmission textures. Emission brighter than glTF's factors allow is written with the
+/// `KHR_materials_emissive_strength` extension, and translucent materials with `KHR_materials_transmission`,
+/// `KHR_materials_ior` and `KHR_materials_volume`. Instances with several models (animation frames) are written with
+/// their first model. Custom materials from a [`crate::VoxelMaterialFactory`] are not written, and neither are cloud
+/// volumes.
+///
+/// Models loaded with [`crate::VoxLoaderSettings::lazy_meshing`] that have not been spawned yet are meshed for the export.
+///
+/// ### Arguments
+/// * `world` - the world holding the [`VoxelModel`], [`VoxelContext`], [`Mesh`], [`StandardMaterial`] and [`Image`]
+///   assets used by the scene
+/// * `scene` - the scene to export, such as one loaded from a `.vox` file
+/// * `writer` - the destination for the `.glb` bytes
+pub fn write_glb_scene<W: Write>(
+    world: &World,
+    scene: &Scene,
+    writer: &mut W,
+) -> Result<(), VoxExportError> {
+    let mut roots: Vec<Entity> = scene
+        .world
+        .iter_entities()
+        .filter(|entity| !entity.contains::<Parent>())
+        .map(|entity| entity.id())
+        .collect();
+    roots.sort();
+    let mut gltf = GltfWriter::new(world);
+    let mut nodes = Vec::new();
+    for root in roots {
+        if let Some(node) = gltf.write_hierarchy(&scene.world, root, None)? {
+            nodes.push(node);
+        }
+    }
+    gltf.finish(nodes, writer)
+}
+
+/// Serializes a list of spawned [`VoxelModelInstance`] entities as a binary glTF (`.glb`) file, with a node at the
+/// [`GlobalTransform`] of each instance, named after its [`Name`]. Entities without a [`VoxelModelInstance`] are
+/// skipped. See [`write_glb_scene`] for how the meshes and materials are written.
+///
+/// ### Arguments
+/// * `world` - the world containing the entities and their assets
+/// * `entities` - the instances to export
+/// * `writer` - the destination for the `.glb` bytes
+pub fn write_glb_instances<W: Write>(
+    world: &World,
+    entities: &[Entity],
+    writer: &mut W,
+) -> Result<(), VoxExportError> {
+    let mut gltf = GltfWriter::new(world);
+    let mut nodes = Vec::new();
+    for entity in entities.iter().copied() {
+        let Some(instance) = world.get::<VoxelModelInstance>(entity) else {
+            continue;
+        };
+        let mut node = Map::new();
+        if let Some(name) = world.get::<Name>(entity) {
+            node.insert("name".to_string(), json!(name.as_str()));
+        }
+        let transform = match world.get::<GlobalTransform>(entity) {
+            Some(global_transform) => global_transform.compute_transform(),
+            None => world.get::<Transform>(entity).copied().unwrap_or_default(),
+        };
+        write_transform(&mut node, &transform);
+        if let Some(mesh) = gltf.write_instance(instance)? {
+            node.insert("mesh".to_string(), json!(mesh));
+        }
+        nodes.push(gltf.push_node(node));
+    }
+    gltf.finish(nodes, writer)
+}
+
+/// Collects the JSON and binary buffer of a glTF file, writing each asset once however many nodes use it
+struct GltfWriter<'w> {
+    models: &'w Assets<VoxelModel>,
+    contexts: &'w Assets<VoxelContext>,
+    meshes: &'w Assets<Mesh>,
+    materials: &'w Assets<StandardMaterial>,
+    images: &'w Assets<Image>,
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    gltf_nodes: Vec<Value>,
+    gltf_meshes: Vec<Value>,
+    gltf_materials: Vec<Value>,
+    gltf_textures: Vec<Value>,
+    gltf_images: Vec<Value>,
+    extensions_used: Vec<String>,
+    mesh_ids: HashMap<(AssetId<VoxelModel>, AssetId<VoxelContext>), Option<usize>>,
+    material_ids: HashMap<MaterialKey, usize>,
+    /// The index of each texture, and the factor that its values were divided by to fit in 8 bits
+    texture_ids: HashMap<AssetId<Image>, Option<(usize, f32)>>,
+}
+
+/// Translucent materials are copies of the context's transmissive material with the index of refraction and thickness
+/// of the model, so are told apart by those as well
+#[derive(PartialEq, Eq, Hash)]
+struct MaterialKey {
+    material: AssetId<StandardMaterial>,
+    ior_and_thickness: Option<(u32, u32)>,
+    /// Meshes with vertex colors don't sample the palette texture
+    vertex_colors: bool,
+}
+
+impl<'w> GltfWriter<'w> {
+    fn new(world: &'w World) -> Self {
+        Self {
+            models: world.resource::<Assets<VoxelModel>>(),
+            contexts: world.resource::<Assets<VoxelContext>>(),
+            meshes: world.resource::<Assets<Mesh>>(),
+            materials: world.resource::<Assets<StandardMaterial>>(),
+            images: world.resource::<Assets<Image>>(),
+            buffer: Vec::new(),
+            buffer_views: Vec::new(),
+            accessors: Vec::new(),
+            gltf_nodes: Vec::new(),
+            gltf_meshes: Vec::new(),
+            gltf_materials: Vec::new(),
+            gltf_textures: Vec::new(),
+            gltf_images: Vec::new(),
+            extensions_used: Vec::new(),
+            mesh_ids: HashMap::new(),
+            material_ids: HashMap::new(),
+            texture_ids: HashMap::new(),
+        }
+    }
+
+    /// Writes the node for `entity` and everything beneath it, returning the index of the node, or `None` if there are
+    /// no instances in the hierarchy
+    fn write_hierarchy(
+        &mut self,
+        entities: &World,
+        entity: Entity,
+        parent_name: Option<&str>,
+    ) -> Result<Option<usize>, VoxExportError> {
+        if !contains_instances(entities, entity) {
+            return Ok(None);
+        }
+        let name = entities.get::<Name>(entity).map(|name| name.as_str());
+        let mut node = Map::new();
+        if let Some(name) = name {
+            let local_name = parent_name
+                .and_then(|parent_name| name.strip_prefix(parent_name))
+                .and_then(|name| name.strip_prefix('/'))
+                .unwrap_or(name);
+            node.insert("name".to_string(), json!(local_name));
+        }
+        if let Some(transform) = entities.get::<Transform>(entity) {
+            write_transform(&mut node, transform);
+        }
+        if let Some(instance) = entities.get::<VoxelModelInstance>(entity) {
+            if let Some(mesh) = self.write_instance(instance)? {
+                node.insert("mesh".to_string(), json!(mesh));
+            }
+        }
+        let mut children = Vec::new();
+        if let Some(entity_children) = entities.get::<Children>(entity) {
+            for child in entity_children.iter() {
+                if let Some(child) = self.write_hierarchy(entities, *child, name.or(parent_name))? {
+                    children.push(child);
+                }
+            }
+        }
+        if !children.is_empty() {
+            node.insert("children".to_string(), json!(children));
+        }
+        Ok(Some(self.push_node(node)))
+    }
+
+    fn push_node(&mut self, node: Map<String, Value>) -> usize {
+        self.gltf_nodes.push(Value::Object(node));
+        self.gltf_nodes.len() - 1
+    }
+
+    /// The index of the mesh of the instance's first model, writing it if this is the first time it has been
+    /// encountered. Returns `None` for models without a mesh, such as those made only of cloud voxels.
+    fn write_instance(
+        &mut self,
+        instance: &VoxelModelInstance,
+    ) -> Result<Option<usize>, VoxExportError> {
+        let Some(model_handle) = instance.models.first() else {
+            return Ok(None);
+        };
+        let key = (model_handle.id(), instance.context.id());
+        if let Some(mesh) = self.mesh_ids.get(&key) {
+            return Ok(*mesh);
+        }
+        let (Some(model), Some(context)) = (
+            self.models.get(model_handle),
+            self.contexts.get(&instance.context),
+        ) else {
+            return Err(VoxExportError::AssetNotLoaded);
+        };
+        let color_mode = model.data.settings.color_mode;
+        let remeshed;
+        let (mesh, translucency) = if model.awaiting_mesh {
+            let (mesh, average_ior, _) = model.data.remesh(&context.palette);
+            remeshed = mesh;
+            let thickness = model.data.size().min_element() as f32;
+            (remeshed.as_ref(), average_ior.map(|ior| (ior, thickness)))
+        } else {
+            let mesh = match &model.mesh {
+                Some(handle) => Some(
+                    self.meshes
+                        .get(handle)
+                        .ok_or(VoxExportError::AssetNotLoaded)?,
+                ),
+                None => None,
+            };
+            let translucency = model
+                .has_translucency
+                .then(|| {
+                    let material = self.materials.get(model.material.as_ref()?)?;
+                    Some((material.ior, material.thickness))
+                })
+                .flatten();
+            (mesh, translucency)
+        };
+        let Some(mesh) = mesh else {
+            self.mesh_ids.insert(key, None);
+            return Ok(None);
+        };
+        // the instance is rendered with the materials of its own context, which may not be the one it was loaded with
+        let material = match translucency {
+            Some(_) => context.transmissive_material.id(),
+            None => context.opaque_material(color_mode).id(),
+        };
+        let material = self.write_material(MaterialKey {
+            material,
+            ior_and_thickness: translucency
+                .map(|(ior, thickness)| (ior.to_bits(), thickness.to_bits())),
+            vertex_colors: color_mode == MeshColorMode::VertexColors,
+        })?;
+        let index = self.write_mesh(mesh, model.name.as_str(), material);
+        self.mesh_ids.insert(key, index);
+        Ok(index)
+    }
+
+    /// Writes the triangles of a mesh as a glTF mesh with a single primitive, returning its index
+    fn write_mesh(&mut self, mesh: &Mesh, name: &str, material: Option<usize>) -> Option<usize> {
+        if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+            return None;
+        }
+        let mut attributes = Map::new();
+        let vertex_attributes: [(MeshVertexAttribute, &str); 4] = [
+            (Mesh::ATTRIBUTE_POSITION, "POSITION"),
+            (Mesh::ATTRIBUTE_NORMAL, "NORMAL"),
+            (Mesh::ATTRIBUTE_UV_0, "TEXCOORD_0"),
+            (Mesh::ATTRIBUTE_COLOR, "COLOR_0"),
+        ];
+        for (attribute, semantic) in vertex_attributes {
+            let Some(values) = mesh.attribute(attribute) else {
+                continue;
+            };
+            let accessor = match values {
+                VertexAttributeValues::Float32x2(values) => {
+                    self.push_float_accessor(values, "VEC2", false)
+                }
+                VertexAttributeValues::Float32x3(values) => {
+                    // positions must give their bounds
+                    self.push_float_accessor(values, "VEC3", semantic == "POSITION")
+                }
+                VertexAttributeValues::Float32x4(values) => {
+                    self.push_float_accessor(values, "VEC4", false)
+                }
+                _ => continue,
+            };
+            attributes.insert(semantic.to_string(), json!(accessor));
+        }
+        if !attributes.contains_key("POSITION") {
+            return None;
+        }
+        let mut primitive = Map::new();
+        primitive.insert("attributes".to_string(), Value::Object(attributes));
+        if let Some(indices) = mesh.indices() {
+            let (bytes, component_type): (Vec<u8>, u32) = match indices {
+                Indices::U16(indices) => (
+                    indices
+                        .iter()
+                        .flat_map(|index| index.to_le_bytes())
+                        .collect(),
+                    UNSIGNED_SHORT,
+                ),
+                Indices::U32(indices) => (
+                    indices
+                        .iter()
+                        .flat_map(|index| index.to_le_bytes())
+                        .collect(),
+                    UNSIGNED_INT,
+                ),
+            };
+            let view = self.push_buffer_view(&bytes, Some(ELEMENT_ARRAY_BUFFER));
+            let accessor = self.push_accessor(json!({
+                "bufferView": view,
+                "componentType": component_type,
+                "count": indices.len(),
+                "type": "SCALAR",
+            }));
+            primitive.insert("indices".to_string(), json!(accessor));
+        }
+        if let Some(material) = material {
+            primitive.insert("material".to_string(), json!(material));
+        }
+        self.gltf_meshes.push(json!({
+            "name": name,
+            "primitives": [primitive],
+        }));
+        Some(self.gltf_meshes.len() - 1)
+    }
+
+    /// Writes a material and its textures, returning its index, or `None` if the material isn't loaded, as for models
+    /// loaded with [`crate::VoxLoaderSettings::data_only`]
+    fn write_material(&mut self, key: MaterialKey) -> Result<Option<usize>, VoxExportError> {
+        if let Some(index) = self.material_ids.get(&key) {
+            return Ok(Some(*index));
+        }
+        let Some(material) = self.materials.get(key.material) else {
+            return Ok(None);
+        };
+        let base_color = material.base_color.to_linear();
+        let mut pbr = Map::new();
+        pbr.insert(
+            "baseColorFactor".to_string(),
+            json!(base_color.to_f32_array()),
+        );
+        pbr.insert("metallicFactor".to_string(), json!(material.metallic));
+        pbr.insert(
+            "roughnessFactor".to_string(),
+            json!(material.perceptual_roughness),
+        );
+        let base_color_texture = material
+            .base_color_texture
+            .as_ref()
+            .filter(|_| !key.vertex_colors);
+        if let Some((texture, _)) = self.write_texture(base_color_texture)? {
+            pbr.insert("baseColorTexture".to_string(), json!({ "index": texture }));
+        }
+        if let Some((texture, _)) =
+            self.write_texture(material.metallic_roughness_texture.as_ref())?
+        {
+            pbr.insert(
+                "metallicRoughnessTexture".to_string(),
+                json!({ "index": texture }),
+            );
+        }
+        let mut gltf_material = Map::new();
+        gltf_material.insert("pbrMetallicRoughness".to_string(), Value::Object(pbr));
+        let mut extensions = Map::new();
+
+        let mut emissive = material.emissive;
+        if let Some((texture, scale)) = self.write_texture(material.emissive_texture.as_ref())? {
+            gltf_material.insert("emissiveTexture".to_string(), json!({ "index": texture }));
+            emissive *= scale;
+        }
+        // factors are limited to 1.0, so brighter emission is written as a strength
+        let strength = emissive.red.max(emissive.green).max(emissive.blue);
+        if strength > 0.0 {
+            let factor = emissive * (1.0 / strength.max(1.0));
+            gltf_material.insert(
+                "emissiveFactor".to_string(),
+                json!([factor.red, factor.green, factor.blue]),
+            );
+        }
+        if strength > 1.0 {
+            extensions.insert(
+                "KHR_materials_emissive_strength".to_string(),
+                json!({ "emissiveStrength": strength }),
+            );
+        }
+
+        if material.specular_transmission > 0.0 {
+            let mut transmission = Map::new();
+            transmission.insert(
+                "transmissionFactor".to_string(),
+                json!(material.specular_transmission),
+            );
+            #[cfg(feature = "pbr_transmission_textures")]
+            if let Some((texture, _)) =
+                self.write_texture(material.specular_transmission_texture.as_ref())?
+            {
+                transmission.insert(
+                    "transmissionTexture".to_string(),
+                    json!({ "index": texture }),
+                );
+            }
+            extensions.insert(
+                "KHR_materials_transmission".to_string(),
+                Value::Object(transmission),
+            );
+            let (ior, thickness) = key
+                .ior_and_thickness
+                .map(|(ior, thickness)| (f32::from_bits(ior), f32::from_bits(thickness)))
+                .unwrap_or((material.ior, material.thickness));
+            extensions.insert("KHR_materials_ior".to_string(), json!({ "ior": ior }));
+            if thickness > 0.0 {
+                extensions.insert(
+                    "KHR_materials_volume".to_string(),
+                    json!({ "thicknessFactor": thickness }),
+                );
+            }
+        }
+        match material.alpha_mode {
+            AlphaMode::Opaque => (),
+            AlphaMode::Mask(cutoff) => {
+                gltf_material.insert("alphaMode".to_string(), json!("MASK"));
+                gltf_material.insert("alphaCutoff".to_string(), json!(cutoff));
+            }
+            _ => {
+                gltf_material.insert("alphaMode".to_string(), json!("BLEND"));
+            }
+        }
+        if material.double_sided {
+            gltf_material.insert("doubleSided".to_string(), json!(true));
+        }
+        for extension in extensions.keys() {
+            if !self.extensions_used.contains(extension) {
+                self.extensions_used.push(extension.clone());
+            }
+        }
+        if !extensions.is_empty() {
+            gltf_material.insert("extensions".to_string(), Value::Object(extensions));
+        }
+        self.gltf_materials.push(Value::Object(gltf_material));
+        let index = self.gltf_materials.len() - 1;
+        self.material_ids.insert(key, index);
+        Ok(Some(index))
+    }
+
+    /// Writes an image as an embedded PNG texture, returning its index and the factor that its values were divided by.
+    /// Returns `None` if there is no image, or if it isn't in one of the formats used for palette textures.
+    fn write_texture(
+        &mut self,
+        handle: Option<&bevy::asset::Handle<Image>>,
+    ) -> Result<Option<(usize, f32)>, VoxExportError> {
+        let Some(handle) = handle else {
+            return Ok(None);
+        };
+        if let Some(texture) = self.texture_ids.get(&handle.id()) {
+            return Ok(*texture);
+        }
+        let Some(image) = self.images.get(handle) else {
+            return Err(VoxExportError::AssetNotLoaded);
+        };
+        let texture = match image_to_rgba8(image) {
+            Some((pixels, scale)) => {
+                let mut png = Vec::new();
+                image::RgbaImage::from_raw(image.width(), image.height(), pixels)
+                    .ok_or_else(|| std::io::Error::other("image data does not match its size"))?
+                    .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+                    .map_err(std::io::Error::other)?;
+                let view = self.push_buffer_view(&png, None);
+                self.gltf_images.push(json!({
+                    "bufferView": view,
+                    "mimeType": "image/png",
+                }));
+                self.gltf_textures.push(json!({
+                    "sampler": 0,
+                    "source": self.gltf_images.len() - 1,
+                }));
+                Some((self.gltf_textures.len() - 1, scale))
+            }
+            None => None,
+        };
+        self.texture_ids.insert(handle.id(), texture);
+        Ok(texture)
+    }
+
+    fn push_float_accessor<const N: usize>(
+        &mut self,
+        values: &[[f32; N]],
+        kind: &str,
+        with_bounds: bool,
+    ) -> usize {
+        let bytes: Vec<u8> = values
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let view = self.push_buffer_view(&bytes, Some(ARRAY_BUFFER));
+        let mut accessor = json!({
+            "bufferView": view,
+            "componentType": FLOAT,
+            "count": values.len(),
+            "type": kind,
+        });
+        if with_bounds {
+            let mut min = [f32::INFINITY; N];
+            let mut max = [f32::NEG_INFINITY; N];
+            for value in values {
+                for axis in 0..N {
+                    min[axis] = min[axis].min(value[axis]);
+                    max[axis] = max[axis].max(value[axis]);
+                }
+            }
+            if !values.is_empty() {
+                accessor["min"] = json!(min.as_slice());
+                accessor["max"] = json!(max.as_slice());
+            }
+        }
+        self.push_accessor(accessor)
+    }
+
+    fn push_accessor(&mut self, accessor: Value) -> usize {
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    /// Appends the bytes to the binary buffer, aligned to 4 bytes as accessors require, returning the index of the view
+    fn push_buffer_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        pad_to_4(&mut self.buffer, 0);
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.buffer.extend_from_slice(bytes);
+        self.buffer_views.push(view);
+        self.buffer_views.len() - 1
+    }
+
+    /// Writes the JSON and binary chunks of the file, with `nodes` at the root of its only scene
+    fn finish<W: Write>(mut self, nodes: Vec<usize>, writer: &mut W) -> Result<(), VoxExportError> {
+        if self.gltf_nodes.is_empty() {
+            return Err(VoxExportError::NoModels);
+        }
+        pad_to_4(&mut self.buffer, 0);
+        let mut root = json!({
+            "asset": {
+                "version": "2.0",
+                "generator": concat!("bevy_vox_scene ", env!("CARGO_PKG_VERSION")),
+            },
+            "scene": 0,
+            "scenes": [{ "nodes": nodes }],
+            "nodes": self.gltf_nodes,
+            "meshes": self.gltf_meshes,
+            "materials": self.gltf_materials,
+            "textures": self.gltf_textures,
+            "images": self.gltf_images,
+            "samplers": [{ "magFilter": NEAREST, "minFilter": NEAREST }],
+            "accessors": self.accessors,
+            "bufferViews": self.buffer_views,
+            "buffers": [{ "byteLength": self.buffer.len() }],
+        });
+        if !self.extensions_used.is_empty() {
+            root["extensionsUsed"] = json!(self.extensions_used);
+        }
+        // glTF doesn't allow empty arrays
+        if let Some(root) = root.as_object_mut() {
+            root.retain(|_, value| value.as_array().is_none_or(|array| !array.is_empty()));
+        }
+        let mut json = serde_json::to_vec(&root).map_err(std::io::Error::from)?;
+        pad_to_4(&mut json, b' ');
+
+        let mut chunks = Vec::new();
+        for (kind, data) in [(CHUNK_JSON, &json), (CHUNK_BIN, &self.buffer)] {
+            if kind == CHUNK_BIN && data.is_empty() {
+                continue;
+            }
+            chunks.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            chunks.extend_from_slice(&kind.to_le_bytes());
+            chunks.extend_from_slice(data);
+        }
+        writer.write_all(&GLB_MAGIC.to_le_bytes())?;
+        writer.write_all(&GLB_VERSION.to_le_bytes())?;
+        writer.write_all(&(12 + chunks.len() as u32).to_le_bytes())?;
+        writer.write_all(&chunks)?;
+        Ok(())
+    }
+}
+
+fn contains_instances(world: &World, entity: Entity) -> bool {
+    world.get::<VoxelModelInstance>(entity).is_some()
+        || world.get::<Children>(entity).is_some_and(|children| {
+            children
+                .iter()
+                .any(|child| contains_instances(world, *child))
+        })
+}
+
+/// Writes the parts of the transform that aren't the identity
+fn write_transform(node: &mut Map<String, Value>, transform: &Transform) {
+    if transform.translation != Transform::IDENTITY.translation {
+        node.insert(
+            "translation".to_string(),
+            json!(transform.translation.to_array()),
+        );
+    }
+    if transform.rotation != Transform::IDENTITY.rotation {
+        node.insert("rotation".to_string(), json!(transform.rotation.to_array()));
+    }
+    if transform.scale != Transform::IDENTITY.scale {
+        node.insert("scale".to_string(), json!(transform.scale.to_array()));
+    }
+}
+
+/// Converts a palette texture to 8-bit sRGB RGBA, as glTF requires for color textures, or 8-bit linear RGBA for data
+/// textures. High dynamic range emission is divided by its brightest value, which is returned with the pixels.
+fn image_to_rgba8(image: &Image) -> Option<(Vec<u8>, f32)> {
+    let unorm16 = |bytes: &[u8]| (u16::from_le_bytes([bytes[0], bytes[1]]) >> 8) as u8;
+    match image.texture_descriptor.format {
+        TextureFormat::Rgba8UnormSrgb => Some((image.data.clone(), 1.0)),
+        TextureFormat::Rgba8Unorm => Some((
+            image
+                .data
+                .chunks_exact(4)
+                .flat_map(|pixel| {
+                    let color = LinearRgba::from_u8_array([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                    Srgba::from(color).to_u8_array()
+                })
+                .collect(),
+            1.0,
+        )),
+        TextureFormat::Rgba32Float => {
+            let values: Vec<f32> = image
+                .data
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect();
+            let scale = values
+                .chunks_exact(4)
+                .flat_map(|pixel| pixel[..3].iter().copied())
+                .fold(1.0, f32::max);
+            Some((
+                values
+                    .chunks_exact(4)
+                    .flat_map(|pixel| {
+                        let color = LinearRgba::rgb(pixel[0], pixel[1], pixel[2]) * (1.0 / scale);
+                        Srgba::from(color).to_u8_array()
+                    })
+                    .collect(),
+                scale,
+            ))
+        }
+        TextureFormat::Rgba16Unorm => {
+            Some((image.data.chunks_exact(2).map(unorm16).collect(), 1.0))
+        }
+        TextureFormat::R16Unorm => Some((
+            image
+                .data
+                .chunks_exact(2)
+                .flat_map(|bytes| {
+                    let value = unorm16(bytes);
+                    [value, value, value, u8::MAX]
+                })
+                .collect(),
+            1.0,
+        )),
+        _ => None,
+    }
+}
+
+fn pad_to_4(bytes: &mut Vec<u8>, padding: u8) {
+    bytes.resize(bytes.len().next_multiple_of(4), padding);
+}
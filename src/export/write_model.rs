@@ -0,0 +1,50 @@
+use bevy::math::IVec3;
+use dot_vox::{Model, Size};
+
+use crate::{model::RawVoxel, Voxel, VoxelData, VoxelQueryable};
+
+use super::VoxExportError;
+
+/// The maximum length of a Magica Voxel model along any axis
+const MAX_MODEL_SIZE: u32 = 256;
+
+impl VoxelData {
+    /// Export the data to Magica Voxel, performing coordinate conversion from bevy's right-handed Y-up to MV's left-handed Z-up.
+    /// This is the inverse of `VoxelData::from_model`.
+    pub(super) fn to_model(&self) -> Result<Model, VoxExportError> {
+        let size = self.size().as_uvec3();
+        if size.max_element() > MAX_MODEL_SIZE {
+            return Err(VoxExportError::ModelTooLarge(size));
+        }
+        let mut voxels: Vec<dot_vox::Voxel> = Vec::new();
+        for x in 0..size.x {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    let Ok(voxel) =
+                        self.get_voxel_at_point(IVec3::new(x as i32, y as i32, z as i32))
+                    else {
+                        continue;
+                    };
+                    if voxel == Voxel::EMPTY {
+                        continue;
+                    }
+                    let raw_voxel = RawVoxel::from(voxel);
+                    voxels.push(dot_vox::Voxel {
+                        x: ((size.x - 1) - x) as u8,
+                        y: z as u8,
+                        z: y as u8,
+                        i: raw_voxel.0,
+                    });
+                }
+            }
+        }
+        Ok(Model {
+            size: Size {
+                x: size.x,
+                y: size.z,
+                z: size.y,
+            },
+            voxels,
+        })
+    }
+}
@@ -0,0 +1,75 @@
+use bevy::color::ColorToPacked;
+use dot_vox::{Color, Dict, Material};
+
+use crate::{VoxLoaderSettings, VoxelElement, VoxelPalette};
+
+/// Magica Voxel's maximum radiant flux for emissive materials
+const MAX_FLUX: f32 = 4.0;
+
+impl VoxelPalette {
+    /// The colors of the palette, in the format written to the `RGBA` chunk.
+    pub(crate) fn to_dot_vox_colors(&self) -> Vec<Color> {
+        self.elements
+            .iter()
+            .map(|element| {
+                let [r, g, b, a] = if self.uses_srgb {
+                    element.color.to_srgba().to_u8_array()
+                } else {
+                    element.color.to_linear().to_u8_array()
+                };
+                Color { r, g, b, a }
+            })
+            .collect()
+    }
+
+    /// The materials of the palette, in the format written to the `MATL` chunks.
+    ///
+    /// This is the inverse of `VoxelPalette::from_data`, so the `settings` should be the ones that the palette was loaded with.
+    pub(crate) fn to_dot_vox_materials(&self, settings: &VoxLoaderSettings) -> Vec<Material> {
+        self.elements
+            .iter()
+            .enumerate()
+            .map(|(index, element)| Material {
+                id: index as u32 + 1,
+                properties: element.to_material_properties(settings),
+            })
+            .collect()
+    }
+}
+
+impl VoxelElement {
+    fn to_material_properties(&self, settings: &VoxLoaderSettings) -> Dict {
+        let mut properties = Dict::default();
+        let mut insert = |key: &str, value: String| {
+            properties.insert(key.to_string(), value);
+        };
+        if self.density > 0.0 {
+            insert("_type", "_media".to_string());
+            insert("_d", (self.density / 10.0).to_string());
+        } else if self.translucency > 0.0 {
+            insert("_type", "_glass".to_string());
+            insert("_alpha", self.translucency.to_string());
+            insert("_ior", (self.refraction_index - 1.0).max(0.0).to_string());
+        } else if self.emission > 0.0 && settings.emission_strength > 0.0 {
+            // Loading multiplies `_emit` by `_flux + 1`, so use the flux to encode emission greater than 1
+            let emission = self.emission / settings.emission_strength;
+            let flux = (emission.ceil() - 1.0).clamp(0.0, MAX_FLUX);
+            insert("_type", "_emit".to_string());
+            insert("_emit", (emission / (flux + 1.0)).to_string());
+            insert("_flux", flux.to_string());
+        } else if self.metalness > 0.0
+            || (self.roughness - settings.diffuse_roughness).abs() > 0.001
+        {
+            insert("_type", "_metal".to_string());
+        } else {
+            // The roughness of diffuse materials is supplied by the loader settings
+            insert("_type", "_diffuse".to_string());
+            return properties;
+        }
+        insert("_rough", self.roughness.to_string());
+        if self.metalness > 0.0 {
+            insert("_metal", self.metalness.to_string());
+        }
+        properties
+    }
+}
@@ -0,0 +1,319 @@
+use std::{collections::BTreeMap, io::Write};
+
+#[cfg(feature = "render")]
+use bevy::prelude::Visibility;
+use bevy::{
+    asset::{AssetId, Assets},
+    core::Name,
+    ecs::{entity::Entity, world::World},
+    hierarchy::Children,
+    math::Mat3,
+    prelude::Transform,
+    utils::HashMap,
+};
+use dot_vox::{Dict, DotVoxData, Frame, Layer, Model, Rotation, SceneNode, ShapeModel};
+
+use crate::{
+    load::rotation_from_orientation, VoxLoaderSettings, VoxelAnimationPlayer, VoxelContext,
+    VoxelLayer, VoxelModel, VoxelModelInstance,
+};
+
+use super::{VoxExportError, VOX_VERSION};
+
+/// The byte encoding of [`Rotation::IDENTITY`]
+const IDENTITY_ORIENTATION: u8 = 0b0000100;
+
+/// Serializes a spawned hierarchy of [`VoxelModelInstance`]s as a Magica Voxel `.vox` file, with a scene graph mirroring the hierarchy.
+///
+/// This is the inverse of the scene graph parsing performed by the loader:
+/// - every entity becomes a transform node, which contains a shape node if the entity has a [`VoxelModelInstance`], a group node if it has
+///   children, or both if it has both.
+/// - node names are taken from [`Name`] (stripping the path of ancestor names that the loader prepends), hidden flags from [`Visibility::Hidden`],
+///   and layers from [`VoxelLayer`].
+/// - instances with several models (animation frames) are written as a single shape node with one model per frame.
+///
+/// Entities that have no [`VoxelModelInstance`] in their hierarchy (such as cameras, lights, or the children spawned for fog volumes and
+/// animation frames) are skipped. Models shared by several instances are only written once.
+///
+/// ### Arguments
+/// * `world` - the world containing the hierarchy. All of the [`VoxelModel`] and [`VoxelContext`] assets must be loaded.
+/// * `root` - the entity written as the root node of the scene graph. Its own transform is not written, as Magica Voxel ignores the
+///   transform of the root node. All of the instances in the hierarchy must share the same [`VoxelContext`].
+/// * `writer` - the destination for the `.vox` bytes
+pub fn write_vox_scene<W: Write>(
+    world: &World,
+    root: Entity,
+    writer: &mut W,
+) -> Result<(), VoxExportError> {
+    let Some(settings) = find_settings(world, root) else {
+        return Err(VoxExportError::NoModels);
+    };
+    let mut scene_writer = SceneWriter {
+        world,
+        models: world.resource::<Assets<VoxelModel>>(),
+        scene_scale: settings.voxel_size,
+        settings,
+        nodes: Vec::new(),
+        model_ids: HashMap::new(),
+        dot_vox_models: Vec::new(),
+        context: None,
+        layers: BTreeMap::new(),
+    };
+    scene_writer.write_node(root, None, true)?;
+
+    let Some(context_id) = scene_writer.context else {
+        return Err(VoxExportError::NoModels);
+    };
+    let Some(context) = world.resource::<Assets<VoxelContext>>().get(context_id) else {
+        return Err(VoxExportError::AssetNotLoaded);
+    };
+    let layer_count = scene_writer
+        .layers
+        .keys()
+        .last()
+        .map_or(1, |last_id| last_id + 1);
+    let layers = (0..layer_count)
+        .map(|id| {
+            let mut attributes = Dict::default();
+            if let Some(Some(name)) = scene_writer.layers.get(&id) {
+                attributes.insert("_name".to_string(), name.clone());
+            }
+            Layer { attributes }
+        })
+        .collect();
+    let data = DotVoxData {
+        version: VOX_VERSION,
+        index_map: vec![],
+        models: scene_writer.dot_vox_models,
+        palette: context.palette.to_dot_vox_colors(),
+        materials: context.palette.to_dot_vox_materials(&scene_writer.settings),
+        scenes: scene_writer.nodes,
+        layers,
+    };
+    data.write_vox(writer)?;
+    Ok(())
+}
+
+struct SceneWriter<'w> {
+    world: &'w World,
+    models: &'w Assets<VoxelModel>,
+    settings: VoxLoaderSettings,
+    scene_scale: f32,
+    nodes: Vec<SceneNode>,
+    model_ids: HashMap<AssetId<VoxelModel>, u32>,
+    dot_vox_models: Vec<Model>,
+    context: Option<AssetId<VoxelContext>>,
+    layers: BTreeMap<u32, Option<String>>,
+}
+
+impl SceneWriter<'_> {
+    /// Writes the transform node for `entity` and everything beneath it, returning the index of the transform node.
+    fn write_node(
+        &mut self,
+        entity: Entity,
+        parent_name: Option<&str>,
+        is_root: bool,
+    ) -> Result<u32, VoxExportError> {
+        let transform_index = self.reserve_node();
+        let accumulated = self
+            .world
+            .get::<Name>(entity)
+            .map(|name| name.as_str())
+            .or(parent_name);
+        let instance = self.world.get::<VoxelModelInstance>(entity);
+        let children: Vec<Entity> = self
+            .world
+            .get::<Children>(entity)
+            .map(|children| {
+                children
+                    .iter()
+                    .copied()
+                    .filter(|child| self.contains_instances(*child))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let layer_id = match self.world.get::<VoxelLayer>(entity) {
+            Some(layer) => {
+                self.layers.insert(layer.id, layer.name.clone());
+                layer.id
+            }
+            None if is_root => u32::MAX,
+            None => 0,
+        };
+
+        let child = match instance {
+            Some(instance) if children.is_empty() => self.write_shape(entity, instance)?,
+            _ => {
+                let group_index = self.reserve_node();
+                let mut group_children = Vec::new();
+                if let Some(instance) = instance {
+                    // a node can't hold both a shape and a group, so wrap the shape in an identity transform alongside the children
+                    let shape_transform_index = self.reserve_node();
+                    let shape_index = self.write_shape(entity, instance)?;
+                    self.nodes[shape_transform_index as usize] = SceneNode::Transform {
+                        attributes: Dict::default(),
+                        frames: vec![Frame::default()],
+                        child: shape_index,
+                        layer_id: if is_root { 0 } else { layer_id },
+                    };
+                    group_children.push(shape_transform_index);
+                }
+                for child in children {
+                    group_children.push(self.write_node(child, accumulated, false)?);
+                }
+                self.nodes[group_index as usize] = SceneNode::Group {
+                    attributes: Dict::default(),
+                    children: group_children,
+                };
+                group_index
+            }
+        };
+
+        let mut attributes = Dict::default();
+        if let Some(name) = self.world.get::<Name>(entity) {
+            let name = name.as_str();
+            let local_name = parent_name
+                .and_then(|parent_name| name.strip_prefix(parent_name))
+                .and_then(|name| name.strip_prefix('/'))
+                .unwrap_or(name);
+            attributes.insert("_name".to_string(), local_name.to_string());
+        }
+        #[cfg(feature = "render")]
+        if self.world.get::<Visibility>(entity) == Some(&Visibility::Hidden) {
+            attributes.insert("_hidden".to_string(), "1".to_string());
+        }
+        let frame = match self.world.get::<Transform>(entity) {
+            Some(transform) if !is_root => frame_from_transform(transform, self.scene_scale),
+            _ => Frame::default(),
+        };
+        self.nodes[transform_index as usize] = SceneNode::Transform {
+            attributes,
+            frames: vec![frame],
+            child,
+            layer_id,
+        };
+        Ok(transform_index)
+    }
+
+    /// Writes a shape node for the instance, returning its index
+    fn write_shape(
+        &mut self,
+        entity: Entity,
+        instance: &VoxelModelInstance,
+    ) -> Result<u32, VoxExportError> {
+        let context_id = instance.context.id();
+        match self.context {
+            Some(existing) if existing != context_id => {
+                return Err(VoxExportError::MultipleContexts)
+            }
+            _ => self.context = Some(context_id),
+        }
+        let is_animated = instance.models.len() > 1;
+        let player = self.world.get::<VoxelAnimationPlayer>(entity);
+        let mut models = Vec::new();
+        for (index, handle) in instance.models.iter().enumerate() {
+            let model_id = self.model_id(handle.id())?;
+            let mut attributes = Dict::default();
+            if is_animated {
+                // the keyframe is the first frame of the timeline that shows the model
+                let frame = player
+                    .and_then(|player| player.frames.iter().position(|frame| *frame == index))
+                    .unwrap_or(index);
+                attributes.insert("_f".to_string(), frame.to_string());
+            }
+            models.push(ShapeModel {
+                model_id,
+                attributes,
+            });
+        }
+        let shape_index = self.nodes.len() as u32;
+        self.nodes.push(SceneNode::Shape {
+            attributes: Dict::default(),
+            models,
+        });
+        Ok(shape_index)
+    }
+
+    /// The index of the model in the file, converting the model if this is the first time it has been encountered
+    fn model_id(&mut self, id: AssetId<VoxelModel>) -> Result<u32, VoxExportError> {
+        if let Some(model_id) = self.model_ids.get(&id) {
+            return Ok(*model_id);
+        }
+        let Some(model) = self.models.get(id) else {
+            return Err(VoxExportError::AssetNotLoaded);
+        };
+        let model_id = self.dot_vox_models.len() as u32;
+        self.dot_vox_models.push(model.data.to_model()?);
+        self.model_ids.insert(id, model_id);
+        Ok(model_id)
+    }
+
+    /// Nodes reference their children by index, so parents are pushed as placeholders and filled in once their children are written
+    fn reserve_node(&mut self) -> u32 {
+        self.nodes.push(SceneNode::Group {
+            attributes: Dict::default(),
+            children: vec![],
+        });
+        self.nodes.len() as u32 - 1
+    }
+
+    fn contains_instances(&self, entity: Entity) -> bool {
+        self.world.get::<VoxelModelInstance>(entity).is_some()
+            || self.world.get::<Children>(entity).is_some_and(|children| {
+                children.iter().any(|child| self.contains_instances(*child))
+            })
+    }
+}
+
+/// The loader settings of the first model found in the hierarchy, used for the scale of the scene and to convert the materials
+fn find_settings(world: &World, entity: Entity) -> Option<VoxLoaderSettings> {
+    if let Some(instance) = world.get::<VoxelModelInstance>(entity) {
+        let models = world.resource::<Assets<VoxelModel>>();
+        if let Some(model) = instance
+            .models
+            .first()
+            .and_then(|handle| models.get(handle))
+        {
+            return Some(model.data.settings.clone());
+        }
+    }
+    world
+        .get::<Children>(entity)?
+        .iter()
+        .find_map(|child| find_settings(world, *child))
+}
+
+/// The inverse of the loader's conversion from a Magica Voxel frame to a bevy transform.
+/// Magica Voxel only supports integer translations and axis-aligned rotations, so the transform is snapped to the nearest of each.
+fn frame_from_transform(transform: &Transform, scene_scale: f32) -> Frame {
+    let position = (transform.translation / scene_scale).round().as_ivec3();
+    let mut attributes = Dict::default();
+    attributes.insert(
+        "_t".to_string(),
+        format!("{} {} {}", -position.x, position.z, position.y),
+    );
+    let target =
+        Mat3::from_quat(transform.rotation) * Mat3::from_diagonal(transform.scale.signum());
+    let orientation = (0..128u8)
+        .filter(|byte| {
+            let index_nz1 = byte & 0b11;
+            let index_nz2 = (byte >> 2) & 0b11;
+            index_nz1 != index_nz2 && index_nz1 != 0b11 && index_nz2 != 0b11
+        })
+        .min_by(|a, b| {
+            let distance = |byte: u8| {
+                let rotation = rotation_from_orientation(&Rotation::from_byte(byte));
+                (rotation - target)
+                    .to_cols_array()
+                    .iter()
+                    .map(|element| element * element)
+                    .sum::<f32>()
+            };
+            distance(*a).total_cmp(&distance(*b))
+        })
+        .expect("there are 48 valid orientations");
+    if orientation != IDENTITY_ORIENTATION {
+        attributes.insert("_r".to_string(), orientation.to_string());
+    }
+    Frame::new(attributes)
+}
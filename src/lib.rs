@@ -40,37 +40,79 @@
 //! # }
 //!```
 
+#[cfg(any(feature = "render", feature = "colliders", feature = "schematics"))]
+use bevy::app::Update;
+#[cfg(feature = "schematics")]
+use bevy::ecs::schedule::common_conditions::resource_changed;
+#[cfg(any(feature = "render", feature = "schematics"))]
+use bevy::ecs::schedule::IntoSystemConfigs;
 use bevy::{
-    app::{App, Plugin, Update},
+    app::{App, Plugin},
     asset::AssetApp,
 };
 
+#[cfg(feature = "export_voxels")]
+mod export;
 mod load;
+#[cfg(feature = "render")]
+mod material;
 mod model;
 mod observers;
+#[cfg(feature = "picking")]
+mod picking;
+#[cfg(feature = "render")]
 mod systems;
 
 #[cfg(test)]
 mod tests;
 
+#[cfg(feature = "export_gltf")]
+pub use export::{write_glb_instances, write_glb_scene};
+#[cfg(feature = "export_voxels")]
+pub use export::{write_vox_scene, VoxExportError};
+#[cfg(feature = "goxel")]
+use load::GoxelSceneLoader;
+#[cfg(feature = "schematics")]
+pub use load::SchematicBlocks;
 #[doc(inline)]
-use load::VoxSceneLoader;
+use load::{KvxSceneLoader, QubicleSceneLoader, VoxSceneLoader, VoxelPaletteLoader};
 pub use load::{
-    UnitOffset, VoxLoaderSettings, VoxelAnimationPlayer, VoxelLayer, VoxelModelInstance,
+    MeshColorMode, MeshingMode, PaletteLoaderError, PaletteLoaderSettings, UnitOffset,
+    VoxLoaderError, VoxLoaderSettings, VoxelAnimationPlayer, VoxelLayer, VoxelModelInstance,
 };
+#[cfg(feature = "schematics")]
+use load::{SchematicSceneLoader, SharedSchematicBlocks};
+#[cfg(feature = "render")]
+pub use load::{
+    VoxelFog, VoxelRenderSettings, VoxelSky, VoxelSun, VoxelTransformAnimation, SKY_BRIGHTNESS,
+    SUN_ILLUMINANCE,
+};
+#[cfg(feature = "render")]
+pub use material::{VoxelMaterialFactory, VoxelMaterialPlugin};
+#[cfg(feature = "colliders")]
+pub use model::collider::{VoxelCollider, VoxelColliderBox, VoxelColliderMesh};
+#[cfg(feature = "render")]
+pub use model::mesh::ATTRIBUTE_PALETTE_INDEX;
+#[cfg(feature = "modify_voxels")]
+pub use model::modify::{ModifyVoxelCommandsExt, VoxelRegion, VoxelRegionMode};
+#[cfg(feature = "render")]
+pub use model::palette_animation::{PaletteEffect, VoxelPaletteAnimation};
 #[cfg(feature = "generate_voxels")]
 pub use model::sdf::SDF;
-#[cfg(feature = "modify_voxels")]
 pub use model::{
-    modify::{ModifyVoxelCommandsExt, VoxelRegion, VoxelRegionMode},
-    queryable::VoxelQueryable,
+    Voxel, VoxelContext, VoxelData, VoxelElement, VoxelModel, VoxelPalette, VoxelQueryable,
+    VoxelRayHit,
 };
-pub use model::{Voxel, VoxelContext, VoxelData, VoxelElement, VoxelModel, VoxelPalette};
 pub use observers::VoxelInstanceSpawned;
+#[cfg(feature = "picking")]
+pub use picking::{VoxelPickingHits, VoxelPickingPlugin};
 
 /// Plugin adding functionality for loading `.vox` files.
 ///
-/// Registers an [`bevy::asset::AssetLoader`] capable of loading `.vox` files as spawnable [`VoxelScene`]s.
+/// Registers an [`bevy::asset::AssetLoader`] capable of loading `.vox` files as spawnable [`VoxelScene`]s, along with
+/// loaders for Qubicle `.qb` and Build engine `.kvx` files, and standalone palette files as [`VoxelPalette`]s. Goxel
+/// `.gox` files are loaded with the `goxel` feature, and Minecraft `.schem` and `.nbt` structures with the `schematics`
+/// feature.
 #[derive(Default)]
 pub struct VoxScenePlugin {
     /// Inject global settings. This is a workaround for `load_with_settings` currently being broken.
@@ -83,13 +125,65 @@ impl Plugin for VoxScenePlugin {
     fn build(&self, app: &mut App) {
         app.init_asset::<VoxelModel>()
             .init_asset::<VoxelContext>()
+            .init_asset::<VoxelPalette>()
             .register_type::<VoxelLayer>()
             .register_type::<VoxelModelInstance>()
             .register_type::<VoxelAnimationPlayer>()
             .register_asset_loader(VoxSceneLoader {
                 global_settings: self.global_settings.clone(),
             })
-            .add_observer(observers::on_voxel_instance_spawned)
-            .add_systems(Update, systems::update_animations);
+            .register_asset_loader(QubicleSceneLoader {
+                global_settings: self.global_settings.clone(),
+            })
+            .register_asset_loader(KvxSceneLoader {
+                global_settings: self.global_settings.clone(),
+            })
+            .register_asset_loader(VoxelPaletteLoader)
+            .add_observer(observers::on_voxel_instance_spawned);
+        #[cfg(feature = "goxel")]
+        app.register_asset_loader(GoxelSceneLoader {
+            global_settings: self.global_settings.clone(),
+        });
+        #[cfg(feature = "schematics")]
+        {
+            app.init_resource::<SchematicBlocks>();
+            let blocks =
+                SharedSchematicBlocks::new(app.world().resource::<SchematicBlocks>().clone());
+            app.register_asset_loader(SchematicSceneLoader {
+                global_settings: self.global_settings.clone(),
+                blocks: blocks.clone(),
+            })
+            .insert_resource(blocks)
+            .add_systems(
+                Update,
+                load::sync_schematic_blocks.run_if(resource_changed::<SchematicBlocks>),
+            );
+        }
+        #[cfg(feature = "render")]
+        app.init_asset::<VoxelRenderSettings>()
+            .init_resource::<model::palette_animation::PaletteImageMaterials>()
+            .register_type::<VoxelTransformAnimation>()
+            .add_systems(
+                Update,
+                (
+                    systems::update_animations,
+                    systems::start_transform_animations,
+                    systems::swap_instance_contexts,
+                    (
+                        model::palette_animation::index_palette_materials,
+                        model::palette_animation::animate_palettes,
+                    )
+                        .chain(),
+                ),
+            );
+        #[cfg(feature = "colliders")]
+        app.add_systems(Update, model::collider::update_colliders);
+    }
+
+    #[cfg(feature = "schematics")]
+    fn finish(&self, app: &mut App) {
+        // picks up a `SchematicBlocks` resource that was inserted after the plugin, before anything is loaded
+        let blocks = app.world().resource::<SchematicBlocks>();
+        app.world().resource::<SharedSchematicBlocks>().set(blocks);
     }
 }
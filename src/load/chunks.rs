@@ -0,0 +1,140 @@
+use dot_vox::Dict;
+
+/// The `MAIN` chunk follows the "VOX " magic number and the version
+pub(super) const MAIN_CHUNK_OFFSET: usize = 8;
+
+/// Each chunk starts with its id, followed by the sizes of its contents and of its children
+const CHUNK_HEADER_SIZE: usize = 12;
+
+/// Finds the contents of the top-level chunks with the given id, such as the `rCAM` and `rOBJ` render chunks.
+///
+/// `dot_vox` discards any chunks that it doesn't recognise, so these are read directly from the bytes of the file.
+#[cfg(feature = "render")]
+pub(super) fn find_chunks<'a>(bytes: &'a [u8], chunk_id: &[u8; 4]) -> Vec<&'a [u8]> {
+    let mut chunks = Vec::new();
+    let Some(main_content_size) = read_u32(bytes, MAIN_CHUNK_OFFSET + 4) else {
+        return chunks;
+    };
+    let mut offset = MAIN_CHUNK_OFFSET + CHUNK_HEADER_SIZE + main_content_size as usize;
+    while let (Some(content_size), Some(children_size)) =
+        (read_u32(bytes, offset + 4), read_u32(bytes, offset + 8))
+    {
+        let content_start = offset + CHUNK_HEADER_SIZE;
+        let content_end = content_start + content_size as usize;
+        let Some(content) = bytes.get(content_start..content_end) else {
+            break;
+        };
+        if &bytes[offset..offset + 4] == chunk_id {
+            chunks.push(content);
+        }
+        offset = content_end + children_size as usize;
+    }
+    chunks
+}
+
+/// Walks the chunks of the file, returning the byte offset of the first chunk that is truncated
+/// or has malformed contents, along with a description of the problem.
+pub(super) fn find_malformed_chunk(bytes: &[u8]) -> Option<(usize, String)> {
+    if bytes.get(0..4) != Some(b"VOX ") {
+        return Some((0, "missing \"VOX \" magic number".to_string()));
+    }
+    if bytes.get(MAIN_CHUNK_OFFSET..MAIN_CHUNK_OFFSET + 4) != Some(b"MAIN") {
+        return Some((MAIN_CHUNK_OFFSET, "missing MAIN chunk".to_string()));
+    }
+    let (Some(main_content_size), Some(main_children_size)) = (
+        read_u32(bytes, MAIN_CHUNK_OFFSET + 4),
+        read_u32(bytes, MAIN_CHUNK_OFFSET + 8),
+    ) else {
+        return Some((MAIN_CHUNK_OFFSET, "truncated chunk header".to_string()));
+    };
+    let mut offset = MAIN_CHUNK_OFFSET + CHUNK_HEADER_SIZE + main_content_size as usize;
+    let end = offset + main_children_size as usize;
+    if end > bytes.len() {
+        return Some((
+            MAIN_CHUNK_OFFSET,
+            "MAIN chunk extends past the end of the file".to_string(),
+        ));
+    }
+    while offset < end {
+        let (Some(content_size), Some(children_size)) =
+            (read_u32(bytes, offset + 4), read_u32(bytes, offset + 8))
+        else {
+            return Some((offset, "truncated chunk header".to_string()));
+        };
+        let id = String::from_utf8_lossy(&bytes[offset..offset + 4]).into_owned();
+        let content_start = offset + CHUNK_HEADER_SIZE;
+        let content_end = content_start + content_size as usize;
+        let chunk_end = content_end + children_size as usize;
+        if chunk_end > end {
+            return Some((
+                offset,
+                format!("{} chunk extends past the end of the file", id),
+            ));
+        }
+        if !is_valid_content(
+            &bytes[offset..offset + 4],
+            &bytes[content_start..content_end],
+        ) {
+            return Some((offset, format!("malformed {} chunk", id)));
+        }
+        offset = chunk_end;
+    }
+    None
+}
+
+/// Checks that the contents of the chunk are long enough for the data that they declare
+fn is_valid_content(chunk_id: &[u8], content: &[u8]) -> bool {
+    match chunk_id {
+        b"SIZE" => content.len() >= 12,
+        b"XYZI" => {
+            read_u32(content, 0).is_some_and(|count| content.len() >= 4 + 4 * count as usize)
+        }
+        b"RGBA" => content.len() >= 1024,
+        // an id followed by a dictionary of attributes
+        b"nTRN" | b"nGRP" | b"nSHP" | b"MATL" | b"LAYR" | b"rCAM" => {
+            content.get(4..).and_then(parse_dict).is_some()
+        }
+        b"rOBJ" => parse_dict(content).is_some(),
+        _ => true,
+    }
+}
+
+/// Reads a dictionary (a count followed by that many key-value string pairs) from the start of `bytes`
+pub(super) fn parse_dict(bytes: &[u8]) -> Option<Dict> {
+    let count = read_u32(bytes, 0)?;
+    let mut offset = 4;
+    let mut dict = Dict::new();
+    for _ in 0..count {
+        let (key, next) = read_string(bytes, offset)?;
+        let (value, next) = read_string(bytes, next)?;
+        dict.insert(key, value);
+        offset = next;
+    }
+    Some(dict)
+}
+
+/// Reads a value made up of space-separated numbers, such as a color or a vector
+#[cfg(feature = "render")]
+pub(super) fn dict_floats(dict: &Dict, key: &str) -> Option<Vec<f32>> {
+    dict.get(key)?
+        .split_whitespace()
+        .map(|component| component.parse().ok())
+        .collect()
+}
+
+#[cfg(feature = "render")]
+pub(super) fn dict_float(dict: &Dict, key: &str) -> Option<f32> {
+    dict.get(key)?.trim().parse().ok()
+}
+
+pub(super) fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_string(bytes: &[u8], offset: usize) -> Option<(String, usize)> {
+    let length = read_u32(bytes, offset)? as usize;
+    let start = offset + 4;
+    let string = bytes.get(start..start + length)?;
+    Some((String::from_utf8_lossy(string).into_owned(), start + length))
+}
@@ -1,9 +1,11 @@
 use std::time::Duration;
 
+#[cfg(feature = "render")]
+use bevy::{animation::graph::AnimationNodeIndex, prelude::Visibility};
 use bevy::{
     asset::Handle,
     ecs::component::Component,
-    prelude::{ReflectComponent, Transform, Visibility},
+    prelude::{ReflectComponent, Transform},
     reflect::Reflect,
     time::Stopwatch,
 };
@@ -13,17 +15,20 @@ use crate::{VoxelContext, VoxelModel};
 #[derive(Debug, Clone)]
 pub(crate) struct LayerInfo {
     pub name: Option<String>,
+    #[cfg(feature = "render")]
     pub is_hidden: bool,
 }
 
 /// An instance of a [`VoxelModel`], or an animation consisting of a series of models.
 #[derive(Component, Clone, Reflect)]
 #[reflect(Component)]
-#[require(Transform, Visibility)]
+#[cfg_attr(feature = "render", require(Transform, Visibility))]
+#[cfg_attr(not(feature = "render"), require(Transform))]
 pub struct VoxelModelInstance {
     /// Handle to the model
     pub models: Vec<Handle<VoxelModel>>,
-    /// Handle to the context
+    /// Handle to the context. Changing this renders the instance with the materials of the new context's palette, without
+    /// remeshing the models. See [`VoxelContext::recolored`].
     pub context: Handle<VoxelContext>,
 }
 
@@ -36,16 +41,20 @@ impl VoxelModelInstance {
         }
     }
 
+    #[cfg(feature = "render")]
     pub(crate) fn has_animation(&self) -> bool {
         self.models.len() > 1
     }
 }
 
+/// The default duration of each frame of an animation
+pub(crate) const DEFAULT_FRAME_DURATION: Duration = Duration::from_millis(125);
+
 /// Plays Voxel Animations
 #[derive(Component, Clone, Reflect)]
 #[reflect(Component)]
 pub struct VoxelAnimationPlayer {
-    /// Frame indices
+    /// Indices of the models to show on each frame. A model is held for several frames by repeating its index.
     pub frames: Vec<usize>,
     /// Duration that each frame remains on screen
     pub frame_rate: Duration,
@@ -63,7 +72,7 @@ impl Default for VoxelAnimationPlayer {
     fn default() -> Self {
         Self {
             frames: vec![],
-            frame_rate: Duration::from_secs_f32(1.0 / 8.0),
+            frame_rate: DEFAULT_FRAME_DURATION,
             repeat_mode: AnimationRepeatMode::Forever,
             despawn_on_finish: true,
             is_paused: false,
@@ -97,13 +106,24 @@ impl Default for AnimationTimer {
     }
 }
 
+#[cfg(feature = "render")]
 pub enum AnimationUpdate {
     SameFrame,
     AdvanceFrame(usize),
     ReachedEnd,
 }
 
+#[cfg(feature = "render")]
 impl VoxelAnimationPlayer {
+    /// The index of the model shown on the current frame
+    #[cfg(feature = "picking")]
+    pub(crate) fn current_model_index(&self) -> usize {
+        self.frames
+            .get(self.timer.current_frame_index)
+            .copied()
+            .unwrap_or_default()
+    }
+
     pub(crate) fn did_advance_frame(&mut self, delta: Duration) -> AnimationUpdate {
         if self.is_paused {
             return AnimationUpdate::SameFrame;
@@ -134,9 +154,21 @@ impl VoxelAnimationPlayer {
     }
 }
 
+#[cfg(feature = "render")]
 #[derive(Component)]
 pub struct VoxelAnimationFrame(pub usize);
 
+/// Added to the root entity of a scene containing transform keyframe animations, alongside an
+/// [`bevy::animation::AnimationPlayer`] and the `animation-graph` subasset.
+/// The animation starts playing on a loop when the scene is spawned.
+#[cfg(feature = "render")]
+#[derive(Component, Clone, Reflect)]
+#[reflect(Component)]
+pub struct VoxelTransformAnimation {
+    /// The node of the animation graph that plays the file's `animation` clip
+    pub node: AnimationNodeIndex,
+}
+
 /// A component specifying which layer the Entity belongs to, with an optional name.
 ///
 /// This can be configured in the Magica Voxel world editor.
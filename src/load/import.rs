@@ -0,0 +1,375 @@
+use anyhow::anyhow;
+use bevy::{
+    asset::io::Reader,
+    math::{IVec3, UVec3},
+    utils::HashMap,
+};
+use dot_vox::{Dict, Frame, Layer, Model, SceneNode, ShapeModel, Size};
+
+use crate::{
+    model::{color_from_rgba8, VoxelPalette},
+    VoxelElement,
+};
+
+use super::{VoxLoaderError, VoxLoaderSettings};
+
+/// The maximum length of a Magica Voxel model along any axis
+const MAX_MODEL_SIZE: u32 = 256;
+
+/// The most colors a palette can hold, as [`crate::Voxel::EMPTY`] takes one of the 256 indices
+const MAX_COLORS: usize = 255;
+
+/// Builds the models, scene graph and layers of a Magica Voxel file from a file in another format, so that it can be
+/// loaded with [`super::load_voxel_scene`]. Positions and sizes are given in voxels, in bevy's right-handed Y-up space.
+pub(super) struct VoxFileBuilder {
+    pub models: Vec<Model>,
+    pub graph: Vec<SceneNode>,
+    pub layers: Vec<Layer>,
+}
+
+impl VoxFileBuilder {
+    /// A file with an empty group at the root of its scene graph
+    pub fn new() -> Self {
+        Self {
+            models: Vec::new(),
+            graph: vec![
+                SceneNode::Transform {
+                    attributes: Dict::default(),
+                    frames: vec![Frame::default()],
+                    child: 1,
+                    layer_id: u32::MAX,
+                },
+                SceneNode::Group {
+                    attributes: Dict::default(),
+                    children: Vec::new(),
+                },
+            ],
+            layers: Vec::new(),
+        }
+    }
+
+    /// Adds a layer, returning its id
+    #[cfg(feature = "goxel")]
+    pub fn add_layer(&mut self, name: Option<&str>, is_hidden: bool) -> u32 {
+        let mut attributes = Dict::default();
+        if let Some(name) = name {
+            attributes.insert("_name".to_string(), name.to_string());
+        }
+        if is_hidden {
+            attributes.insert("_hidden".to_string(), "1".to_string());
+        }
+        self.layers.push(Layer { attributes });
+        self.layers.len() as u32 - 1
+    }
+
+    /// Adds a model to the root of the scene, filling the voxels from `minimum` to `minimum + size`. Each voxel is given
+    /// as its position relative to `minimum`, along with its index in the palette.
+    ///
+    /// Models that are larger than Magica Voxel allows are split into several models, placed under a single node with
+    /// the `name` of the model, and named `{name}/part-{index}`. Parts of a split model that contain no voxels are left
+    /// out.
+    pub fn add_model(
+        &mut self,
+        name: Option<&str>,
+        minimum: IVec3,
+        size: UVec3,
+        voxels: impl IntoIterator<Item = (UVec3, u8)>,
+        layer_id: u32,
+    ) {
+        let mut parts: HashMap<UVec3, Vec<(UVec3, u8)>> = HashMap::new();
+        for (position, index) in voxels {
+            if position.cmpge(size).any() {
+                continue;
+            }
+            let part = position / MAX_MODEL_SIZE;
+            parts
+                .entry(part)
+                .or_default()
+                .push((position - part * MAX_MODEL_SIZE, index));
+        }
+        if size.max_element() <= MAX_MODEL_SIZE {
+            let voxels = parts.remove(&UVec3::ZERO).unwrap_or_default();
+            let node = self.add_shape(name, position_of(minimum, size), size, voxels, layer_id);
+            self.add_to_root(node);
+            return;
+        }
+
+        let center = position_of(minimum, size);
+        let mut parts: Vec<(UVec3, Vec<(UVec3, u8)>)> = parts.into_iter().collect();
+        parts.sort_by_key(|(part, _)| (part.z, part.y, part.x));
+        let node = self.graph.len() as u32;
+        let group = node + 1;
+        self.graph
+            .push(transform_node(name, center, group, layer_id));
+        self.add_to_root(node);
+        self.graph.push(SceneNode::Group {
+            attributes: Dict::default(),
+            children: Vec::new(),
+        });
+        for (part_index, (part, voxels)) in parts.into_iter().enumerate() {
+            let part_minimum = part * MAX_MODEL_SIZE;
+            let part_size = (size - part_minimum).min(UVec3::splat(MAX_MODEL_SIZE));
+            let position = position_of(minimum + part_minimum.as_ivec3(), part_size) - center;
+            let part_name = name.map(|_| format!("part-{}", part_index));
+            let node = self.add_shape(part_name.as_deref(), position, part_size, voxels, layer_id);
+            if let SceneNode::Group { children, .. } = &mut self.graph[group as usize] {
+                children.push(node);
+            }
+        }
+    }
+
+    /// Adds a transform node and a shape node for a single model, returning the index of the transform node
+    fn add_shape(
+        &mut self,
+        name: Option<&str>,
+        position: IVec3,
+        size: UVec3,
+        voxels: Vec<(UVec3, u8)>,
+        layer_id: u32,
+    ) -> u32 {
+        let model_id = self.models.len() as u32;
+        self.models.push(to_model(size, voxels));
+        let node = self.graph.len() as u32;
+        self.graph
+            .push(transform_node(name, position, node + 1, layer_id));
+        self.graph.push(SceneNode::Shape {
+            attributes: Dict::default(),
+            models: vec![ShapeModel {
+                model_id,
+                attributes: Dict::default(),
+            }],
+        });
+        node
+    }
+
+    fn add_to_root(&mut self, node: u32) {
+        if let SceneNode::Group { children, .. } = &mut self.graph[1] {
+            children.push(node);
+        }
+    }
+}
+
+fn transform_node(name: Option<&str>, position: IVec3, child: u32, layer_id: u32) -> SceneNode {
+    let mut attributes = Dict::default();
+    if let Some(name) = name {
+        attributes.insert("_name".to_string(), name.to_string());
+    }
+    let mut frame = Dict::default();
+    frame.insert(
+        "_t".to_string(),
+        format!("{} {} {}", position.x, position.y, position.z),
+    );
+    SceneNode::Transform {
+        attributes,
+        frames: vec![Frame::new(frame)],
+        child,
+        layer_id,
+    }
+}
+
+/// The position of a model filling the voxels from `minimum` to `minimum + size`, in Magica Voxel's left-handed Z-up
+/// space. Magica Voxel places models by the voxel at the middle of each axis, rounding down.
+fn position_of(minimum: IVec3, size: UVec3) -> IVec3 {
+    let size = size.as_ivec3();
+    IVec3::new(
+        -(minimum.x + size.x) + size.x / 2,
+        minimum.z + size.z / 2,
+        minimum.y + size.y / 2,
+    )
+}
+
+/// Converts voxels from bevy's right-handed Y-up space to Magica Voxel's left-handed Z-up space.
+/// This is the inverse of `VoxelData::from_model`.
+fn to_model(size: UVec3, voxels: Vec<(UVec3, u8)>) -> Model {
+    Model {
+        size: Size {
+            x: size.x,
+            y: size.z,
+            z: size.y,
+        },
+        voxels: voxels
+            .into_iter()
+            .map(|(position, index)| dot_vox::Voxel {
+                x: ((size.x - 1) - position.x) as u8,
+                y: position.z as u8,
+                z: position.y as u8,
+                i: index,
+            })
+            .collect(),
+    }
+}
+
+/// A palette of diffuse elements with the given 8-bit colors
+pub(super) fn palette_from_colors(
+    colors: &[[u8; 4]],
+    settings: &VoxLoaderSettings,
+) -> VoxelPalette {
+    VoxelPalette::new(
+        colors
+            .iter()
+            .map(|color| VoxelElement {
+                color: color_from_rgba8(*color, settings.uses_srgb),
+                roughness: settings.diffuse_roughness,
+                ..Default::default()
+            })
+            .collect(),
+        settings.uses_srgb,
+    )
+}
+
+/// Reduces the colors of true-color voxels to a palette, using median cut if there are more colors than fit.
+#[derive(Default)]
+pub(super) struct ColorQuantizer {
+    counts: HashMap<[u8; 3], u32>,
+}
+
+impl ColorQuantizer {
+    /// Counts a voxel of the given color
+    pub fn add(&mut self, color: [u8; 3]) {
+        *self.counts.entry(color).or_default() += 1;
+    }
+
+    /// The colors of the palette, along with the palette index for each of the colors that were added
+    pub fn palette(self) -> (Vec<[u8; 4]>, HashMap<[u8; 3], u8>) {
+        let mut boxes: Vec<Vec<([u8; 3], u32)>> = vec![self.counts.into_iter().collect()];
+        // split the box with the widest range of a channel at its median, until there are enough boxes
+        while boxes.len() < MAX_COLORS {
+            let Some((index, channel, _)) = boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, colors)| colors.len() > 1)
+                .map(|(index, colors)| {
+                    let (channel, range) = (0..3)
+                        .map(|channel| {
+                            let values = colors.iter().map(|(color, _)| color[channel]);
+                            let range =
+                                values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+                            (channel, range)
+                        })
+                        .max_by_key(|(_, range)| *range)
+                        .unwrap_or((0, 0));
+                    (index, channel, range)
+                })
+                .max_by_key(|(_, _, range)| *range)
+            else {
+                break;
+            };
+            let mut colors = boxes.swap_remove(index);
+            colors.sort_unstable_by_key(|(color, _)| (color[channel], *color));
+            let total: u32 = colors.iter().map(|(_, count)| count).sum();
+            let mut seen = 0;
+            let split = colors
+                .iter()
+                .position(|(_, count)| {
+                    seen += count;
+                    seen * 2 >= total
+                })
+                .unwrap_or(0)
+                .clamp(0, colors.len() - 2)
+                + 1;
+            let upper = colors.split_off(split);
+            boxes.push(colors);
+            boxes.push(upper);
+        }
+        // the boxes are sorted so that the palette doesn't depend on the order of the hash map
+        boxes.sort_unstable_by_key(|colors| colors.iter().map(|(color, _)| *color).min());
+        let mut palette = Vec::with_capacity(boxes.len());
+        let mut indices = HashMap::new();
+        for (index, colors) in boxes.iter().enumerate() {
+            let total: u64 = colors.iter().map(|(_, count)| *count as u64).sum();
+            let average = |channel: usize| {
+                let sum: u64 = colors
+                    .iter()
+                    .map(|(color, count)| color[channel] as u64 * *count as u64)
+                    .sum();
+                (sum / total.max(1)) as u8
+            };
+            palette.push([average(0), average(1), average(2), u8::MAX]);
+            for (color, _) in colors {
+                indices.insert(*color, index as u8);
+            }
+        }
+        (palette, indices)
+    }
+}
+
+/// Reads the whole of a file being loaded
+pub(super) async fn read_file(reader: &mut dyn Reader) -> Result<Vec<u8>, VoxLoaderError> {
+    let mut bytes = Vec::new();
+    reader
+        .read_to_end(&mut bytes)
+        .await
+        .map_err(|e| VoxLoaderError::InvalidAsset(anyhow!(e)))?;
+    Ok(bytes)
+}
+
+/// Reads the little-endian values of a binary file, reporting where the file ends early
+pub(super) struct ByteReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    format: &'static str,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(bytes: &'a [u8], format: &'static str) -> Self {
+        Self {
+            bytes,
+            offset: 0,
+            format,
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    #[cfg(feature = "goxel")]
+    pub fn is_at_end(&self) -> bool {
+        self.offset >= self.bytes.len()
+    }
+
+    /// Moves to `offset`, which may be past the end of the file
+    pub fn seek(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    pub fn bytes(&mut self, length: usize) -> Result<&'a [u8], VoxLoaderError> {
+        let end = self.offset.saturating_add(length);
+        let Some(bytes) = self.bytes.get(self.offset..end) else {
+            return Err(self.error("unexpected end of file"));
+        };
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, VoxLoaderError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, VoxLoaderError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, VoxLoaderError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, VoxLoaderError> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], VoxLoaderError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    /// An error for the value at the current offset
+    pub fn error(&self, message: impl Into<String>) -> VoxLoaderError {
+        VoxLoaderError::InvalidFile {
+            format: self.format,
+            offset: self.offset,
+            message: message.into(),
+        }
+    }
+}
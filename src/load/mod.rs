@@ -1,31 +1,81 @@
+mod chunks;
 mod components;
+mod import;
+#[cfg(feature = "schematics")]
+mod nbt;
+mod parse_animation;
+#[cfg(feature = "render")]
+mod parse_camera;
+#[cfg(feature = "goxel")]
+mod parse_goxel;
+mod parse_kvx;
 mod parse_model;
+mod parse_palette;
+mod parse_qubicle;
 mod parse_scene;
+#[cfg(feature = "schematics")]
+mod parse_schematic;
+#[cfg(feature = "render")]
+mod render_assets;
+#[cfg(feature = "render")]
+mod render_settings;
 
 use anyhow::anyhow;
+#[cfg(feature = "render")]
+use bevy::prelude::World;
 use bevy::{
-    asset::{io::Reader, AssetLoader, Handle, LoadContext},
-    color::LinearRgba,
-    image::Image,
-    log::info,
+    asset::{io::Reader, AssetLoader, LoadContext},
+    log::{info, warn},
     math::Vec3,
-    pbr::StandardMaterial,
     scene::Scene,
     utils::HashSet,
 };
+use chunks::{find_malformed_chunk, MAIN_CHUNK_OFFSET};
 use components::LayerInfo;
-pub use components::{
-    AnimationUpdate, VoxelAnimationFrame, VoxelAnimationPlayer, VoxelLayer, VoxelModelInstance,
+#[cfg(feature = "render")]
+pub use components::{AnimationUpdate, VoxelAnimationFrame, VoxelTransformAnimation};
+pub use components::{VoxelAnimationPlayer, VoxelLayer, VoxelModelInstance};
+use dot_vox::{Layer, Model, SceneNode};
+use parse_animation::TransformAnimations;
+#[cfg(feature = "render")]
+use parse_camera::parse_cameras;
+#[cfg(feature = "goxel")]
+pub(crate) use parse_goxel::GoxelSceneLoader;
+pub(crate) use parse_kvx::KvxSceneLoader;
+pub(crate) use parse_palette::VoxelPaletteLoader;
+pub use parse_palette::{PaletteLoaderError, PaletteLoaderSettings};
+pub(crate) use parse_qubicle::QubicleSceneLoader;
+#[cfg(feature = "export_voxels")]
+pub(crate) use parse_scene::rotation_from_orientation;
+use parse_scene::{
+    default_scene_graph, find_model_names, parse_scene_graph, validate_scene_graph,
+    SceneGraphContext,
+};
+#[cfg(feature = "schematics")]
+pub use parse_schematic::SchematicBlocks;
+#[cfg(feature = "schematics")]
+pub(crate) use parse_schematic::{
+    sync_schematic_blocks, SchematicSceneLoader, SharedSchematicBlocks,
+};
+#[cfg(feature = "render")]
+use render_assets::PaletteMaterials;
+#[cfg(feature = "render")]
+use render_settings::parse_render_settings;
+#[cfg(feature = "render")]
+pub use render_settings::{
+    VoxelFog, VoxelRenderSettings, VoxelSky, VoxelSun, SKY_BRIGHTNESS, SUN_ILLUMINANCE,
 };
-use parse_scene::{find_model_names, parse_scene_graph};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::{
-    model::{MaterialProperty, VoxelModel, VoxelPalette},
-    VoxelContext, VoxelData, VoxelQueryable,
+    model::{VoxelModel, VoxelPalette},
+    VoxelContext, VoxelData,
 };
 
+/// Versions of the `.vox` format written by Magica Voxel 0.98 and later
+const SUPPORTED_VERSIONS: [u32; 2] = [150, 200];
+
 /// An asset loader capable of loading models in `.vox` files as [`bevy::scene::Scene`]s.
 ///
 /// It converts Magica Voxel's left-handed Z-up space to bevy's right-handed Y-up space.
@@ -37,7 +87,9 @@ pub(super) struct VoxSceneLoader {
 }
 
 /// Settings for the VoxSceneLoader.
+/// Fields that are missing when deserializing, such as from a `.meta` file, take their default values.
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
 pub struct VoxLoaderSettings {
     /// The length of each side of a single voxel. Defaults to 1.0.
     pub voxel_size: f32,
@@ -53,6 +105,47 @@ pub struct VoxLoaderSettings {
     pub uses_srgb: bool,
     /// Magica Voxel doesn't let you adjust the roughness for the default "diffuse" block type, so it can be adjusted with this setting. Defaults to 0.8.
     pub diffuse_roughness: f32,
+    /// Whether the cameras saved in the file should be spawned into the scene. Defaults to false.
+    /// Magica Voxel saves 10 camera slots, and only the first of these will be active.
+    /// Regardless of this setting, each camera can be loaded as a separate scene by appending `#camera-{no}` to the asset loading path.
+    /// Requires the `render` feature.
+    pub spawn_cameras: bool,
+    /// Whether the sun from Magica Voxel's render tab should be spawned into the scene as a [`bevy::pbr::DirectionalLight`],
+    /// and any fog added to the cameras spawned by [`VoxLoaderSettings::spawn_cameras`]. Defaults to false.
+    /// Regardless of this setting, the lighting can be loaded as a `VoxelRenderSettings` asset by appending `#render-settings` to the asset loading path.
+    /// Requires the `render` feature.
+    pub spawn_lights: bool,
+    /// Whether problems that the loader can work around should fail the load instead. Defaults to false, in which case they
+    /// are logged as warnings. These are nested transform nodes, group or shape nodes without a parent transform node,
+    /// invalid boolean attributes, node names that are used more than once, and unsupported file versions.
+    /// Enable this in asset pipelines that should reject broken files.
+    pub strict: bool,
+    /// Whether to load only the voxel data, names, layers and transforms, skipping the meshes, materials and images.
+    /// Defaults to false. Use this where models are only queried, such as on a headless server, to avoid the cost of meshing.
+    /// Models are always loaded this way if the `render` feature is disabled.
+    pub data_only: bool,
+    /// Whether to defer meshing each model until the first time an instance of it is spawned. Defaults to false.
+    /// Use this for large libraries of models where only a few are spawned, so that load time and memory scale with the
+    /// models that are actually used. The `{name}@mesh`, `{name}@material` and `{name}@cloud-image` subassets are not
+    /// available when this is set; use the handles on the [`VoxelModel`] once it has been spawned instead.
+    /// Has no effect if [`VoxLoaderSettings::data_only`] is set.
+    pub lazy_meshing: bool,
+    /// The number of lower resolution meshes to generate for each model, as levels of detail. Defaults to 0.
+    /// Each level halves the resolution of the one before, and can be loaded by appending `#{name}@mesh-lod{level}` to the
+    /// asset loading path. Spawned instances switch between the levels with [`bevy::render::view::VisibilityRange`].
+    pub lod_levels: u32,
+    /// The distance from the camera at which a model switches to its first level of detail. Defaults to 50.0.
+    /// Each further level takes over at twice the distance of the level before.
+    pub lod_distance: f32,
+    /// Whether to bake ambient occlusion from neighbouring voxels into the vertex colors of the meshes. Defaults to false.
+    /// This darkens the corners and crevices of a model without relying on screen-space ambient occlusion, at the cost
+    /// of merging fewer faces in the mesh, as faces are only merged where their occlusion is the same.
+    /// Only applies to [`MeshingMode::Blocky`].
+    pub ambient_occlusion: bool,
+    /// How the surface of each model is meshed. Defaults to [`MeshingMode::Blocky`].
+    pub meshing_mode: MeshingMode,
+    /// How the meshes carry the color of each voxel. Defaults to [`MeshColorMode::PaletteTexture`].
+    pub color_mode: MeshColorMode,
 }
 
 impl Default for VoxLoaderSettings {
@@ -64,10 +157,31 @@ impl Default for VoxLoaderSettings {
             emission_strength: 20.0,
             uses_srgb: true,
             diffuse_roughness: 0.8,
+            spawn_cameras: false,
+            spawn_lights: false,
+            strict: false,
+            data_only: false,
+            lazy_meshing: false,
+            lod_levels: 0,
+            lod_distance: 50.0,
+            ambient_occlusion: false,
+            meshing_mode: MeshingMode::Blocky,
+            color_mode: MeshColorMode::PaletteTexture,
         }
     }
 }
 
+impl VoxLoaderSettings {
+    /// Returns `error` if [`VoxLoaderSettings::strict`] is set, otherwise logs it as a warning
+    pub(super) fn report(&self, error: VoxLoaderError) -> Result<(), VoxLoaderError> {
+        if self.strict {
+            return Err(error);
+        }
+        warn!("{}", error);
+        Ok(())
+    }
+}
+
 /// An offset applied to the vertex positions of the mesh expressed as a unit of the mesh's size.
 /// For a fully centered mesh, use [`UnitOffset::CENTER`]
 /// For a mesh centred around it's base, use [`UnitOffset::CENTER_BASE`]
@@ -85,10 +199,123 @@ impl UnitOffset {
     pub const CENTER_BASE: Self = UnitOffset(Vec3::new(0.5, 0.0, 0.5));
 }
 
+/// The algorithm used to generate the mesh of a model from its voxel data
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MeshingMode {
+    /// A cube for each voxel, with the faces of neighbouring voxels of the same material merged into larger quads
+    #[default]
+    Blocky,
+    /// A smooth surface passing between the centers of the filled and empty voxels, using the Surface Nets algorithm.
+    /// Suited to terrain and organic shapes. Each face takes the color of the voxel it belongs to, so the materials of
+    /// the model's [`crate::VoxelContext`] are unchanged.
+    SurfaceNets,
+}
+
+/// How the meshes generated from a model carry the color of each voxel
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MeshColorMode {
+    /// Each vertex has a UV into the 16x16 texture of palette colors held by the material
+    #[default]
+    PaletteTexture,
+    /// Each vertex has the linear color of its voxel in [`bevy::render::mesh::Mesh::ATTRIBUTE_COLOR`], and the raw
+    /// palette index of its voxel in [`crate::ATTRIBUTE_PALETTE_INDEX`], for custom shaders and for exporting the meshes.
+    /// The palette UVs are still written, so that the emission, roughness, metalness and transmission textures of the
+    /// material apply, while the material's base color texture is left out.
+    VertexColors,
+}
+
+/// An error that occurred while loading a voxel file
 #[derive(Error, Debug)]
 pub enum VoxLoaderError {
+    /// The file could not be read
     #[error(transparent)]
     InvalidAsset(#[from] anyhow::Error),
+    /// A file in a format other than `.vox` is not valid
+    #[error("failed to parse the {format} file at byte {offset}: {message}")]
+    InvalidFile {
+        /// The format of the file
+        format: &'static str,
+        /// Byte offset of the value that could not be parsed
+        offset: usize,
+        /// Description of the failure
+        message: String,
+    },
+    /// The file is not a valid `.vox` file
+    #[error("failed to parse the chunk at byte {offset}: {message}")]
+    Parse {
+        /// Byte offset of the chunk that could not be parsed. If the failure couldn't be narrowed down to a single chunk,
+        /// this is the offset of the `MAIN` chunk.
+        offset: usize,
+        /// Description of the failure
+        message: String,
+    },
+    /// The file was written with a version of the format that the loader doesn't support
+    #[error("unsupported file version {0}")]
+    UnsupportedVersion(u32),
+    /// The file does not contain any models
+    #[error("the file does not contain any models")]
+    NoModels,
+    /// A node of the scene graph references a child node that does not exist, or that is one of its own ancestors
+    #[error("scene node {node} references invalid child node {child}")]
+    InvalidSceneNodeReference {
+        /// Index of the node holding the reference
+        node: u32,
+        /// Index of the child node being referenced
+        child: u32,
+    },
+    /// The structure uses more kinds of block than fit in a palette
+    #[error("the structure uses more than 255 kinds of block")]
+    TooManyBlocks,
+    /// A shape node of the scene graph references a model that does not exist
+    #[error(
+        "shape node {node} references model {model}, but the file only has {model_count} models"
+    )]
+    ModelIndexOutOfRange {
+        /// Index of the shape node
+        node: u32,
+        /// Index of the model being referenced
+        model: u32,
+        /// The number of models in the file
+        model_count: usize,
+    },
+    /// Several nodes of the scene graph have the same name, so only the first can be loaded as a labeled subasset.
+    /// Only returned if [`VoxLoaderSettings::strict`] is set.
+    #[error("the name {0:?} is used by more than one node")]
+    DuplicateName(String),
+    /// A transform node is the direct child of another transform node.
+    /// Only returned if [`VoxLoaderSettings::strict`] is set.
+    #[error("transform node {node} is the child of another transform node")]
+    NestedTransform {
+        /// Index of the nested transform node
+        node: u32,
+    },
+    /// A group or shape node is not the child of a transform node.
+    /// Only returned if [`VoxLoaderSettings::strict`] is set.
+    #[error("node {node} does not have a parent transform node")]
+    MissingParentTransform {
+        /// Index of the group or shape node
+        node: u32,
+    },
+    /// A boolean attribute such as `_hidden` has a value other than `0` or `1`.
+    /// Only returned if [`VoxLoaderSettings::strict`] is set.
+    #[error("attribute {key} of node {node} has invalid boolean value {value:?}")]
+    InvalidBoolean {
+        /// Index of the node
+        node: u32,
+        /// Name of the attribute
+        key: String,
+        /// The invalid value
+        value: String,
+    },
+    /// A model of a shape node has an `_f` keyframe index past the last frame that the loader supports, so its
+    /// keyframe is moved to that frame. Only returned if [`VoxLoaderSettings::strict`] is set.
+    #[error("shape node {node} has keyframe {frame}, past the last supported frame {max}", max = parse_scene::MAX_KEYFRAME)]
+    KeyframeOutOfRange {
+        /// Index of the shape node
+        node: u32,
+        /// The keyframe index of the model
+        frame: u32,
+    },
 }
 
 impl AssetLoader for VoxSceneLoader {
@@ -122,141 +349,150 @@ impl VoxSceneLoader {
         load_context: &'a mut LoadContext,
         settings: &'a VoxLoaderSettings,
     ) -> Result<Scene, VoxLoaderError> {
+        // dot_vox skips chunks that it fails to parse, so check the structure of the file first
+        if let Some((offset, message)) = find_malformed_chunk(bytes) {
+            return Err(VoxLoaderError::Parse { offset, message });
+        }
         let file = match dot_vox::load_bytes(bytes) {
             Ok(data) => data,
-            Err(error) => return Err(VoxLoaderError::InvalidAsset(anyhow!(error))),
+            Err(error) => {
+                return Err(VoxLoaderError::Parse {
+                    offset: MAIN_CHUNK_OFFSET,
+                    message: error.to_string(),
+                })
+            }
         };
         info!("Loading {}", load_context.asset_path());
         let settings = self.global_settings.clone().unwrap_or(settings.clone());
+        if !SUPPORTED_VERSIONS.contains(&file.version) {
+            settings.report(VoxLoaderError::UnsupportedVersion(file.version))?;
+        }
+        if file.models.is_empty() {
+            return Err(VoxLoaderError::NoModels);
+        }
+        // Files written by older versions of Magica Voxel or by other editors may not have a scene graph
+        let graph = if file.scenes.is_empty() {
+            default_scene_graph(file.models.len())
+        } else {
+            validate_scene_graph(&file.scenes, file.models.len(), &settings)?;
+            file.scenes.clone()
+        };
 
-        // Palette
         let palette = VoxelPalette::from_data(
             &file,
             settings.diffuse_roughness,
             settings.emission_strength,
             settings.uses_srgb,
         );
-        let translucent_material = palette.create_material_in_load_context(load_context);
-        let opaque_material = load_context.labeled_asset_scope("material".to_string(), |_| {
-            let mut opaque_material = translucent_material.clone();
-            #[cfg(feature = "pbr_transmission_textures")]
-            {
-                opaque_material.specular_transmission_texture = None;
-            }
-            opaque_material.specular_transmission = 0.0;
-            opaque_material
-        });
-        if palette.emission == MaterialProperty::VariesPerElement {
-            load_context.labeled_asset_scope("material-no-emission".to_string(), |_| {
-                let mut non_emissive = translucent_material.clone();
-                non_emissive.emissive_texture = None;
-                non_emissive.emissive = LinearRgba::BLACK;
-                non_emissive
-            });
-        }
-
-        // Scene graph
-        let layers: Vec<LayerInfo> = file
-            .layers
-            .iter()
-            .map(|layer| LayerInfo {
-                name: layer.name(),
-                is_hidden: layer.hidden(),
-            })
-            .collect();
-
-        let model_count = file.models.len();
-        let mut subassets: HashSet<String> = HashSet::new();
-        let mut model_names: Vec<Option<String>> = vec![None; model_count];
-        find_model_names(&mut model_names, &file.scenes, &file.scenes[0], None);
-        let scene = parse_scene_graph(
+        let scene = load_voxel_scene(
             load_context,
-            &file.scenes,
-            &file.scenes[0],
-            None,
-            &mut model_names,
-            &mut subassets,
-            &layers,
-            settings.voxel_size,
+            &settings,
+            file.models,
+            &graph,
+            &file.layers,
+            palette,
         );
+        #[cfg(feature = "render")]
+        let scene = load_cameras_and_lights(bytes, load_context, scene, &settings);
+        Ok(scene)
+    }
+}
 
-        // Models
+/// Adds the models and the context of a file as subassets, along with a scene for each named node of its scene graph,
+/// and returns the scene of the whole file. Files in formats other than `.vox` are converted to Magica Voxel's models,
+/// scene graph and layers so that they are loaded in the same way.
+pub(super) fn load_voxel_scene(
+    load_context: &mut LoadContext,
+    settings: &VoxLoaderSettings,
+    models: Vec<Model>,
+    graph: &Vec<SceneNode>,
+    layers: &[Layer],
+    palette: VoxelPalette,
+) -> Scene {
+    #[cfg(feature = "render")]
+    let materials = (!settings.data_only).then(|| PaletteMaterials::new(load_context, &palette));
 
-        model_names
-            .iter()
-            .zip(file.models)
-            .enumerate()
-            .for_each(|(index, (maybe_name, model))| {
-                let name = maybe_name.clone().unwrap_or(format!("model-{}", index));
-                let data = VoxelData::from_model(&model, settings.clone());
-                let (visible_voxels, ior, needs_meshing) =
-                    data.visible_voxels(&palette.indices_of_refraction, &palette.density_for_voxel);
-                let (cloud_voxels, has_cloud) = data.cloud_voxels(&palette.density_for_voxel);
-                let mesh = if needs_meshing {
-                    let mesh_handle = load_context
-                        .labeled_asset_scope(format!("{}@mesh", name), |_| {
-                            crate::model::mesh::mesh_model(&visible_voxels, &data)
-                        });
-                    Some(mesh_handle)
-                } else {
-                    None
-                };
+    // Scene graph
+    let layers: Vec<LayerInfo> = layers
+        .iter()
+        .map(|layer| LayerInfo {
+            name: layer.name(),
+            #[cfg(feature = "render")]
+            is_hidden: layer.hidden(),
+        })
+        .collect();
 
-                let material: Option<Handle<StandardMaterial>> = if needs_meshing {
-                    if let Some(ior) = ior {
-                        let handle =
-                            load_context.labeled_asset_scope(format!("{}@material", name), |_| {
-                                let mut material = translucent_material.clone();
-                                material.ior = ior;
-                                material.thickness = data.size().min_element() as f32;
-                                material
-                            });
-                        Some(handle)
-                    } else {
-                        let handle =
-                            load_context.labeled_asset_scope(format!("{}@material", name), |_| {
-                                let mut opaque_material = translucent_material.clone();
-                                #[cfg(feature = "pbr_transmission_textures")]
-                                {
-                                    opaque_material.specular_transmission_texture = None;
-                                }
-                                opaque_material.specular_transmission = 0.0;
-                                opaque_material
-                            });
-                        Some(handle)
-                    }
-                } else {
-                    None
-                };
-                let cloud_image: Option<Handle<Image>> = if has_cloud {
-                    let cloud_handle = load_context
-                        .labeled_asset_scope(format!("{}@cloud-image", name), |_| {
-                            crate::model::cloud::create_cloud_image(&cloud_voxels, &data)
-                        });
-                    Some(cloud_handle)
-                } else {
-                    None
-                };
-                load_context.labeled_asset_scope(format!("{}@model", name), |_| VoxelModel {
-                    name,
-                    data,
-                    mesh,
-                    material,
-                    cloud_image,
-                    has_translucency: ior.is_some(),
-                });
-            });
+    let model_count = models.len();
+    let mut model_names: Vec<Option<String>> = vec![None; model_count];
+    find_model_names(&mut model_names, graph, &graph[0], None);
+    let mut scene_graph = SceneGraphContext {
+        graph,
+        model_names: &model_names,
+        layers: &layers,
+        subassets: HashSet::new(),
+        animations: TransformAnimations::new(load_context),
+        scene_scale: settings.voxel_size,
+    };
+    let scene = parse_scene_graph(load_context, &mut scene_graph, &graph[0], None);
+    scene_graph.animations.add_labeled_assets(load_context);
 
-        let transmissive_material = load_context
-            .add_labeled_asset("material-transmissive".to_string(), translucent_material);
-        load_context.add_labeled_asset(
-            "voxel-context".to_string(),
-            VoxelContext {
-                palette,
-                opaque_material,
-                transmissive_material,
-            },
-        );
-        Ok(scene)
+    // Models
+    for (index, (maybe_name, model)) in model_names.iter().zip(models).enumerate() {
+        let name = maybe_name.clone().unwrap_or(format!("model-{}", index));
+        let data = VoxelData::from_model(&model, settings.clone());
+        let label = format!("{}@model", name);
+        #[cfg(feature = "render")]
+        if let Some(materials) = &materials {
+            let model = if settings.lazy_meshing {
+                VoxelModel::awaiting_mesh(name, data)
+            } else {
+                materials.load_model(load_context, &palette, name, data)
+            };
+            load_context.add_labeled_asset(label, model);
+            continue;
+        }
+        load_context.add_labeled_asset(label, VoxelModel::without_mesh(name, data));
+    }
+
+    #[cfg(feature = "render")]
+    let context = match materials {
+        Some(materials) => materials.into_context(load_context, palette),
+        None => VoxelContext::without_materials(palette),
+    };
+    #[cfg(not(feature = "render"))]
+    let context = VoxelContext::without_materials(palette);
+    load_context.add_labeled_asset("voxel-context".to_string(), context);
+    scene
+}
+
+/// Adds the cameras and render settings as subassets, and spawns them into the scene if the settings ask for them
+#[cfg(feature = "render")]
+fn load_cameras_and_lights(
+    bytes: &[u8],
+    load_context: &mut LoadContext,
+    mut scene: Scene,
+    settings: &VoxLoaderSettings,
+) -> Scene {
+    let cameras = parse_cameras(bytes);
+    for camera in cameras.iter() {
+        load_context.labeled_asset_scope(format!("camera-{}", camera.id), |_| {
+            let mut world = World::default();
+            camera.spawn(&mut world, settings.voxel_size, true);
+            Scene::new(world)
+        });
+    }
+    let render_settings = parse_render_settings(bytes, settings.voxel_size);
+    if settings.spawn_cameras {
+        for camera in cameras.iter() {
+            let entity = camera.spawn(&mut scene.world, settings.voxel_size, camera.id == 0);
+            if let (true, Some(fog)) = (settings.spawn_lights, render_settings.distance_fog()) {
+                scene.world.entity_mut(entity).insert(fog);
+            }
+        }
+    }
+    if settings.spawn_lights {
+        render_settings.spawn_lights(&mut scene.world);
     }
+    load_context.add_labeled_asset("render-settings".to_string(), render_settings);
+    scene
 }
@@ -0,0 +1,161 @@
+use bevy::utils::HashMap;
+
+use super::{import::ByteReader, VoxLoaderError};
+
+/// How deeply lists and compounds may be nested, so that malformed files can't overflow the stack
+const MAX_DEPTH: usize = 512;
+
+/// A value in Minecraft's Named Binary Tag format
+pub(super) enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    ByteArray(Vec<u8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(HashMap<String, Tag>),
+    IntArray(Vec<i32>),
+    /// A float, double or long array, which structures don't use
+    Other,
+}
+
+impl Tag {
+    pub fn get(&self, key: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(entries) => entries.get(key),
+            _ => None,
+        }
+    }
+
+    /// The value of any integer tag
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Tag::Byte(value) => Some(*value as i64),
+            Tag::Short(value) => Some(*value as i64),
+            Tag::Int(value) => Some(*value as i64),
+            Tag::Long(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        match self {
+            Tag::List(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&HashMap<String, Tag>> {
+        match self {
+            Tag::Compound(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Tag::ByteArray(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// The values of a list of integer tags, or of an int array
+    pub fn as_ints(&self) -> Option<Vec<i64>> {
+        match self {
+            Tag::IntArray(values) => Some(values.iter().map(|value| *value as i64).collect()),
+            Tag::List(values) => values.iter().map(Tag::as_i64).collect(),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the root compound of an uncompressed NBT file
+pub(super) fn read_nbt(bytes: &[u8]) -> Result<Tag, VoxLoaderError> {
+    let mut reader = ByteReader::new(bytes, "NBT");
+    let kind = reader.u8()?;
+    if kind != 10 {
+        return Err(reader.error("the root tag is not a compound"));
+    }
+    read_string(&mut reader)?;
+    read_payload(&mut reader, kind, 0)
+}
+
+fn read_payload(reader: &mut ByteReader, kind: u8, depth: usize) -> Result<Tag, VoxLoaderError> {
+    if depth > MAX_DEPTH {
+        return Err(reader.error("tags are nested too deeply"));
+    }
+    Ok(match kind {
+        1 => Tag::Byte(i8::from_be_bytes(reader.array()?)),
+        2 => Tag::Short(i16::from_be_bytes(reader.array()?)),
+        3 => Tag::Int(i32::from_be_bytes(reader.array()?)),
+        4 => Tag::Long(i64::from_be_bytes(reader.array()?)),
+        5 => {
+            reader.bytes(4)?;
+            Tag::Other
+        }
+        6 => {
+            reader.bytes(8)?;
+            Tag::Other
+        }
+        7 => {
+            let length = read_length(reader)?;
+            Tag::ByteArray(reader.bytes(length)?.to_vec())
+        }
+        8 => Tag::String(read_string(reader)?),
+        9 => {
+            let kind = reader.u8()?;
+            let length = read_length(reader)?;
+            Tag::List(
+                (0..length)
+                    .map(|_| read_payload(reader, kind, depth + 1))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        10 => {
+            let mut entries = HashMap::new();
+            loop {
+                let kind = reader.u8()?;
+                if kind == 0 {
+                    break;
+                }
+                let name = read_string(reader)?;
+                entries.insert(name, read_payload(reader, kind, depth + 1)?);
+            }
+            Tag::Compound(entries)
+        }
+        11 => {
+            let length = read_length(reader)?;
+            let bytes = reader.bytes(length.saturating_mul(4))?;
+            Tag::IntArray(
+                bytes
+                    .chunks_exact(4)
+                    .map(|value| i32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+                    .collect(),
+            )
+        }
+        12 => {
+            let length = read_length(reader)?;
+            reader.bytes(length.saturating_mul(8))?;
+            Tag::Other
+        }
+        _ => return Err(reader.error(format!("unknown tag type {kind}"))),
+    })
+}
+
+fn read_length(reader: &mut ByteReader) -> Result<usize, VoxLoaderError> {
+    let length = i32::from_be_bytes(reader.array()?);
+    usize::try_from(length).map_err(|_| reader.error(format!("invalid length {length}")))
+}
+
+fn read_string(reader: &mut ByteReader) -> Result<String, VoxLoaderError> {
+    let length = u16::from_be_bytes(reader.array()?) as usize;
+    Ok(String::from_utf8_lossy(reader.bytes(length)?).into_owned())
+}
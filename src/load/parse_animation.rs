@@ -0,0 +1,191 @@
+#[cfg(feature = "render")]
+use bevy::{
+    animation::{
+        animated_field,
+        animation_curves::{AnimatableCurve, AnimatableKeyframeCurve, AnimatedField},
+        gltf_curves::SteppedKeyframeCurve,
+        graph::{AnimationGraph, AnimationGraphHandle, AnimationNodeIndex},
+        AnimationClip, AnimationPlayer, AnimationTarget, AnimationTargetId,
+    },
+    asset::Handle,
+    core::Name,
+    log::warn,
+    prelude::Transform,
+    utils::HashMap,
+};
+use bevy::{
+    asset::LoadContext,
+    ecs::entity::Entity,
+    prelude::{EntityWorldMut, World},
+};
+#[cfg(feature = "render")]
+use dot_vox::Frame;
+use dot_vox::SceneNode;
+
+#[cfg(feature = "render")]
+use super::{
+    components::{VoxelTransformAnimation, DEFAULT_FRAME_DURATION},
+    parse_scene::transform_from_frame,
+};
+
+/// Collects the keyframes of every animated transform node in the file into a single [`AnimationClip`],
+/// labeled `animation`, which is played by an [`AnimationGraph`] labeled `animation-graph`.
+#[cfg(feature = "render")]
+pub(super) struct TransformAnimations {
+    keyframes: HashMap<AnimationTargetId, Vec<(f32, Transform)>>,
+    graph: AnimationGraph,
+    graph_handle: Handle<AnimationGraph>,
+    clip_node: AnimationNodeIndex,
+}
+
+#[cfg(feature = "render")]
+impl TransformAnimations {
+    pub(super) fn new(context: &mut LoadContext) -> Self {
+        let (graph, clip_node) = AnimationGraph::from_clip(context.get_label_handle("animation"));
+        Self {
+            keyframes: HashMap::new(),
+            graph,
+            graph_handle: context.get_label_handle("animation-graph"),
+            clip_node,
+        }
+    }
+
+    /// If the transform node has more than one keyframe, records its keyframes and marks the entity as an [`AnimationTarget`] of `player`
+    pub(super) fn add_node(
+        &mut self,
+        entity: &mut EntityWorldMut,
+        graph: &[SceneNode],
+        scene_node: &SceneNode,
+        node_name: Option<&String>,
+        player: Entity,
+        scene_scale: f32,
+    ) {
+        let SceneNode::Transform { frames, .. } = scene_node else {
+            return;
+        };
+        if frames.len() < 2 {
+            return;
+        }
+        // Unnamed nodes are identified by their index in the scene graph
+        let target_name = match node_name {
+            Some(name) => name.clone(),
+            None => {
+                let index = graph
+                    .iter()
+                    .position(|node| std::ptr::eq(node, scene_node))
+                    .unwrap_or_default();
+                format!("node-{}", index)
+            }
+        };
+        let id = AnimationTargetId::from_name(&Name::new(target_name));
+        self.keyframes
+            .entry(id)
+            .or_insert_with(|| keyframes_from_frames(frames, scene_scale));
+        entity.insert(AnimationTarget { id, player });
+    }
+
+    /// Adds an [`AnimationPlayer`] to the root entity of a scene if any of its descendants are animated. The animation is
+    /// started once the scene is spawned, as an [`AnimationPlayer`] that is already playing can't be cloned into the world
+    /// by the scene spawner.
+    pub(super) fn add_player(&self, world: &mut World, root: Entity) {
+        let is_animated = world
+            .query::<&AnimationTarget>()
+            .iter(world)
+            .any(|target| target.player == root);
+        if !is_animated {
+            return;
+        }
+        world.entity_mut(root).insert((
+            AnimationPlayer::default(),
+            AnimationGraphHandle(self.graph_handle.clone()),
+            VoxelTransformAnimation {
+                node: self.clip_node,
+            },
+        ));
+    }
+
+    /// Adds the animation clip and graph as labeled assets, if the file has any animated transforms
+    pub(super) fn add_labeled_assets(self, context: &mut LoadContext) {
+        if self.keyframes.is_empty() {
+            return;
+        }
+        let mut clip = AnimationClip::default();
+        for (id, keyframes) in self.keyframes {
+            let translations = keyframes
+                .iter()
+                .map(|(time, transform)| (*time, transform.translation));
+            // Magica Voxel's rotations are axis-aligned flips and turns, so they are not interpolated
+            let rotations = keyframes
+                .iter()
+                .map(|(time, transform)| (*time, transform.rotation));
+            let scales = keyframes
+                .iter()
+                .map(|(time, transform)| (*time, transform.scale));
+            match (
+                AnimatableKeyframeCurve::new(translations),
+                SteppedKeyframeCurve::new(rotations),
+                SteppedKeyframeCurve::new(scales),
+            ) {
+                (Ok(translations), Ok(rotations), Ok(scales)) => {
+                    clip.add_curve_to_target(
+                        id,
+                        AnimatableCurve::new(animated_field!(Transform::translation), translations),
+                    );
+                    clip.add_curve_to_target(
+                        id,
+                        AnimatableCurve::new(animated_field!(Transform::rotation), rotations),
+                    );
+                    clip.add_curve_to_target(
+                        id,
+                        AnimatableCurve::new(animated_field!(Transform::scale), scales),
+                    );
+                }
+                _ => warn!("Transform animation has fewer than 2 distinct keyframes"),
+            }
+        }
+        context.add_labeled_asset("animation".to_string(), clip);
+        context.add_labeled_asset("animation-graph".to_string(), self.graph);
+    }
+}
+
+/// Transform animations need `bevy_animation`, which depends on `bevy_render`, so they are skipped without the `render` feature
+#[cfg(not(feature = "render"))]
+pub(super) struct TransformAnimations;
+
+#[cfg(not(feature = "render"))]
+impl TransformAnimations {
+    pub(super) fn new(_context: &mut LoadContext) -> Self {
+        Self
+    }
+
+    pub(super) fn add_node(
+        &mut self,
+        _entity: &mut EntityWorldMut,
+        _graph: &[SceneNode],
+        _scene_node: &SceneNode,
+        _node_name: Option<&String>,
+        _player: Entity,
+        _scene_scale: f32,
+    ) {
+    }
+
+    pub(super) fn add_player(&self, _world: &mut World, _root: Entity) {}
+
+    pub(super) fn add_labeled_assets(self, _context: &mut LoadContext) {}
+}
+
+/// Keyframes are sorted by their `_f` frame index, with each frame lasting [`DEFAULT_FRAME_DURATION`] as for model animations.
+#[cfg(feature = "render")]
+fn keyframes_from_frames(frames: &[Frame], scene_scale: f32) -> Vec<(f32, Transform)> {
+    let mut keyframes: Vec<(f32, Transform)> = frames
+        .iter()
+        .map(|frame| {
+            let time =
+                frame.frame_index().unwrap_or(0) as f32 * DEFAULT_FRAME_DURATION.as_secs_f32();
+            let transform = Transform::from_matrix(transform_from_frame(frame, scene_scale));
+            (time, transform)
+        })
+        .collect();
+    keyframes.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    keyframes
+}
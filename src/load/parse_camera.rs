@@ -0,0 +1,98 @@
+use bevy::{
+    core::Name,
+    core_pipeline::core_3d::Camera3d,
+    ecs::{entity::Entity, world::World},
+    math::{Quat, Vec3},
+    prelude::{Camera, OrthographicProjection, PerspectiveProjection, Projection, Transform},
+    render::camera::ScalingMode,
+};
+use dot_vox::Dict;
+
+use super::{
+    chunks::{dict_float, dict_floats, find_chunks, parse_dict, read_u32},
+    parse_scene::convert_axes,
+};
+
+/// A camera saved in one of the `rCAM` chunks of a `.vox` file. Magica Voxel saves 10 camera slots.
+pub(super) struct VoxCamera {
+    pub(super) id: u32,
+    attributes: Dict,
+}
+
+pub(super) fn parse_cameras(bytes: &[u8]) -> Vec<VoxCamera> {
+    find_chunks(bytes, b"rCAM")
+        .into_iter()
+        .filter_map(|content| {
+            let id = read_u32(content, 0)?;
+            let attributes = parse_dict(&content[4..])?;
+            Some(VoxCamera { id, attributes })
+        })
+        .collect()
+}
+
+impl VoxCamera {
+    /// Spawns the camera into `world`. Only one camera should be active at a time.
+    pub(super) fn spawn(&self, world: &mut World, scene_scale: f32, is_active: bool) -> Entity {
+        world
+            .spawn((
+                Name::new(format!("camera-{}", self.id)),
+                Camera3d::default(),
+                Camera {
+                    is_active,
+                    ..Default::default()
+                },
+                self.projection(scene_scale),
+                self.transform(scene_scale),
+            ))
+            .id()
+    }
+
+    /// Magica Voxel cameras orbit the `_focus` point at a distance of `_radius`, with `_angle` holding the pitch, yaw and roll
+    /// in degrees. With all angles at zero the camera looks along Magica Voxel's +Y axis (into the front of the scene).
+    /// The result is converted from Magica Voxel's left-handed Z-up space in the same way as the scene graph's transforms.
+    pub(super) fn transform(&self, scene_scale: f32) -> Transform {
+        let focus = self.vec3("_focus").unwrap_or(Vec3::ZERO);
+        let angle = self.vec3("_angle").unwrap_or(Vec3::ZERO);
+        let radius = self.float("_radius").unwrap_or(0.0);
+        let rotation = Quat::from_rotation_z(angle.y.to_radians())
+            * Quat::from_rotation_x(-angle.x.to_radians())
+            * Quat::from_rotation_y(angle.z.to_radians());
+        let forward = convert_axes(rotation * Vec3::Y);
+        let up = convert_axes(rotation * Vec3::Z);
+        let focus = convert_axes(focus) * scene_scale;
+        Transform::from_translation(focus - forward * radius * scene_scale).looking_to(forward, up)
+    }
+
+    /// `_mode` is one of `pers`, `free`, `pano`, `orth` or `iso`. Orthographic and isometric cameras use an orthographic
+    /// projection, with `_frustum` (the tangent of half the field of view) giving the height of the view at the focus point.
+    /// Every other mode uses a perspective projection with the vertical `_fov` in degrees.
+    pub(super) fn projection(&self, scene_scale: f32) -> Projection {
+        match self.attributes.get("_mode").map(String::as_str) {
+            Some("orth") | Some("iso") => {
+                let frustum = self.float("_frustum").unwrap_or(0.414214);
+                let radius = self.float("_radius").unwrap_or(0.0);
+                Projection::Orthographic(OrthographicProjection {
+                    scaling_mode: ScalingMode::FixedVertical {
+                        viewport_height: 2.0 * frustum * radius * scene_scale,
+                    },
+                    ..OrthographicProjection::default_3d()
+                })
+            }
+            _ => Projection::Perspective(PerspectiveProjection {
+                fov: self.float("_fov").unwrap_or(45.0).to_radians(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn float(&self, key: &str) -> Option<f32> {
+        dict_float(&self.attributes, key)
+    }
+
+    fn vec3(&self, key: &str) -> Option<Vec3> {
+        match dict_floats(&self.attributes, key)?[..] {
+            [x, y, z] => Some(Vec3::new(x, y, z)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    log::info,
+    math::IVec3,
+    scene::Scene,
+};
+
+use super::{
+    import::{palette_from_colors, read_file, ByteReader, ColorQuantizer, VoxFileBuilder},
+    load_voxel_scene, VoxLoaderError, VoxLoaderSettings,
+};
+
+/// The length of each side of the blocks that Goxel stores voxels in
+const BLOCK_SIZE: i32 = 16;
+
+/// The furthest that a block can be from the origin along each axis, so that the positions and sizes of the models
+/// built from the blocks can't overflow
+const MAX_BLOCK_POSITION: i32 = 1 << 20;
+
+/// An asset loader capable of loading the layers of Goxel `.gox` files as [`bevy::scene::Scene`]s.
+///
+/// Each layer is loaded as a model in a [`crate::VoxelLayer`] of the same name, and can be loaded on its own by appending
+/// `#{name}` to the asset loading path. The true-color voxels are reduced to a palette of at most 255 colors.
+pub(crate) struct GoxelSceneLoader {
+    pub(crate) global_settings: Option<VoxLoaderSettings>,
+}
+
+impl AssetLoader for GoxelSceneLoader {
+    type Asset = Scene;
+    type Settings = VoxLoaderSettings;
+    type Error = VoxLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let bytes = read_file(reader).await?;
+        info!("Loading {}", load_context.asset_path());
+        let settings = self.global_settings.clone().unwrap_or(settings.clone());
+        let layers = parse_goxel(&bytes)?;
+
+        let mut quantizer = ColorQuantizer::default();
+        for layer in layers.iter() {
+            for (_, color) in layer.voxels.iter() {
+                quantizer.add(*color);
+            }
+        }
+        let (colors, indices) = quantizer.palette();
+        let mut file = VoxFileBuilder::new();
+        for layer in layers {
+            let Some((minimum, maximum)) = layer
+                .voxels
+                .iter()
+                .map(|(position, _)| (*position, *position))
+                .reduce(|(minimum, maximum), (position, _)| {
+                    (minimum.min(position), maximum.max(position))
+                })
+            else {
+                // layers without voxels have nothing to show
+                continue;
+            };
+            let name = layer.name.as_deref();
+            let layer_id = file.add_layer(name, !layer.is_visible);
+            file.add_model(
+                name,
+                minimum,
+                (maximum - minimum + IVec3::ONE).as_uvec3(),
+                layer
+                    .voxels
+                    .iter()
+                    .map(|(position, color)| ((*position - minimum).as_uvec3(), indices[color])),
+                layer_id,
+            );
+        }
+        if file.models.is_empty() {
+            return Err(VoxLoaderError::NoModels);
+        }
+        Ok(load_voxel_scene(
+            load_context,
+            &settings,
+            file.models,
+            &file.graph,
+            &file.layers,
+            palette_from_colors(&colors, &settings),
+        ))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["gox"]
+    }
+}
+
+/// A layer of voxels, converted to bevy's right-handed Y-up space
+struct GoxelLayer {
+    name: Option<String>,
+    is_visible: bool,
+    voxels: Vec<(IVec3, [u8; 3])>,
+}
+
+/// Reads the layers of a Goxel file, which is made of chunks holding either a block of voxels as a PNG image, or a
+/// layer listing the positions of its blocks followed by a dictionary of its properties
+fn parse_goxel(bytes: &[u8]) -> Result<Vec<GoxelLayer>, VoxLoaderError> {
+    let mut reader = ByteReader::new(bytes, "Goxel");
+    if &reader.array::<4>()? != b"GOX " {
+        return Err(reader.error("missing `GOX ` header"));
+    }
+    let version = reader.i32()?;
+    let mut blocks: Vec<Vec<u8>> = Vec::new();
+    let mut layers: Vec<GoxelLayer> = Vec::new();
+    while !reader.is_at_end() {
+        let kind = reader.array::<4>()?;
+        let length = read_length(&mut reader)?;
+        let end = reader.offset() + length;
+        match &kind {
+            b"BL16" => blocks.push(read_block(&mut reader, length)?),
+            b"LAYR" => layers.push(read_layer(&mut reader, end, &blocks, version)?),
+            _ => (),
+        }
+        reader.seek(end);
+        let _crc = reader.u32()?;
+    }
+    Ok(layers)
+}
+
+/// Decodes a block of 16x16x16 voxels, which are stored in the pixels of a 64x64 RGBA image in x, y, z order
+fn read_block(reader: &mut ByteReader, length: usize) -> Result<Vec<u8>, VoxLoaderError> {
+    let offset = reader.offset();
+    let image = image::load_from_memory_with_format(reader.bytes(length)?, image::ImageFormat::Png)
+        .map_err(|e| VoxLoaderError::InvalidFile {
+            format: "Goxel",
+            offset,
+            message: e.to_string(),
+        })?
+        .into_rgba8();
+    if image.width() * image.height() != (BLOCK_SIZE * BLOCK_SIZE * BLOCK_SIZE) as u32 {
+        return Err(VoxLoaderError::InvalidFile {
+            format: "Goxel",
+            offset,
+            message: "blocks must be 64x64 images".to_string(),
+        });
+    }
+    Ok(image.into_raw())
+}
+
+fn read_layer(
+    reader: &mut ByteReader,
+    end: usize,
+    blocks: &[Vec<u8>],
+    version: i32,
+) -> Result<GoxelLayer, VoxLoaderError> {
+    let block_count = reader.i32()?;
+    let mut voxels = Vec::new();
+    for _ in 0..block_count {
+        let index = reader.i32()?;
+        let mut position = IVec3::new(reader.i32()?, reader.i32()?, reader.i32()?);
+        let _ = reader.i32()?;
+        if position.min_element() < -MAX_BLOCK_POSITION
+            || position.max_element() > MAX_BLOCK_POSITION
+        {
+            return Err(reader.error(format!("block at {position} is out of range")));
+        }
+        if version == 1 {
+            // the first version of the format placed blocks by their centers
+            position -= IVec3::splat(BLOCK_SIZE / 2);
+        }
+        let block = usize::try_from(index)
+            .ok()
+            .and_then(|index| blocks.get(index))
+            .ok_or_else(|| reader.error(format!("layer references missing block {index}")))?;
+        for (index, pixel) in block.chunks_exact(4).enumerate() {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let index = index as i32;
+            let offset = IVec3::new(
+                index % BLOCK_SIZE,
+                (index / BLOCK_SIZE) % BLOCK_SIZE,
+                index / (BLOCK_SIZE * BLOCK_SIZE),
+            );
+            let point = position + offset;
+            // Goxel is right-handed and Z-up
+            voxels.push((
+                IVec3::new(point.x, point.z, -point.y),
+                [pixel[0], pixel[1], pixel[2]],
+            ));
+        }
+    }
+    let mut layer = GoxelLayer {
+        name: None,
+        is_visible: true,
+        voxels,
+    };
+    while reader.offset() < end {
+        let key_length = read_length(reader)?;
+        if key_length == 0 {
+            break;
+        }
+        let key = reader.bytes(key_length)?;
+        let value_length = read_length(reader)?;
+        let value = reader.bytes(value_length)?;
+        match key {
+            b"name" => {
+                let name = String::from_utf8_lossy(value);
+                let name = name.trim_end_matches('\0');
+                layer.name = (!name.is_empty()).then(|| name.to_string());
+            }
+            b"visible" => layer.is_visible = value.first().is_none_or(|visible| *visible != 0),
+            _ => (),
+        }
+    }
+    Ok(layer)
+}
+
+fn read_length(reader: &mut ByteReader) -> Result<usize, VoxLoaderError> {
+    let length = reader.i32()?;
+    usize::try_from(length).map_err(|_| reader.error(format!("invalid length {length}")))
+}
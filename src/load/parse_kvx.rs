@@ -0,0 +1,161 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    log::info,
+    math::{IVec3, UVec3, Vec3},
+    scene::Scene,
+};
+
+use super::{
+    import::{palette_from_colors, read_file, ByteReader, VoxFileBuilder},
+    load_voxel_scene, VoxLoaderError, VoxLoaderSettings,
+};
+
+/// The length of the palette at the end of the file, as 256 colors of red, green and blue values from 0 to 63
+const PALETTE_LENGTH: usize = 768;
+
+/// An asset loader capable of loading Build engine `.kvx` voxel sprites as [`bevy::scene::Scene`]s.
+///
+/// Only the full-resolution model is loaded, and not the lower mip levels that may follow it. The model is placed with
+/// its pivot at the origin. The last color of the palette is replaced with the closest of the others, as its index is
+/// used for [`crate::Voxel::EMPTY`].
+pub(crate) struct KvxSceneLoader {
+    pub(crate) global_settings: Option<VoxLoaderSettings>,
+}
+
+impl AssetLoader for KvxSceneLoader {
+    type Asset = Scene;
+    type Settings = VoxLoaderSettings;
+    type Error = VoxLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let bytes = read_file(reader).await?;
+        info!("Loading {}", load_context.asset_path());
+        let settings = self.global_settings.clone().unwrap_or(settings.clone());
+        let sprite = parse_kvx(&bytes)?;
+        if sprite.voxels.is_empty() {
+            return Err(VoxLoaderError::NoModels);
+        }
+
+        let (colors, last) = sprite.palette.split_at(u8::MAX as usize);
+        let closest = closest_color(colors, last[0]);
+        let mut file = VoxFileBuilder::new();
+        file.add_model(
+            None,
+            sprite.minimum,
+            sprite.size,
+            sprite.voxels.into_iter().map(|(position, index)| {
+                (position, if index == u8::MAX { closest } else { index })
+            }),
+            0,
+        );
+        Ok(load_voxel_scene(
+            load_context,
+            &settings,
+            file.models,
+            &file.graph,
+            &file.layers,
+            palette_from_colors(colors, &settings),
+        ))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["kvx"]
+    }
+}
+
+/// A voxel sprite, converted to bevy's right-handed Y-up space
+struct KvxSprite {
+    minimum: IVec3,
+    size: UVec3,
+    voxels: Vec<(UVec3, u8)>,
+    palette: Vec<[u8; 4]>,
+}
+
+/// Reads the first model of a KVX file. Each model has a header giving its size and pivot, followed by the offsets of
+/// its columns of voxels, each of which holds a list of slabs of visible voxels.
+fn parse_kvx(bytes: &[u8]) -> Result<KvxSprite, VoxLoaderError> {
+    let mut reader = ByteReader::new(bytes, "KVX");
+    if bytes.len() < PALETTE_LENGTH {
+        return Err(reader.error("file is too short to contain a palette"));
+    }
+    let palette = bytes[bytes.len() - PALETTE_LENGTH..]
+        .chunks_exact(3)
+        .map(|color| {
+            // scales 6-bit values to 8 bits, so that 63 becomes 255
+            let [red, green, blue] = [color[0], color[1], color[2]].map(|value| {
+                let value = value.min(63);
+                (value << 2) | (value >> 4)
+            });
+            [red, green, blue, u8::MAX]
+        })
+        .collect();
+
+    let _length = reader.i32()?;
+    let mut read_size = || {
+        let size = reader.i32()?;
+        u32::try_from(size).map_err(|_| reader.error(format!("invalid size {size}")))
+    };
+    let size = UVec3::new(read_size()?, read_size()?, read_size()?);
+    // pivots are fixed-point, with 8 fractional bits
+    let pivot = Vec3::new(
+        reader.i32()? as f32,
+        reader.i32()? as f32,
+        reader.i32()? as f32,
+    ) / 256.0;
+    let base = reader.offset();
+    let x_offsets = (0..=size.x)
+        .map(|_| reader.u32())
+        .collect::<Result<Vec<_>, _>>()?;
+    let xy_offsets = (0..size.x)
+        .map(|_| (0..=size.y).map(|_| reader.u16()).collect())
+        .collect::<Result<Vec<Vec<_>>, _>>()?;
+
+    let mut voxels = Vec::new();
+    for x in 0..size.x {
+        for y in 0..size.y {
+            let column = base + x_offsets[x as usize] as usize;
+            let start = column + xy_offsets[x as usize][y as usize] as usize;
+            let end = column + xy_offsets[x as usize][y as usize + 1] as usize;
+            reader.seek(start);
+            while reader.offset() < end {
+                let top = reader.u8()? as u32;
+                let length = reader.u8()? as usize;
+                let _culled_faces = reader.u8()?;
+                for (z, index) in (top..).zip(reader.bytes(length)?) {
+                    if z >= size.z {
+                        return Err(reader.error("slab extends past the bottom of the model"));
+                    }
+                    // the z axis of KVX points down
+                    voxels.push((UVec3::new(x, size.z - 1 - z, y), *index));
+                }
+            }
+        }
+    }
+    let pivot = Vec3::new(pivot.x, size.z as f32 - pivot.z, pivot.y);
+    Ok(KvxSprite {
+        minimum: (-pivot).round().as_ivec3(),
+        size: UVec3::new(size.x, size.z, size.y),
+        voxels,
+        palette,
+    })
+}
+
+/// The index of the color in `colors` that is closest to `color`
+fn closest_color(colors: &[[u8; 4]], color: [u8; 4]) -> u8 {
+    let distance = |other: &[u8; 4]| -> i32 {
+        (0..3)
+            .map(|channel| (other[channel] as i32 - color[channel] as i32).pow(2))
+            .sum()
+    };
+    colors
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, other)| distance(other))
+        .map(|(index, _)| index as u8)
+        .unwrap_or_default()
+}
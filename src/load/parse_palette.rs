@@ -0,0 +1,236 @@
+use anyhow::anyhow;
+use bevy::asset::{io::Reader, AssetLoader, LoadContext};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::model::{color_from_rgba8, VoxelPalette};
+
+/// The most colors a palette can hold. The last is never used, as [`crate::Voxel::EMPTY`] has no color.
+const MAX_COLORS: usize = 256;
+
+/// An asset loader for palettes kept outside of Magica Voxel, which loads them as [`VoxelPalette`]s.
+///
+/// Paint.NET, hex and image palettes are registered with the compound `palette.txt`, `palette.hex` and `palette.png`
+/// extensions, so that the loader doesn't claim every text file or image that is loaded without a type.
+pub(crate) struct VoxelPaletteLoader;
+
+/// Settings for loading a [`VoxelPalette`] from a palette file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PaletteLoaderSettings {
+    /// Whether the colors are in sRGB space, like those of Magica Voxel, or linear space. Defaults to `true`.
+    pub uses_srgb: bool,
+}
+
+impl Default for PaletteLoaderSettings {
+    fn default() -> Self {
+        Self { uses_srgb: true }
+    }
+}
+
+/// An error that occurred while loading a palette file
+#[derive(Error, Debug)]
+pub enum PaletteLoaderError {
+    /// The file could not be read
+    #[error(transparent)]
+    InvalidAsset(#[from] anyhow::Error),
+    /// A line of the file is not valid for its format
+    #[error("failed to parse line {line}: {message}")]
+    Parse {
+        /// The line that could not be parsed, starting from 1
+        line: usize,
+        /// Description of the failure
+        message: String,
+    },
+    /// The file holds more colors than fit in a palette
+    #[error("the file contains {0} colors, but a palette holds at most 256")]
+    TooManyColors(usize),
+    /// The file does not contain any colors
+    #[error("the file does not contain any colors")]
+    NoColors,
+}
+
+impl AssetLoader for VoxelPaletteLoader {
+    type Asset = VoxelPalette;
+    type Settings = PaletteLoaderSettings;
+    type Error = PaletteLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let extension = load_context
+            .path()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_default();
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(|e| PaletteLoaderError::InvalidAsset(anyhow!(e)))?;
+        let text = || String::from_utf8_lossy(&bytes);
+        let colors = match extension.as_str() {
+            "gpl" => parse_gpl(&text())?,
+            "txt" => parse_paint_net(&text())?,
+            "hex" => parse_hex(&text())?,
+            "pal" => parse_jasc(&text())?,
+            #[cfg(feature = "palette_images")]
+            "png" => read_png(&bytes)?,
+            _ => {
+                return Err(PaletteLoaderError::InvalidAsset(anyhow!(
+                    "unsupported palette extension {extension:?}"
+                )))
+            }
+        };
+        if colors.is_empty() {
+            return Err(PaletteLoaderError::NoColors);
+        }
+        if colors.len() > MAX_COLORS {
+            return Err(PaletteLoaderError::TooManyColors(colors.len()));
+        }
+        Ok(VoxelPalette::from_colors(
+            colors
+                .into_iter()
+                .map(|color| color_from_rgba8(color, settings.uses_srgb))
+                .collect(),
+            settings.uses_srgb,
+        ))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        #[cfg(feature = "palette_images")]
+        return &["gpl", "palette.txt", "palette.hex", "pal", "palette.png"];
+        #[cfg(not(feature = "palette_images"))]
+        return &["gpl", "palette.txt", "palette.hex", "pal"];
+    }
+}
+
+/// Reads the pixels of an image, such as a 256x1 or 16x16 palette exported from Magica Voxel, row by row
+#[cfg(feature = "palette_images")]
+fn read_png(bytes: &[u8]) -> Result<Vec<[u8; 4]>, PaletteLoaderError> {
+    let image = image::load_from_memory_with_format(bytes, image::ImageFormat::Png)
+        .map_err(|e| PaletteLoaderError::InvalidAsset(anyhow!(e)))?
+        .into_rgba8();
+    let pixels = image.width() as usize * image.height() as usize;
+    if pixels > MAX_COLORS {
+        return Err(PaletteLoaderError::TooManyColors(pixels));
+    }
+    Ok(image.pixels().map(|pixel| pixel.0).collect())
+}
+
+/// Parses a GIMP palette, which starts with a `GIMP Palette` header, followed by one color per line as decimal red,
+/// green and blue values and an optional name.
+fn parse_gpl(text: &str) -> Result<Vec<[u8; 4]>, PaletteLoaderError> {
+    let mut lines = numbered_lines(text);
+    match lines.next() {
+        Some((_, "GIMP Palette")) => (),
+        Some((line, _)) => return Err(parse_error(line, "expected a `GIMP Palette` header")),
+        None => return Err(PaletteLoaderError::NoColors),
+    }
+    lines
+        .filter(|(_, content)| {
+            !content.starts_with('#')
+                && !content.starts_with("Name:")
+                && !content.starts_with("Columns:")
+        })
+        .map(|(line, content)| parse_decimal_rgb(line, content))
+        .collect()
+}
+
+/// Parses a Paint.NET palette, which holds one color per line as 8 hexadecimal digits in the order alpha, red, green,
+/// blue, with comments starting with `;`.
+fn parse_paint_net(text: &str) -> Result<Vec<[u8; 4]>, PaletteLoaderError> {
+    numbered_lines(text)
+        .filter(|(_, content)| !content.starts_with(';'))
+        .map(|(line, content)| {
+            let [alpha, red, green, blue] = parse_hex_bytes::<4>(line, content)?;
+            Ok([red, green, blue, alpha])
+        })
+        .collect()
+}
+
+/// Parses a list of colors with one color per line as 6 hexadecimal digits, optionally preceded by `#`.
+fn parse_hex(text: &str) -> Result<Vec<[u8; 4]>, PaletteLoaderError> {
+    numbered_lines(text)
+        .map(|(line, content)| {
+            let [red, green, blue] = parse_hex_bytes::<3>(line, content.trim_start_matches('#'))?;
+            Ok([red, green, blue, u8::MAX])
+        })
+        .collect()
+}
+
+/// Parses a JASC palette, which starts with a `JASC-PAL` header, a version and the number of colors, followed by one
+/// color per line as decimal red, green and blue values.
+fn parse_jasc(text: &str) -> Result<Vec<[u8; 4]>, PaletteLoaderError> {
+    let mut lines = numbered_lines(text);
+    let (Some((header_line, header)), Some(_version), Some((count_line, count))) =
+        (lines.next(), lines.next(), lines.next())
+    else {
+        return Err(PaletteLoaderError::NoColors);
+    };
+    if header != "JASC-PAL" {
+        return Err(parse_error(header_line, "expected a `JASC-PAL` header"));
+    }
+    let count: usize = count
+        .parse()
+        .map_err(|_| parse_error(count_line, "expected the number of colors"))?;
+    let colors = lines
+        .map(|(line, content)| parse_decimal_rgb(line, content))
+        .collect::<Result<Vec<_>, _>>()?;
+    if colors.len() != count {
+        return Err(parse_error(
+            count_line,
+            &format!(
+                "declares {count} colors, but the file contains {}",
+                colors.len()
+            ),
+        ));
+    }
+    Ok(colors)
+}
+
+/// The trimmed, non-empty lines of `text`, along with their line numbers starting from 1
+fn numbered_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(index, content)| (index + 1, content.trim()))
+        .filter(|(_, content)| !content.is_empty())
+}
+
+/// Parses the red, green and blue values at the start of a line, separated by whitespace
+fn parse_decimal_rgb(line: usize, content: &str) -> Result<[u8; 4], PaletteLoaderError> {
+    let mut values = content.split_whitespace().map(str::parse::<u8>);
+    let mut channel = || {
+        values
+            .next()
+            .and_then(Result::ok)
+            .ok_or_else(|| parse_error(line, "expected red, green and blue values from 0 to 255"))
+    };
+    Ok([channel()?, channel()?, channel()?, u8::MAX])
+}
+
+/// Parses exactly `N` bytes written as pairs of hexadecimal digits
+fn parse_hex_bytes<const N: usize>(
+    line: usize,
+    content: &str,
+) -> Result<[u8; N], PaletteLoaderError> {
+    let error = || parse_error(line, &format!("expected {} hexadecimal digits", N * 2));
+    if content.len() != N * 2 || !content.is_ascii() {
+        return Err(error());
+    }
+    let mut bytes = [0; N];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&content[index * 2..index * 2 + 2], 16).map_err(|_| error())?;
+    }
+    Ok(bytes)
+}
+
+fn parse_error(line: usize, message: &str) -> PaletteLoaderError {
+    PaletteLoaderError::Parse {
+        line,
+        message: message.to_string(),
+    }
+}
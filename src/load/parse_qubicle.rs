@@ -0,0 +1,190 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    log::info,
+    math::{IVec3, UVec3},
+    scene::Scene,
+};
+
+use super::{
+    import::{palette_from_colors, read_file, ByteReader, ColorQuantizer, VoxFileBuilder},
+    load_voxel_scene, VoxLoaderError, VoxLoaderSettings,
+};
+
+/// Marks a run of voxels with the same color in a compressed matrix
+const RUN_FLAG: u32 = 2;
+/// Marks the end of a slice of a compressed matrix
+const NEXT_SLICE_FLAG: u32 = 6;
+/// The most voxels that the matrices of a file may hold between them, so that malformed files can't claim billions of
+/// voxels
+const MAX_VOLUME: u32 = 128 * 128 * 128;
+
+/// An asset loader capable of loading the matrices in Qubicle `.qb` files as [`bevy::scene::Scene`]s.
+///
+/// Each matrix is loaded as a model, and the true-color voxels are reduced to a palette of at most 255 colors. Named
+/// matrices can be loaded on their own by appending `#{name}` to the asset loading path, as with `.vox` files.
+pub(crate) struct QubicleSceneLoader {
+    pub(crate) global_settings: Option<VoxLoaderSettings>,
+}
+
+impl AssetLoader for QubicleSceneLoader {
+    type Asset = Scene;
+    type Settings = VoxLoaderSettings;
+    type Error = VoxLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let bytes = read_file(reader).await?;
+        info!("Loading {}", load_context.asset_path());
+        let settings = self.global_settings.clone().unwrap_or(settings.clone());
+        let matrices = parse_qubicle(&bytes)?;
+        if matrices.is_empty() {
+            return Err(VoxLoaderError::NoModels);
+        }
+
+        let mut quantizer = ColorQuantizer::default();
+        for matrix in matrices.iter() {
+            for (_, color) in matrix.voxels.iter() {
+                quantizer.add(*color);
+            }
+        }
+        let (colors, indices) = quantizer.palette();
+        let mut file = VoxFileBuilder::new();
+        for matrix in matrices {
+            file.add_model(
+                (!matrix.name.is_empty()).then_some(matrix.name.as_str()),
+                matrix.minimum,
+                matrix.size,
+                matrix
+                    .voxels
+                    .iter()
+                    .map(|(position, color)| (*position, indices[color])),
+                0,
+            );
+        }
+        Ok(load_voxel_scene(
+            load_context,
+            &settings,
+            file.models,
+            &file.graph,
+            &file.layers,
+            palette_from_colors(&colors, &settings),
+        ))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["qb"]
+    }
+}
+
+/// A matrix of voxels, converted to bevy's right-handed Y-up space
+struct QubicleMatrix {
+    name: String,
+    minimum: IVec3,
+    size: UVec3,
+    voxels: Vec<(UVec3, [u8; 3])>,
+}
+
+/// Reads the matrices of a Qubicle binary file
+fn parse_qubicle(bytes: &[u8]) -> Result<Vec<QubicleMatrix>, VoxLoaderError> {
+    let mut reader = ByteReader::new(bytes, "Qubicle");
+    let _version = reader.u32()?;
+    let is_bgra = match reader.u32()? {
+        0 => false,
+        1 => true,
+        _ => return Err(reader.error("unknown color format")),
+    };
+    let is_right_handed = reader.u32()? == 1;
+    let is_compressed = reader.u32()? == 1;
+    // the visibility mask only changes the meaning of non-zero alpha values, which are all treated as filled
+    let _visibility_mask_encoded = reader.u32()?;
+    let matrix_count = reader.u32()?;
+    let mut remaining_volume = MAX_VOLUME;
+    (0..matrix_count)
+        .map(|_| {
+            let name_length = reader.u8()? as usize;
+            let name = String::from_utf8_lossy(reader.bytes(name_length)?).into_owned();
+            let size = UVec3::new(reader.u32()?, reader.u32()?, reader.u32()?);
+            let position = IVec3::new(reader.i32()?, reader.i32()?, reader.i32()?);
+            let volume = size
+                .x
+                .checked_mul(size.y)
+                .and_then(|area| area.checked_mul(size.z))
+                .filter(|volume| *volume <= remaining_volume);
+            let Some(volume) = volume else {
+                return Err(reader.error(format!("matrix of size {size} is too large")));
+            };
+            remaining_volume -= volume;
+            // both corners must fit in an `i32`, whichever way the z axis is flipped
+            let far_corner = position.as_i64vec3() + size.as_i64vec3();
+            if position.min_element() == i32::MIN || far_corner.max_element() > i32::MAX as i64 {
+                return Err(reader.error(format!("matrix at {position} is out of range")));
+            }
+            let mut voxels = Vec::new();
+            let mut set_voxel = |index: u32, z: u32, color: u32| {
+                let [first, green, third, alpha] = color.to_le_bytes();
+                if alpha == 0 {
+                    return;
+                }
+                let color = if is_bgra {
+                    [third, green, first]
+                } else {
+                    [first, green, third]
+                };
+                let (x, y) = (index % size.x, index / size.x);
+                // left-handed matrices have their z axis pointing away from the viewer
+                let z = if is_right_handed { z } else { size.z - 1 - z };
+                voxels.push((UVec3::new(x, y, z), color));
+            };
+            let slice_length = size.x * size.y;
+            if size.x == 0 || size.y == 0 || size.z == 0 {
+                // nothing to read
+            } else if is_compressed {
+                for z in 0..size.z {
+                    let mut index: u32 = 0;
+                    loop {
+                        let data = reader.u32()?;
+                        if data == NEXT_SLICE_FLAG {
+                            break;
+                        }
+                        let (count, color) = if data == RUN_FLAG {
+                            (reader.u32()?, reader.u32()?)
+                        } else {
+                            (1, data)
+                        };
+                        // runs can't decode to more voxels than the slice holds
+                        if index.saturating_add(count) > slice_length {
+                            return Err(reader.error("run extends past the end of the slice"));
+                        }
+                        for _ in 0..count {
+                            set_voxel(index, z, color);
+                            index += 1;
+                        }
+                    }
+                }
+            } else {
+                let length = slice_length as usize * size.z as usize * 4;
+                let data = reader.bytes(length)?;
+                for (index, color) in data.chunks_exact(4).enumerate() {
+                    let index = index as u32;
+                    let color = u32::from_le_bytes([color[0], color[1], color[2], color[3]]);
+                    set_voxel(index % slice_length, index / slice_length, color);
+                }
+            }
+            let minimum = if is_right_handed {
+                position
+            } else {
+                IVec3::new(position.x, position.y, -(position.z + size.z as i32))
+            };
+            Ok(QubicleMatrix {
+                name,
+                minimum,
+                size,
+                voxels,
+            })
+        })
+        .collect()
+}
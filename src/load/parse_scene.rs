@@ -1,19 +1,27 @@
+#[cfg(feature = "render")]
+use bevy::prelude::Visibility;
 use bevy::{
     asset::{Handle, LoadContext},
     core::Name,
-    log::warn,
+    ecs::entity::Entity,
     math::{Mat3, Mat4, Quat, Vec3},
-    prelude::{
-        BuildChildren, ChildBuild, EntityWorldMut, Transform, Visibility, World, WorldChildBuilder,
-    },
+    prelude::{BuildChildren, ChildBuild, EntityWorldMut, Transform, World, WorldChildBuilder},
     scene::Scene,
     utils::HashSet,
 };
-use dot_vox::{Frame, SceneNode};
+use dot_vox::{Dict, Frame, Rotation, SceneNode, ShapeModel};
 
 use crate::{VoxelLayer, VoxelModel, VoxelModelInstance};
 
-use super::components::{LayerInfo, VoxelAnimationPlayer};
+use super::{
+    components::{LayerInfo, VoxelAnimationPlayer},
+    parse_animation::TransformAnimations,
+    VoxLoaderError, VoxLoaderSettings,
+};
+
+/// The last frame that a model can be keyframed at, so that a malformed `_f` attribute can't make the timeline of a
+/// [`VoxelAnimationPlayer`] take up more memory than is available
+pub(super) const MAX_KEYFRAME: u32 = 65_535;
 
 pub(super) fn find_model_names(
     name_for_model: &mut Vec<Option<String>>,
@@ -21,112 +29,254 @@ pub(super) fn find_model_names(
     scene_node: &SceneNode,
     parent_name: Option<&String>,
 ) {
-    match scene_node {
+    if let SceneNode::Transform {
+        attributes,
+        frames: _,
+        child,
+        layer_id: _,
+    } = scene_node
+    {
+        let (accumulated, node_name) =
+            get_accumulated_and_node_name(parent_name, attributes.get("_name"));
+        match &graph[*child as usize] {
+            SceneNode::Group {
+                attributes: _,
+                children,
+            } => {
+                for grandchild in children {
+                    find_model_names(
+                        name_for_model,
+                        graph,
+                        &graph[*grandchild as usize],
+                        accumulated.as_ref(),
+                    );
+                }
+            }
+            SceneNode::Shape {
+                attributes: _,
+                models,
+            } => {
+                let Some(model) = models.first() else {
+                    return;
+                };
+                let model_id = model.model_id as usize;
+                match (&name_for_model[model_id], node_name) {
+                    (None, Some(name)) | (Some(_), Some(name)) => {
+                        let mut node_name = name.clone();
+                        // disambiguate model name if we have a scene where different models have the same name
+                        let name_root = name;
+                        let mut disambiguator = 0;
+                        let mut names_to_disambiguate = name_for_model.clone();
+                        names_to_disambiguate.remove(model_id);
+                        while names_to_disambiguate.contains(&Some(node_name.clone())) {
+                            node_name = format_args!("{}_{}", name_root, disambiguator).to_string();
+                            disambiguator += 1;
+                        }
+                        name_for_model[model_id] = Some(node_name)
+                    }
+                    (None, None) | (Some(_), None) => (),
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A scene graph for files that don't have one, with a node for each model placed at the origin:
+/// root transform -> group -> (transform -> shape) for each model
+pub(super) fn default_scene_graph(model_count: usize) -> Vec<SceneNode> {
+    let mut graph = vec![
         SceneNode::Transform {
-            attributes,
-            frames: _,
-            child,
-            layer_id: _,
-        } => {
-            let (accumulated, node_name) =
-                get_accumulated_and_node_name(parent_name, attributes.get("_name"));
-            match &graph[*child as usize] {
-                SceneNode::Group {
-                    attributes: _,
-                    children,
-                } => {
-                    for grandchild in children {
-                        find_model_names(
-                            name_for_model,
-                            graph,
-                            &graph[*grandchild as usize],
-                            accumulated.as_ref(),
-                        );
+            attributes: Dict::default(),
+            frames: vec![Frame::default()],
+            child: 1,
+            layer_id: u32::MAX,
+        },
+        SceneNode::Group {
+            attributes: Dict::default(),
+            children: (0..model_count as u32).map(|index| 2 + index * 2).collect(),
+        },
+    ];
+    for model_id in 0..model_count as u32 {
+        graph.push(SceneNode::Transform {
+            attributes: Dict::default(),
+            frames: vec![Frame::default()],
+            child: graph.len() as u32 + 1,
+            layer_id: 0,
+        });
+        graph.push(SceneNode::Shape {
+            attributes: Dict::default(),
+            models: vec![ShapeModel {
+                model_id,
+                attributes: Dict::default(),
+            }],
+        });
+    }
+    graph
+}
+
+/// Checks that every node reachable from the root only references nodes and models that exist, and that there are no cycles.
+/// Problems that the loader can work around are reported according to [`VoxLoaderSettings::strict`].
+pub(super) fn validate_scene_graph(
+    graph: &[SceneNode],
+    model_count: usize,
+    settings: &VoxLoaderSettings,
+) -> Result<(), VoxLoaderError> {
+    let mut validator = SceneValidator {
+        graph,
+        model_count,
+        settings,
+        ancestors: Vec::new(),
+        validated: HashSet::new(),
+        names: HashSet::new(),
+    };
+    validator.validate_node(0, false, None)
+}
+
+struct SceneValidator<'a> {
+    graph: &'a [SceneNode],
+    model_count: usize,
+    settings: &'a VoxLoaderSettings,
+    ancestors: Vec<u32>,
+    validated: HashSet<u32>,
+    names: HashSet<String>,
+}
+
+impl SceneValidator<'_> {
+    fn validate_node(
+        &mut self,
+        index: u32,
+        parent_is_transform: bool,
+        parent_name: Option<&String>,
+    ) -> Result<(), VoxLoaderError> {
+        // nodes can be shared by several parents, so only check them once
+        if !self.validated.insert(index) {
+            return Ok(());
+        }
+        let (children, accumulated) = match &self.graph[index as usize] {
+            SceneNode::Transform {
+                attributes, child, ..
+            } => {
+                if parent_is_transform {
+                    self.settings
+                        .report(VoxLoaderError::NestedTransform { node: index })?;
+                }
+                if let Some(value) = attributes.get("_hidden") {
+                    if value != "0" && value != "1" {
+                        self.settings.report(VoxLoaderError::InvalidBoolean {
+                            node: index,
+                            key: "_hidden".to_string(),
+                            value: value.clone(),
+                        })?;
                     }
                 }
-                SceneNode::Shape {
-                    attributes: _,
-                    models,
-                } => {
-                    let model_id = models[0].model_id as usize;
-                    match (&name_for_model[model_id], node_name) {
-                        (None, Some(name)) | (Some(_), Some(name)) => {
-                            let mut node_name = name.clone();
-                            // disambiguate model name if we have a scene where different models have the same name
-                            let name_root = name;
-                            let mut disambiguator = 0;
-                            let mut names_to_disambiguate = name_for_model.clone();
-                            names_to_disambiguate.remove(model_id);
-                            while names_to_disambiguate.contains(&Some(node_name.clone())) {
-                                node_name =
-                                    format_args!("{}_{}", name_root, disambiguator).to_string();
-                                disambiguator += 1;
-                            }
-                            name_for_model[model_id] = Some(node_name)
-                        }
-                        (None, None) | (Some(_), None) => (),
-                    };
+                let (accumulated, node_name) =
+                    get_accumulated_and_node_name(parent_name, attributes.get("_name"));
+                if let Some(node_name) = node_name {
+                    if !self.names.insert(node_name.clone()) {
+                        self.settings
+                            .report(VoxLoaderError::DuplicateName(node_name))?;
+                    }
                 }
-                _ => {}
+                (vec![*child], accumulated)
             }
+            SceneNode::Group { children, .. } => {
+                if !parent_is_transform {
+                    self.settings
+                        .report(VoxLoaderError::MissingParentTransform { node: index })?;
+                }
+                (children.clone(), parent_name.cloned())
+            }
+            SceneNode::Shape { models, .. } => {
+                if !parent_is_transform {
+                    self.settings
+                        .report(VoxLoaderError::MissingParentTransform { node: index })?;
+                }
+                if let Some(model) = models
+                    .iter()
+                    .find(|model| model.model_id as usize >= self.model_count)
+                {
+                    return Err(VoxLoaderError::ModelIndexOutOfRange {
+                        node: index,
+                        model: model.model_id,
+                        model_count: self.model_count,
+                    });
+                }
+                if let Some(frame) = models
+                    .iter()
+                    .filter_map(ShapeModel::frame_index)
+                    .find(|frame| *frame > MAX_KEYFRAME)
+                {
+                    self.settings
+                        .report(VoxLoaderError::KeyframeOutOfRange { node: index, frame })?;
+                }
+                (vec![], None)
+            }
+        };
+        let is_transform = matches!(self.graph[index as usize], SceneNode::Transform { .. });
+        self.ancestors.push(index);
+        for child in children {
+            if child as usize >= self.graph.len() || self.ancestors.contains(&child) {
+                return Err(VoxLoaderError::InvalidSceneNodeReference { node: index, child });
+            }
+            self.validate_node(child, is_transform, accumulated.as_ref())?;
         }
-        _ => {}
+        self.ancestors.pop();
+        Ok(())
     }
 }
 
+/// The parts of a file that each node of its scene graph is loaded with
+pub(super) struct SceneGraphContext<'a> {
+    pub graph: &'a Vec<SceneNode>,
+    pub model_names: &'a [Option<String>],
+    pub layers: &'a Vec<LayerInfo>,
+    /// The names of the nodes that have already been added as subassets
+    pub subassets: HashSet<String>,
+    pub animations: TransformAnimations,
+    pub scene_scale: f32,
+}
+
 pub(super) fn parse_scene_graph(
     context: &mut LoadContext,
-    graph: &Vec<SceneNode>,
+    scene_graph: &mut SceneGraphContext,
     scene_node: &SceneNode,
     parent_name: Option<&String>,
-    model_names: &mut Vec<Option<String>>,
-    subassets: &mut HashSet<String>,
-    layers: &Vec<LayerInfo>,
-    scene_scale: f32,
 ) -> Scene {
     let mut world = World::default();
-    match scene_node {
-        SceneNode::Transform {
-            attributes,
-            frames: _, // nb for the root node we ignore the transform
-            child,
-            layer_id,
-        } => {
-            let (accumulated, node_name) =
-                get_accumulated_and_node_name(parent_name, attributes.get("_name"));
-            let mut entity = world.spawn_empty();
-            load_xform_child(
-                context,
-                graph,
-                &graph[*child as usize],
-                &mut entity,
-                accumulated.as_ref(),
-                model_names,
-                subassets,
-                layers,
-                scene_scale,
-            );
+    if let SceneNode::Transform {
+        attributes,
+        frames: _, // nb for the root node we ignore the transform
+        child,
+        layer_id,
+    } = scene_node
+    {
+        let (accumulated, node_name) =
+            get_accumulated_and_node_name(parent_name, attributes.get("_name"));
+        let mut entity = world.spawn_empty();
+        let root = entity.id();
+        load_xform_child(
+            context,
+            scene_graph,
+            &scene_graph.graph[*child as usize],
+            &mut entity,
+            accumulated.as_ref(),
+            root,
+        );
 
-            let maybe_layer = layers.get(*layer_id as usize);
-            if let Some(layer) = maybe_layer {
-                entity.insert(VoxelLayer {
-                    id: *layer_id,
-                    name: layer.name.clone(),
-                });
-            }
-            let node_is_hidden = parse_bool(attributes.get("_hidden").cloned());
-            let layer_is_hidden = maybe_layer.map_or(false, |v| v.is_hidden);
-            let visibility = if node_is_hidden || layer_is_hidden {
-                Visibility::Hidden
-            } else {
-                Visibility::Inherited
-            };
-            entity.insert(visibility);
-            if let Some(node_name) = node_name.clone() {
-                entity.insert(Name::new(node_name.clone()));
-            }
+        let maybe_layer = scene_graph.layers.get(*layer_id as usize);
+        if let Some(layer) = maybe_layer {
+            entity.insert(VoxelLayer {
+                id: *layer_id,
+                name: layer.name.clone(),
+            });
         }
-        _ => {}
+        #[cfg(feature = "render")]
+        insert_visibility(&mut entity, attributes, maybe_layer);
+        if let Some(node_name) = node_name.clone() {
+            entity.insert(Name::new(node_name.clone()));
+        }
+        scene_graph.animations.add_player(&mut world, root);
     }
     Scene::new(world)
 }
@@ -134,13 +284,10 @@ pub(super) fn parse_scene_graph(
 fn load_xform_node(
     context: &mut LoadContext,
     builder: &mut WorldChildBuilder,
-    graph: &Vec<SceneNode>,
+    scene_graph: &mut SceneGraphContext,
     scene_node: &SceneNode,
     parent_name: Option<&String>,
-    model_names: &mut Vec<Option<String>>,
-    subassets: &mut HashSet<String>,
-    layers: &Vec<LayerInfo>,
-    scene_scale: f32,
+    animation_player: Entity,
 ) {
     match scene_node {
         SceneNode::Transform {
@@ -153,7 +300,7 @@ fn load_xform_node(
                 get_accumulated_and_node_name(parent_name, attributes.get("_name"));
             let mut entity = builder.spawn_empty();
 
-            let maybe_layer = layers.get(*layer_id as usize);
+            let maybe_layer = scene_graph.layers.get(*layer_id as usize);
             if let Some(layer) = maybe_layer {
                 entity.insert(VoxelLayer {
                     id: *layer_id,
@@ -164,63 +311,49 @@ fn load_xform_node(
                 entity.insert(Name::new(node_name));
             }
 
-            let node_is_hidden = parse_bool(attributes.get("_hidden").cloned());
-            let layer_is_hidden = maybe_layer.map_or(false, |v| v.is_hidden);
-            let visibility = if node_is_hidden || layer_is_hidden {
-                Visibility::Hidden
-            } else {
-                Visibility::Inherited
-            };
-            entity.insert(visibility);
+            #[cfg(feature = "render")]
+            insert_visibility(&mut entity, attributes, maybe_layer);
 
             load_xform_child(
                 context,
-                graph,
-                &graph[*child as usize],
+                scene_graph,
+                &scene_graph.graph[*child as usize],
                 &mut entity,
                 accumulated.as_ref(),
-                model_names,
-                subassets,
-                layers,
-                scene_scale,
+                animation_player,
             );
 
             entity.insert(Transform::from_matrix(transform_from_frame(
                 &frames[0],
-                scene_scale,
+                scene_graph.scene_scale,
             )));
+            scene_graph.animations.add_node(
+                &mut entity,
+                scene_graph.graph,
+                scene_node,
+                node_name.as_ref(),
+                animation_player,
+                scene_graph.scene_scale,
+            );
 
             if let Some(node_name) = node_name {
                 // create sub-asset
-                if subassets.insert(node_name.clone()) {
+                if scene_graph.subassets.insert(node_name.clone()) {
                     context.labeled_asset_scope(node_name, |context| {
-                        parse_scene_graph(
-                            context,
-                            graph,
-                            scene_node,
-                            parent_name,
-                            model_names,
-                            subassets,
-                            layers,
-                            scene_scale,
-                        )
+                        parse_scene_graph(context, scene_graph, scene_node, parent_name)
                     });
                 }
             }
         }
         SceneNode::Group { .. } | SceneNode::Shape { .. } => {
-            warn!("Found Group or Shape Node without a parent Transform");
             let mut node = builder.spawn_empty();
             load_xform_child(
                 context,
-                graph,
+                scene_graph,
                 scene_node,
                 &mut node,
                 parent_name,
-                model_names,
-                subassets,
-                layers,
-                scene_scale,
+                animation_player,
             );
         }
     }
@@ -228,30 +361,23 @@ fn load_xform_node(
 
 fn load_xform_child(
     context: &mut LoadContext,
-    graph: &Vec<SceneNode>,
+    scene_graph: &mut SceneGraphContext,
     scene_node: &SceneNode,
     entity: &mut EntityWorldMut,
     parent_name: Option<&String>,
-    model_names: &mut Vec<Option<String>>,
-    subassets: &mut HashSet<String>,
-    layers: &Vec<LayerInfo>,
-    scene_scale: f32,
+    animation_player: Entity,
 ) {
     match scene_node {
         SceneNode::Transform { .. } => {
-            warn!("Found nested Transform nodes");
             entity.insert(Transform::IDENTITY);
             entity.with_children(|builder| {
                 load_xform_node(
                     context,
                     builder,
-                    graph,
+                    scene_graph,
                     scene_node,
                     parent_name,
-                    model_names,
-                    subassets,
-                    layers,
-                    scene_scale,
+                    animation_player,
                 );
             });
         }
@@ -265,26 +391,23 @@ fn load_xform_child(
                     load_xform_node(
                         context,
                         builder,
-                        graph,
-                        &graph[*child as usize],
+                        scene_graph,
+                        &scene_graph.graph[*child as usize],
                         parent_name,
-                        model_names,
-                        subassets,
-                        layers,
-                        scene_scale,
+                        animation_player,
                     );
                 }
             });
         }
         SceneNode::Shape {
             attributes: _,
-            models,
+            models: shape_models,
         } => {
-            let models: Vec<Handle<VoxelModel>> = models
+            let models: Vec<Handle<VoxelModel>> = shape_models
                 .iter()
                 .map(|model| {
                     let model_id = model.model_id as usize;
-                    let model_name = model_names[model_id]
+                    let model_name = scene_graph.model_names[model_id]
                         .clone()
                         .unwrap_or(format!("model-{}", model_id));
                     context.get_label_handle(format!("{}@model", model_name))
@@ -297,7 +420,7 @@ fn load_xform_child(
             });
             if model_count > 1 {
                 entity.insert(VoxelAnimationPlayer {
-                    frames: (0..model_count).collect(),
+                    frames: animation_frames(shape_models),
                     ..Default::default()
                 });
             }
@@ -305,6 +428,34 @@ fn load_xform_child(
     }
 }
 
+/// Expands the `_f` keyframe index of each model into a timeline with one entry per frame, so that a model is held
+/// until the next keyframe. Frames before the first keyframe show the first model. Models without a keyframe index
+/// are placed at their position in the shape node, and keyframes past [`MAX_KEYFRAME`] are moved to it.
+fn animation_frames(models: &[ShapeModel]) -> Vec<usize> {
+    let mut keyframes: Vec<(u32, usize)> = models
+        .iter()
+        .enumerate()
+        .map(|(index, model)| {
+            let frame = model.frame_index().unwrap_or(index as u32);
+            (frame.min(MAX_KEYFRAME), index)
+        })
+        .collect();
+    keyframes.sort_by_key(|(frame, _)| *frame);
+    let Some((last_frame, _)) = keyframes.last() else {
+        return vec![];
+    };
+    (0..=*last_frame)
+        .map(|frame| {
+            keyframes
+                .iter()
+                .rev()
+                .find(|(keyframe, _)| *keyframe <= frame)
+                .unwrap_or(&keyframes[0])
+                .1
+        })
+        .collect()
+}
+
 fn get_accumulated_and_node_name(
     parent_name: Option<&String>,
     node_name: Option<&String>,
@@ -320,35 +471,53 @@ fn get_accumulated_and_node_name(
     }
 }
 
+/// Hides the entity if either the node or its layer is hidden
+#[cfg(feature = "render")]
+fn insert_visibility(entity: &mut EntityWorldMut, attributes: &Dict, layer: Option<&LayerInfo>) {
+    let node_is_hidden = parse_bool(attributes.get("_hidden").cloned());
+    let layer_is_hidden = layer.is_some_and(|v| v.is_hidden);
+    let visibility = if node_is_hidden || layer_is_hidden {
+        Visibility::Hidden
+    } else {
+        Visibility::Inherited
+    };
+    entity.insert(visibility);
+}
+
+/// Invalid values are reported by [`validate_scene_graph`], and treated as false
+#[cfg(feature = "render")]
 fn parse_bool(value: Option<String>) -> bool {
-    match value.as_deref() {
-        Some("1") => true,
-        Some("0") => false,
-        Some(_) => {
-            warn!("Invalid boolean string");
-            false
-        }
-        None => false,
-    }
+    value.as_deref() == Some("1")
 }
 
-fn transform_from_frame(frame: &Frame, scene_scale: f32) -> Mat4 {
+pub(super) fn transform_from_frame(frame: &Frame, scene_scale: f32) -> Mat4 {
     let Some(position) = frame.position() else {
         return Mat4::IDENTITY;
     };
-    let position =
-        Vec3::new(-position.x as f32, position.z as f32, position.y as f32) * scene_scale;
+    let position = convert_axes(Vec3::new(
+        position.x as f32,
+        position.y as f32,
+        position.z as f32,
+    )) * scene_scale;
     let translation = Mat4::from_translation(position);
     let rotation = if let Some(orientation) = frame.orientation() {
-        let (rotation, scale) = &orientation.to_quat_scale();
-        let scale: Vec3 = (*scale).into();
-        let quat = Quat::from_array(*rotation);
-        let (axis, angle) = quat.to_axis_angle();
-        let mat3 = Mat3::from_axis_angle(Vec3::new(-axis.x, axis.z, axis.y), angle)
-            * Mat3::from_diagonal(scale);
-        Mat4::from_mat3(mat3)
+        Mat4::from_mat3(rotation_from_orientation(&orientation))
     } else {
         Mat4::IDENTITY
     };
     translation * rotation
 }
+
+/// Converts a Magica Voxel orientation to a bevy rotation (including any flip)
+pub(crate) fn rotation_from_orientation(orientation: &Rotation) -> Mat3 {
+    let (rotation, scale) = &orientation.to_quat_scale();
+    let scale: Vec3 = (*scale).into();
+    let quat = Quat::from_array(*rotation);
+    let (axis, angle) = quat.to_axis_angle();
+    Mat3::from_axis_angle(convert_axes(axis), angle) * Mat3::from_diagonal(scale)
+}
+
+/// Converts a vector from Magica Voxel's left-handed Z-up space to bevy's right-handed Y-up space
+pub(super) fn convert_axes(vector: Vec3) -> Vec3 {
+    Vec3::new(-vector.x, vector.z, vector.y)
+}
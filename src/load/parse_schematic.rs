@@ -0,0 +1,552 @@
+use std::{
+    io::Read,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use anyhow::anyhow;
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    color::Color,
+    ecs::system::{Res, Resource},
+    log::{info, warn},
+    math::{IVec3, UVec3},
+    scene::Scene,
+    utils::{HashMap, HashSet},
+};
+use flate2::read::GzDecoder;
+
+use super::{
+    import::{read_file, VoxFileBuilder},
+    load_voxel_scene,
+    nbt::{read_nbt, Tag},
+    VoxLoaderError, VoxLoaderSettings,
+};
+use crate::{VoxelElement, VoxelPalette};
+
+/// The most kinds of block that fit in a palette, as the last index is used for [`crate::Voxel::EMPTY`]
+const MAX_ELEMENTS: usize = 255;
+
+/// The most blocks that a structure can hold
+const MAX_VOLUME: u64 = 128 * 128 * 128;
+
+/// The most bytes that a compressed file can decompress to. Structure block files store each block as a compound of its
+/// position and state, of around 40 bytes, so this leaves room for the palette and for blocks with block entities.
+const MAX_DECOMPRESSED_SIZE: u64 = MAX_VOLUME * 64;
+
+/// Blocks that are always loaded as empty voxels
+const AIR_BLOCKS: [&str; 4] = [
+    "minecraft:air",
+    "minecraft:cave_air",
+    "minecraft:void_air",
+    "minecraft:structure_void",
+];
+
+/// The colors of the dyes that wool, concrete, terracotta and stained glass come in
+const DYE_COLORS: [(&str, [u8; 3]); 16] = [
+    ("white", [234, 236, 237]),
+    ("orange", [241, 118, 20]),
+    ("magenta", [189, 68, 179]),
+    ("light_blue", [58, 175, 217]),
+    ("yellow", [248, 198, 40]),
+    ("lime", [112, 185, 26]),
+    ("pink", [237, 141, 172]),
+    ("gray", [63, 68, 72]),
+    ("light_gray", [142, 142, 135]),
+    ("cyan", [21, 137, 145]),
+    ("purple", [122, 42, 173]),
+    ("blue", [53, 57, 157]),
+    ("brown", [114, 72, 41]),
+    ("green", [84, 109, 27]),
+    ("red", [161, 39, 35]),
+    ("black", [21, 21, 26]),
+];
+
+/// The average colors of common opaque blocks
+const BLOCK_COLORS: [(&str, [u8; 3]); 48] = [
+    ("stone", [125, 125, 125]),
+    ("granite", [149, 103, 86]),
+    ("polished_granite", [154, 107, 89]),
+    ("diorite", [188, 188, 188]),
+    ("polished_diorite", [192, 193, 194]),
+    ("andesite", [136, 136, 137]),
+    ("polished_andesite", [132, 135, 134]),
+    ("deepslate", [80, 80, 82]),
+    ("cobblestone", [127, 127, 127]),
+    ("mossy_cobblestone", [110, 118, 95]),
+    ("stone_bricks", [122, 121, 122]),
+    ("bricks", [150, 97, 83]),
+    ("bedrock", [85, 85, 85]),
+    ("grass_block", [95, 159, 53]),
+    ("dirt", [134, 96, 67]),
+    ("coarse_dirt", [119, 85, 59]),
+    ("podzol", [91, 63, 24]),
+    ("mud", [60, 57, 60]),
+    ("clay", [160, 166, 179]),
+    ("gravel", [131, 127, 126]),
+    ("sand", [219, 207, 163]),
+    ("red_sand", [190, 102, 33]),
+    ("sandstone", [216, 203, 155]),
+    ("red_sandstone", [186, 99, 29]),
+    ("snow_block", [249, 254, 254]),
+    ("terracotta", [152, 94, 67]),
+    ("obsidian", [15, 10, 24]),
+    ("netherrack", [97, 38, 38]),
+    ("end_stone", [219, 222, 158]),
+    ("oak_log", [109, 85, 50]),
+    ("spruce_log", [58, 37, 16]),
+    ("birch_log", [216, 215, 210]),
+    ("jungle_log", [85, 67, 25]),
+    ("acacia_log", [103, 96, 86]),
+    ("dark_oak_log", [60, 46, 26]),
+    ("oak_planks", [162, 130, 78]),
+    ("spruce_planks", [114, 84, 48]),
+    ("birch_planks", [192, 175, 121]),
+    ("jungle_planks", [160, 115, 80]),
+    ("acacia_planks", [168, 90, 50]),
+    ("dark_oak_planks", [66, 43, 20]),
+    ("oak_leaves", [60, 110, 30]),
+    ("spruce_leaves", [50, 80, 50]),
+    ("birch_leaves", [90, 120, 55]),
+    ("iron_block", [220, 220, 220]),
+    ("gold_block", [246, 208, 61]),
+    ("diamond_block", [98, 237, 228]),
+    ("coal_block", [16, 15, 15]),
+];
+
+/// Maps the ids of Minecraft blocks to the [`VoxelElement`]s that they are loaded as by the loader for `.schem` and
+/// `.nbt` structure files.
+///
+/// Ids are looked up without their block states, so `minecraft:oak_stairs[facing=east]` is loaded as
+/// `minecraft:oak_stairs`. Ids without a namespace are in the `minecraft` namespace. Air and structure void blocks are
+/// always empty.
+///
+/// The default mapping gives common blocks their average color, along with translucent glass, water and ice, and
+/// glowing lava and lights. Other blocks are loaded as the [`SchematicBlocks::fallback`] element. Insert this as a
+/// resource to change the mapping for the structures loaded after it.
+///
+/// ### Example
+/// ```no_run
+/// # use bevy::prelude::*;
+/// # use bevy_vox_scene::{SchematicBlocks, VoxScenePlugin, VoxelElement};
+/// let mut blocks = SchematicBlocks::default();
+/// blocks.insert(
+///     "minecraft:lapis_block",
+///     VoxelElement {
+///         color: Color::srgb_u8(31, 67, 140),
+///         ..default()
+///     },
+/// );
+/// App::new()
+///     .add_plugins((DefaultPlugins, VoxScenePlugin::default()))
+///     .insert_resource(blocks);
+/// ```
+#[derive(Resource, Clone, Debug)]
+pub struct SchematicBlocks {
+    /// The elements for each block id, including its namespace
+    pub elements: HashMap<String, VoxelElement>,
+    /// The element for blocks that aren't in [`SchematicBlocks::elements`], or `None` to leave them out
+    pub fallback: Option<VoxelElement>,
+}
+
+impl Default for SchematicBlocks {
+    fn default() -> Self {
+        let mut blocks = Self::empty();
+        blocks.fallback = Some(diffuse([160, 160, 160]));
+        for (name, color) in BLOCK_COLORS {
+            blocks.insert(name, diffuse(color));
+        }
+        for (dye, color) in DYE_COLORS {
+            blocks.insert(format!("{dye}_wool"), diffuse(color));
+            blocks.insert(format!("{dye}_concrete"), diffuse(color));
+            blocks.insert(
+                format!("{dye}_terracotta"),
+                diffuse(color.map(|c| c / 2 + 40)),
+            );
+            blocks.insert(format!("{dye}_stained_glass"), translucent(color, 0.7, 1.5));
+        }
+        blocks.insert("glass", translucent([220, 235, 240], 0.9, 1.5));
+        blocks.insert("water", translucent([63, 118, 228], 0.6, 1.33));
+        blocks.insert("ice", translucent([145, 183, 253], 0.4, 1.31));
+        for (name, color, emission) in [
+            ("lava", [207, 92, 20], 2.0),
+            ("glowstone", [250, 215, 130], 2.0),
+            ("sea_lantern", [172, 199, 190], 2.0),
+            ("shroomlight", [241, 146, 71], 1.5),
+            ("jack_o_lantern", [214, 152, 52], 1.0),
+        ] {
+            blocks.insert(
+                name,
+                VoxelElement {
+                    emission,
+                    ..diffuse(color)
+                },
+            );
+        }
+        blocks
+    }
+}
+
+impl SchematicBlocks {
+    /// A mapping with no elements, which leaves out every block unless given a [`SchematicBlocks::fallback`]
+    pub fn empty() -> Self {
+        Self {
+            elements: HashMap::new(),
+            fallback: None,
+        }
+    }
+
+    /// Sets the element for a block id, such as `minecraft:stone` or `stone`
+    pub fn insert(&mut self, id: impl Into<String>, element: VoxelElement) {
+        self.elements.insert(normalized_id(&id.into()), element);
+    }
+
+    /// The element for a block id, which may include block states
+    pub fn get(&self, id: &str) -> Option<&VoxelElement> {
+        self.elements.get(&normalized_id(id))
+    }
+}
+
+fn diffuse(color: [u8; 3]) -> VoxelElement {
+    VoxelElement {
+        color: Color::srgb_u8(color[0], color[1], color[2]),
+        roughness: 0.8,
+        ..Default::default()
+    }
+}
+
+fn translucent(color: [u8; 3], translucency: f32, refraction_index: f32) -> VoxelElement {
+    VoxelElement {
+        color: Color::srgb_u8(color[0], color[1], color[2]),
+        roughness: 0.1,
+        translucency,
+        refraction_index,
+        ..Default::default()
+    }
+}
+
+/// The block id without its block states, and with the `minecraft` namespace if it has none
+fn normalized_id(id: &str) -> String {
+    let id = id.split('[').next().unwrap_or_default().trim();
+    if id.contains(':') {
+        id.to_string()
+    } else {
+        format!("minecraft:{id}")
+    }
+}
+
+/// An asset loader capable of loading Minecraft structures as [`bevy::scene::Scene`]s, from Sponge `.schem`
+/// schematics and the `.nbt` files saved by structure blocks.
+///
+/// The blocks are mapped to voxels with the app's [`SchematicBlocks`] resource. Structures larger
+/// than 256 blocks along any axis are split into several models under a single node. The minimum corner of the
+/// structure is placed at the origin.
+pub(crate) struct SchematicSceneLoader {
+    pub(crate) global_settings: Option<VoxLoaderSettings>,
+    pub(crate) blocks: SharedSchematicBlocks,
+}
+
+/// The copy of the [`SchematicBlocks`] resource that the loader reads, as loaders can't access the world
+#[derive(Resource, Clone, Default)]
+pub(crate) struct SharedSchematicBlocks(Arc<RwLock<SchematicBlocks>>);
+
+impl SharedSchematicBlocks {
+    pub(crate) fn new(blocks: SchematicBlocks) -> Self {
+        Self(Arc::new(RwLock::new(blocks)))
+    }
+
+    pub(crate) fn set(&self, blocks: &SchematicBlocks) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = blocks.clone();
+    }
+}
+
+/// Passes changes of the [`SchematicBlocks`] resource to the loader
+pub(crate) fn sync_schematic_blocks(
+    blocks: Res<SchematicBlocks>,
+    shared: Res<SharedSchematicBlocks>,
+) {
+    shared.set(&blocks);
+}
+
+impl AssetLoader for SchematicSceneLoader {
+    type Asset = Scene;
+    type Settings = VoxLoaderSettings;
+    type Error = VoxLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = read_file(reader).await?;
+        info!("Loading {}", load_context.asset_path());
+        let settings = self.global_settings.clone().unwrap_or(settings.clone());
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            let mut decompressed = Vec::new();
+            // read one byte past the limit to tell files that reach it from those that exceed it
+            GzDecoder::new(bytes.as_slice())
+                .take(MAX_DECOMPRESSED_SIZE + 1)
+                .read_to_end(&mut decompressed)
+                .map_err(|e| VoxLoaderError::InvalidAsset(anyhow!(e)))?;
+            if decompressed.len() as u64 > MAX_DECOMPRESSED_SIZE {
+                return Err(VoxLoaderError::InvalidAsset(anyhow!(
+                    "the file decompresses to more than {MAX_DECOMPRESSED_SIZE} bytes"
+                )));
+            }
+            bytes = decompressed;
+        }
+        let root = read_nbt(&bytes)?;
+        let structure = if root.get("blocks").is_some() {
+            read_structure(&root)?
+        } else {
+            read_sponge_schematic(&root)?
+        };
+
+        let mut elements: Vec<VoxelElement> = Vec::new();
+        let mut element_indices: HashMap<String, u8> = HashMap::new();
+        let mut palette: Vec<Option<u8>> = Vec::with_capacity(structure.palette.len());
+        let mut unknown_ids: HashSet<String> = HashSet::new();
+        let blocks = self.blocks.0.read().unwrap_or_else(PoisonError::into_inner);
+        for id in structure.palette.iter() {
+            let id = normalized_id(id);
+            if AIR_BLOCKS.contains(&id.as_str()) {
+                palette.push(None);
+                continue;
+            }
+            // blocks without an element of their own share the fallback element
+            let (key, element) = match blocks.elements.get(&id) {
+                Some(element) => (id, element),
+                None => {
+                    let fallback = blocks.fallback.as_ref();
+                    if unknown_ids.insert(id.clone()) {
+                        match fallback {
+                            Some(_) => warn!("No element for block {id}, using the fallback"),
+                            None => warn!("No element for block {id}, leaving it out"),
+                        }
+                    }
+                    let Some(element) = fallback else {
+                        palette.push(None);
+                        continue;
+                    };
+                    (String::new(), element)
+                }
+            };
+            let index = match element_indices.get(&key) {
+                Some(index) => *index,
+                None => {
+                    if elements.len() == MAX_ELEMENTS {
+                        return Err(VoxLoaderError::TooManyBlocks);
+                    }
+                    elements.push(element.clone());
+                    let index = elements.len() as u8 - 1;
+                    element_indices.insert(key, index);
+                    index
+                }
+            };
+            palette.push(Some(index));
+        }
+
+        let mut file = VoxFileBuilder::new();
+        file.add_model(
+            structure.name.as_deref(),
+            IVec3::ZERO,
+            structure.size,
+            structure
+                .blocks
+                .into_iter()
+                .filter_map(|(position, state)| {
+                    palette
+                        .get(state)
+                        .copied()
+                        .flatten()
+                        .map(|index| (position, index))
+                }),
+            0,
+        );
+        Ok(load_voxel_scene(
+            load_context,
+            &settings,
+            file.models,
+            &file.graph,
+            &file.layers,
+            VoxelPalette::new(elements, settings.uses_srgb),
+        ))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["schem", "nbt"]
+    }
+}
+
+/// The blocks of a structure, which share bevy's right-handed Y-up space
+struct Structure {
+    name: Option<String>,
+    size: UVec3,
+    /// The block ids, including their block states
+    palette: Vec<String>,
+    /// The position of each block, and the index of its id in the palette
+    blocks: Vec<(UVec3, usize)>,
+}
+
+/// Reads a structure saved by a structure block, which lists each block with its position and its index in the palette
+fn read_structure(root: &Tag) -> Result<Structure, VoxLoaderError> {
+    let size = read_size(root.get("size").and_then(Tag::as_ints))?;
+    // structures with several palettes pick one at random when placed, so the first is used
+    let palette = root
+        .get("palette")
+        .or_else(|| {
+            root.get("palettes")
+                .and_then(|palettes| palettes.as_list()?.first())
+        })
+        .and_then(Tag::as_list)
+        .ok_or_else(|| missing("palette"))?
+        .iter()
+        .map(|state| {
+            state
+                .get("Name")
+                .and_then(Tag::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| missing("Name"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let blocks = root
+        .get("blocks")
+        .and_then(Tag::as_list)
+        .ok_or_else(|| missing("blocks"))?
+        .iter()
+        .map(|block| {
+            let state = block
+                .get("state")
+                .and_then(Tag::as_i64)
+                .ok_or_else(|| missing("state"))?;
+            let position = block
+                .get("pos")
+                .and_then(Tag::as_ints)
+                .ok_or_else(|| missing("pos"))?;
+            let [x, y, z] = position[..] else {
+                return Err(invalid("pos", "must have 3 coordinates"));
+            };
+            let position = IVec3::new(x as i32, y as i32, z as i32);
+            if position.cmplt(IVec3::ZERO).any() || position.as_uvec3().cmpge(size).any() {
+                return Err(invalid("pos", "is outside of the structure"));
+            }
+            Ok((position.as_uvec3(), state as usize))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Structure {
+        name: None,
+        size,
+        palette,
+        blocks,
+    })
+}
+
+/// Reads a Sponge schematic, which stores the palette index of every block as a varint, ordered by x, then z, then y
+fn read_sponge_schematic(root: &Tag) -> Result<Structure, VoxLoaderError> {
+    // version 3 schematics are nested in a compound, and keep their blocks in a compound of their own
+    let schematic = root.get("Schematic").unwrap_or(root);
+    let blocks = schematic.get("Blocks").unwrap_or(schematic);
+    let dimension = |name: &str| {
+        schematic
+            .get(name)
+            .and_then(Tag::as_i64)
+            // dimensions are stored as unsigned shorts
+            .map(|value| value as u16 as i64)
+    };
+    let size = read_size(
+        [dimension("Width"), dimension("Height"), dimension("Length")]
+            .into_iter()
+            .collect(),
+    )?;
+    let mut palette = Vec::new();
+    for (id, index) in blocks
+        .get("Palette")
+        .and_then(Tag::as_compound)
+        .ok_or_else(|| missing("Palette"))?
+    {
+        let index = index
+            .as_i64()
+            .and_then(|index| usize::try_from(index).ok())
+            .filter(|index| *index <= u16::MAX as usize)
+            .ok_or_else(|| invalid("Palette", "has an invalid index"))?;
+        if palette.len() <= index {
+            palette.resize(index + 1, String::new());
+        }
+        palette[index] = id.clone();
+    }
+    let data = blocks
+        .get("BlockData")
+        .or_else(|| blocks.get("Data"))
+        .and_then(Tag::as_bytes)
+        .ok_or_else(|| missing("BlockData"))?;
+    let volume = size.x as usize * size.y as usize * size.z as usize;
+    let mut voxels = Vec::new();
+    let mut bytes = data.iter();
+    let mut index = 0;
+    while bytes.len() > 0 {
+        let mut state = 0usize;
+        let mut shift = 0;
+        loop {
+            let Some(byte) = bytes.next() else {
+                return Err(invalid("BlockData", "ends in the middle of a value"));
+            };
+            if shift > 28 {
+                return Err(invalid("BlockData", "has a value that is too large"));
+            }
+            state |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        if index >= volume {
+            return Err(invalid("BlockData", "has more blocks than the schematic"));
+        }
+        let (x, z, y) = (
+            index % size.x as usize,
+            (index / size.x as usize) % size.z as usize,
+            index / (size.x as usize * size.z as usize),
+        );
+        voxels.push((UVec3::new(x as u32, y as u32, z as u32), state));
+        index += 1;
+    }
+    let name = schematic
+        .get("Metadata")
+        .and_then(|metadata| metadata.get("Name"))
+        .and_then(Tag::as_str)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string);
+    Ok(Structure {
+        name,
+        size,
+        palette,
+        blocks: voxels,
+    })
+}
+
+fn read_size(size: Option<Vec<i64>>) -> Result<UVec3, VoxLoaderError> {
+    match size.as_deref() {
+        Some(&[x, y, z])
+            if [x, y, z]
+                .iter()
+                .all(|value| (1..=i32::MAX as i64).contains(value)) =>
+        {
+            if x as u64 * y as u64 * z as u64 > MAX_VOLUME {
+                return Err(VoxLoaderError::InvalidAsset(anyhow!(
+                    "the structure of size {x}x{y}x{z} has more than {MAX_VOLUME} blocks"
+                )));
+            }
+            Ok(UVec3::new(x as u32, y as u32, z as u32))
+        }
+        Some(_) => Err(invalid("size", "must have 3 positive dimensions")),
+        None => Err(missing("size")),
+    }
+}
+
+fn missing(name: &str) -> VoxLoaderError {
+    VoxLoaderError::InvalidAsset(anyhow!("the structure has no valid `{name}` tag"))
+}
+
+fn invalid(name: &str, message: &str) -> VoxLoaderError {
+    VoxLoaderError::InvalidAsset(anyhow!("the `{name}` tag {message}"))
+}
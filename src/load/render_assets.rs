@@ -0,0 +1,149 @@
+use bevy::{
+    asset::{Handle, LoadContext},
+    color::LinearRgba,
+    image::Image,
+    pbr::StandardMaterial,
+    render::mesh::Mesh,
+};
+
+use crate::{
+    model::{material_for_color_mode, MaterialProperty, VoxelModel, VoxelPalette},
+    MeshColorMode, VoxelContext, VoxelData, VoxelQueryable,
+};
+
+/// The materials derived from the palette, which are shared by the models in the file
+pub(super) struct PaletteMaterials {
+    translucent_material: StandardMaterial,
+    opaque_material: Handle<StandardMaterial>,
+    vertex_color_material: Handle<StandardMaterial>,
+}
+
+impl PaletteMaterials {
+    /// Adds the `material`, `material-vertex-colors` and `material-no-emission` subassets
+    pub(super) fn new(load_context: &mut LoadContext, palette: &VoxelPalette) -> Self {
+        let translucent_material = palette.create_material_in_load_context(load_context);
+        let mut opaque_material = translucent_material.clone();
+        #[cfg(feature = "pbr_transmission_textures")]
+        {
+            opaque_material.specular_transmission_texture = None;
+        }
+        opaque_material.specular_transmission = 0.0;
+        let vertex_color_material = load_context.add_labeled_asset(
+            "material-vertex-colors".to_string(),
+            material_for_color_mode(opaque_material.clone(), MeshColorMode::VertexColors),
+        );
+        let opaque_material =
+            load_context.add_labeled_asset("material".to_string(), opaque_material);
+        if palette.emission == MaterialProperty::VariesPerElement {
+            load_context.labeled_asset_scope("material-no-emission".to_string(), |_| {
+                let mut non_emissive = translucent_material.clone();
+                non_emissive.emissive_texture = None;
+                non_emissive.emissive = LinearRgba::BLACK;
+                non_emissive
+            });
+        }
+        Self {
+            translucent_material,
+            opaque_material,
+            vertex_color_material,
+        }
+    }
+
+    /// Meshes the model, adding its mesh, levels of detail, material and cloud image as subassets
+    pub(super) fn load_model(
+        &self,
+        load_context: &mut LoadContext,
+        palette: &VoxelPalette,
+        name: String,
+        data: VoxelData,
+    ) -> VoxelModel {
+        let (visible_voxels, ior, needs_meshing) =
+            data.visible_voxels(&palette.indices_of_refraction, &palette.density_for_voxel);
+        let (cloud_voxels, has_cloud) = data.cloud_voxels(&palette.density_for_voxel);
+        let mesh = if needs_meshing {
+            let mesh_handle = load_context.labeled_asset_scope(format!("{}@mesh", name), |_| {
+                crate::model::mesh::mesh_model(&visible_voxels, &data, palette)
+            });
+            Some(mesh_handle)
+        } else {
+            None
+        };
+
+        let lods: Vec<Handle<Mesh>> = if needs_meshing {
+            data.lod_meshes(palette)
+                .into_iter()
+                .enumerate()
+                .map(|(index, mesh)| {
+                    load_context.add_labeled_asset(format!("{}@mesh-lod{}", name, index + 1), mesh)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let material: Option<Handle<StandardMaterial>> = if needs_meshing {
+            if let Some(ior) = ior {
+                let handle = load_context.labeled_asset_scope(format!("{}@material", name), |_| {
+                    let mut material = material_for_color_mode(
+                        self.translucent_material.clone(),
+                        data.settings.color_mode,
+                    );
+                    material.ior = ior;
+                    material.thickness = data.size().min_element() as f32;
+                    material
+                });
+                Some(handle)
+            } else {
+                let handle = load_context.labeled_asset_scope(format!("{}@material", name), |_| {
+                    let mut opaque_material = self.translucent_material.clone();
+                    #[cfg(feature = "pbr_transmission_textures")]
+                    {
+                        opaque_material.specular_transmission_texture = None;
+                    }
+                    opaque_material.specular_transmission = 0.0;
+                    material_for_color_mode(opaque_material, data.settings.color_mode)
+                });
+                Some(handle)
+            }
+        } else {
+            None
+        };
+        let cloud_image: Option<Handle<Image>> = if has_cloud {
+            let cloud_handle = load_context
+                .labeled_asset_scope(format!("{}@cloud-image", name), |_| {
+                    crate::model::cloud::create_cloud_image(&cloud_voxels, &data)
+                });
+            Some(cloud_handle)
+        } else {
+            None
+        };
+        VoxelModel {
+            name,
+            data,
+            mesh,
+            lods,
+            material,
+            cloud_image,
+            has_translucency: ior.is_some(),
+            awaiting_mesh: false,
+        }
+    }
+
+    /// Adds the `material-transmissive` subasset, returning the context for the models
+    pub(super) fn into_context(
+        self,
+        load_context: &mut LoadContext,
+        palette: VoxelPalette,
+    ) -> VoxelContext {
+        let transmissive_material = load_context.add_labeled_asset(
+            "material-transmissive".to_string(),
+            self.translucent_material,
+        );
+        VoxelContext {
+            palette,
+            opaque_material: self.opaque_material,
+            transmissive_material,
+            vertex_color_material: self.vertex_color_material,
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use bevy::{
+    asset::Asset,
+    color::Color,
+    core::Name,
+    ecs::{entity::Entity, world::World},
+    math::{Dir3, Vec3},
+    pbr::{light_consts::lux, AmbientLight, DirectionalLight, DistanceFog, FogFalloff},
+    prelude::{ClearColor, Transform},
+    reflect::TypePath,
+};
+use dot_vox::Dict;
+
+use super::{
+    chunks::{dict_float, dict_floats, find_chunks, parse_dict},
+    parse_scene::convert_axes,
+};
+
+/// The brightness of [`AmbientLight`] produced by a Magica Voxel sky light with an intensity of 1.
+pub const SKY_BRIGHTNESS: f32 = 500.0;
+
+/// The illuminance in lux of a [`DirectionalLight`] produced by a Magica Voxel sun with an intensity of 1.
+pub const SUN_ILLUMINANCE: f32 = lux::AMBIENT_DAYLIGHT;
+
+/// The lighting and atmosphere from the render tab of a Magica Voxel file.
+///
+/// The loader adds this as a labeled asset which can be loaded by appending `#render-settings` to the asset loading path.
+/// The helper methods convert the settings into their closest bevy equivalents, which can be applied to your cameras and
+/// resources as a starting point for lighting the scene. Alternatively, set [`crate::VoxLoaderSettings::spawn_lights`] to
+/// have the sun spawned into the scene.
+#[derive(Asset, TypePath, Clone, Debug, Default)]
+pub struct VoxelRenderSettings {
+    /// The sun (Magica Voxel's infinite light), if it is switched on
+    pub sun: Option<VoxelSun>,
+    /// The uniform sky light, if it is switched on
+    pub sky: Option<VoxelSky>,
+    /// The uniform fog, if it has a density greater than zero
+    pub fog: Option<VoxelFog>,
+    /// The background color
+    pub background: Option<Color>,
+}
+
+/// Magica Voxel's infinite (sun) light
+#[derive(Clone, Debug)]
+pub struct VoxelSun {
+    /// Color of the light
+    pub color: Color,
+    /// Intensity of the light, in Magica Voxel's units
+    pub intensity: f32,
+    /// The direction the light travels in, in bevy's coordinate space
+    pub direction: Dir3,
+}
+
+/// Magica Voxel's uniform sky light
+#[derive(Clone, Debug)]
+pub struct VoxelSky {
+    /// Color of the light
+    pub color: Color,
+    /// Intensity of the light, in Magica Voxel's units
+    pub intensity: f32,
+}
+
+/// Magica Voxel's uniform fog
+#[derive(Clone, Debug)]
+pub struct VoxelFog {
+    /// Color of the fog
+    pub color: Color,
+    /// Density of the fog per world unit, taking the voxel size into account
+    pub density: f32,
+}
+
+impl VoxelRenderSettings {
+    /// A [`DirectionalLight`] and the [`Transform`] pointing it in the direction of the sun.
+    /// Returns `None` if the sun is switched off.
+    pub fn directional_light(&self) -> Option<(DirectionalLight, Transform)> {
+        let sun = self.sun.as_ref()?;
+        Some((
+            DirectionalLight {
+                color: sun.color,
+                illuminance: sun.intensity * SUN_ILLUMINANCE,
+                shadows_enabled: true,
+                ..Default::default()
+            },
+            Transform::default().looking_to(sun.direction, Vec3::Y),
+        ))
+    }
+
+    /// An [`AmbientLight`] resource approximating the sky light. Returns `None` if the sky light is switched off.
+    pub fn ambient_light(&self) -> Option<AmbientLight> {
+        let sky = self.sky.as_ref()?;
+        Some(AmbientLight {
+            color: sky.color,
+            brightness: sky.intensity * SKY_BRIGHTNESS,
+        })
+    }
+
+    /// A [`DistanceFog`] component for cameras. Returns `None` if the scene has no fog.
+    pub fn distance_fog(&self) -> Option<DistanceFog> {
+        let fog = self.fog.as_ref()?;
+        Some(DistanceFog {
+            color: fog.color,
+            falloff: FogFalloff::Exponential {
+                density: fog.density,
+            },
+            ..Default::default()
+        })
+    }
+
+    /// A [`ClearColor`] resource for the background. Returns `None` if the file has no background color.
+    pub fn clear_color(&self) -> Option<ClearColor> {
+        self.background.map(ClearColor)
+    }
+
+    /// Spawns the sun as a [`DirectionalLight`] into `world`
+    pub(super) fn spawn_lights(&self, world: &mut World) -> Option<Entity> {
+        let (light, transform) = self.directional_light()?;
+        Some(world.spawn((Name::new("sun"), light, transform)).id())
+    }
+}
+
+/// Reads the render settings from the `rOBJ` chunks of the file
+pub(super) fn parse_render_settings(bytes: &[u8], scene_scale: f32) -> VoxelRenderSettings {
+    let mut settings = VoxelRenderSettings::default();
+    for attributes in find_chunks(bytes, b"rOBJ")
+        .into_iter()
+        .filter_map(parse_dict)
+    {
+        match attributes.get("_type").map(String::as_str) {
+            Some("_inf") => settings.sun = parse_sun(&attributes),
+            Some("_uni") => {
+                settings.sky = Some(VoxelSky {
+                    color: color(&attributes, "_k").unwrap_or(Color::WHITE),
+                    intensity: dict_float(&attributes, "_i").unwrap_or(0.0),
+                })
+            }
+            Some("_fog_uni") => {
+                let density = dict_float(&attributes, "_d").unwrap_or(0.0);
+                if density > 0.0 {
+                    settings.fog = Some(VoxelFog {
+                        color: color(&attributes, "_k").unwrap_or(Color::WHITE),
+                        density: density / scene_scale,
+                    });
+                }
+            }
+            Some("_bg") => settings.background = color(&attributes, "_color"),
+            _ => {}
+        }
+    }
+    settings
+}
+
+/// The sun's `_angle` holds its altitude above the horizon and its azimuth in degrees,
+/// with an azimuth of zero placing the sun in front of the scene (Magica Voxel's -Y).
+fn parse_sun(attributes: &Dict) -> Option<VoxelSun> {
+    let intensity = dict_float(attributes, "_i").unwrap_or(0.0);
+    if intensity <= 0.0 {
+        return None;
+    }
+    let angles = dict_floats(attributes, "_angle").unwrap_or_default();
+    let altitude = angles.first().copied().unwrap_or(90.0).to_radians();
+    let azimuth = angles.get(1).copied().unwrap_or(0.0).to_radians();
+    let towards_sun = Vec3::new(
+        altitude.cos() * azimuth.sin(),
+        -altitude.cos() * azimuth.cos(),
+        altitude.sin(),
+    );
+    Some(VoxelSun {
+        color: color(attributes, "_k").unwrap_or(Color::WHITE),
+        intensity,
+        direction: Dir3::new(-convert_axes(towards_sun)).unwrap_or(Dir3::NEG_Y),
+    })
+}
+
+/// Colors are stored as space-separated sRGB bytes
+fn color(attributes: &Dict, key: &str) -> Option<Color> {
+    match dict_floats(attributes, key)?[..] {
+        [r, g, b] => Some(Color::srgb_u8(r as u8, g as u8, b as u8)),
+        _ => None,
+    }
+}
@@ -0,0 +1,211 @@
+use bevy::{
+    app::{App, Plugin, Update},
+    asset::{AssetId, Assets, Handle},
+    ecs::{
+        component::Component,
+        entity::Entity,
+        system::{Commands, EntityCommands, Query, Res, ResMut, Resource},
+        world::{EntityWorldMut, Mut},
+    },
+    pbr::{Material, MeshMaterial3d, StandardMaterial},
+    utils::HashMap,
+};
+
+use crate::{VoxelContext, VoxelPalette};
+
+/// Builds the materials of spawned voxel models, so that they can be rendered with custom shaders.
+///
+/// Register a factory with [`VoxelMaterialPlugin`], and each [`crate::VoxelModelInstance`] will be spawned with a
+/// [`MeshMaterial3d`] of [`VoxelMaterialFactory::Material`] instead of [`StandardMaterial`].
+///
+/// ### Example
+/// ```no_run
+/// # use bevy::{pbr::{ExtendedMaterial, MaterialExtension}, prelude::*, render::render_resource::AsBindGroup};
+/// # use bevy_vox_scene::{VoxScenePlugin, VoxelMaterialFactory, VoxelMaterialPlugin, VoxelPalette};
+/// #[derive(Asset, AsBindGroup, TypePath, Clone)]
+/// struct WindSway {
+///     #[uniform(100)]
+///     strength: f32,
+/// }
+///
+/// impl MaterialExtension for WindSway {}
+///
+/// #[derive(Clone)]
+/// struct WindSwayFactory;
+///
+/// impl VoxelMaterialFactory for WindSwayFactory {
+///     type Material = ExtendedMaterial<StandardMaterial, WindSway>;
+///
+///     fn create_material(&self, _palette: &VoxelPalette, standard_material: &StandardMaterial) -> Self::Material {
+///         ExtendedMaterial {
+///             base: standard_material.clone(),
+///             extension: WindSway { strength: 0.2 },
+///         }
+///     }
+/// }
+///
+/// # fn main() {
+/// App::new().add_plugins((
+///     DefaultPlugins,
+///     VoxScenePlugin::default(),
+///     MaterialPlugin::<ExtendedMaterial<StandardMaterial, WindSway>>::default(),
+///     VoxelMaterialPlugin::new(WindSwayFactory),
+/// ));
+/// # }
+/// ```
+pub trait VoxelMaterialFactory: Send + Sync + 'static {
+    /// The material that the models are spawned with
+    type Material: Material;
+
+    /// Creates the material for models whose [`crate::VoxelModel::material`] is `standard_material`.
+    ///
+    /// `standard_material` is the material that would otherwise be used. It holds the images generated from the palette
+    /// in its base color, emissive, metallic-roughness and specular transmission textures, and the index of refraction
+    /// and thickness of translucent models. This is called once for each distinct standard material, and the result is
+    /// shared by all of the models that use it.
+    fn create_material(
+        &self,
+        palette: &VoxelPalette,
+        standard_material: &StandardMaterial,
+    ) -> Self::Material;
+}
+
+/// Spawns voxel models with the materials built by a [`VoxelMaterialFactory`].
+///
+/// This doesn't add a `MaterialPlugin` for [`VoxelMaterialFactory::Material`], which the app should add itself. Models
+/// spawned before their [`VoxelContext`] has loaded get their material once it has.
+pub struct VoxelMaterialPlugin<F: VoxelMaterialFactory + Clone> {
+    factory: F,
+}
+
+impl<F: VoxelMaterialFactory + Clone> VoxelMaterialPlugin<F> {
+    /// A plugin that builds the materials of spawned models with `factory`
+    pub fn new(factory: F) -> Self {
+        Self { factory }
+    }
+}
+
+impl<F: VoxelMaterialFactory + Clone> Plugin for VoxelMaterialPlugin<F> {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(VoxelMaterials {
+            factory: self.factory.clone(),
+            materials: HashMap::new(),
+        })
+        .insert_resource(CustomMaterialInserter(insert_custom_material::<F>))
+        .add_systems(Update, insert_pending_materials::<F>);
+    }
+}
+
+/// The factory, along with the materials it has built for each standard material. The materials are only kept alive by
+/// the entities using them, so that they are dropped along with the last model using them.
+#[derive(Resource)]
+struct VoxelMaterials<F: VoxelMaterialFactory> {
+    factory: F,
+    materials: HashMap<AssetId<StandardMaterial>, AssetId<F::Material>>,
+}
+
+/// Marks a mesh whose custom material can't be built until its context and standard material have loaded
+#[derive(Component)]
+struct PendingVoxelMaterial {
+    standard_material: Handle<StandardMaterial>,
+    context: Handle<VoxelContext>,
+}
+
+/// Inserts the material built by the registered [`VoxelMaterialFactory`] in place of a model's [`StandardMaterial`]
+#[derive(Resource)]
+pub(crate) struct CustomMaterialInserter(
+    pub(crate) fn(&mut EntityCommands, Handle<StandardMaterial>, Handle<VoxelContext>),
+);
+
+fn insert_custom_material<F: VoxelMaterialFactory>(
+    entity: &mut EntityCommands,
+    standard_material: Handle<StandardMaterial>,
+    context: Handle<VoxelContext>,
+) {
+    entity.queue(move |mut entity: EntityWorldMut| {
+        let material = entity.world_scope(|world| {
+            world.resource_scope(|world, mut materials: Mut<VoxelMaterials<F>>| {
+                world.resource_scope(|world, mut custom_materials: Mut<Assets<F::Material>>| {
+                    custom_material(
+                        &mut materials,
+                        &mut custom_materials,
+                        world.resource::<Assets<VoxelContext>>(),
+                        world.resource::<Assets<StandardMaterial>>(),
+                        &standard_material,
+                        &context,
+                    )
+                })
+            })
+        });
+        match material {
+            Some(material) => {
+                entity
+                    .insert(MeshMaterial3d(material))
+                    .remove::<PendingVoxelMaterial>();
+            }
+            None => {
+                entity.insert(PendingVoxelMaterial {
+                    standard_material,
+                    context,
+                });
+            }
+        }
+    });
+}
+
+/// Inserts the custom materials of meshes that were spawned before their context or standard material had loaded
+fn insert_pending_materials<F: VoxelMaterialFactory>(
+    mut commands: Commands,
+    pending: Query<(Entity, &PendingVoxelMaterial)>,
+    mut materials: ResMut<VoxelMaterials<F>>,
+    mut custom_materials: ResMut<Assets<F::Material>>,
+    contexts: Res<Assets<VoxelContext>>,
+    standard_materials: Res<Assets<StandardMaterial>>,
+) {
+    for (entity, pending) in pending.iter() {
+        let Some(material) = custom_material(
+            &mut materials,
+            &mut custom_materials,
+            &contexts,
+            &standard_materials,
+            &pending.standard_material,
+            &pending.context,
+        ) else {
+            continue;
+        };
+        commands
+            .entity(entity)
+            .insert(MeshMaterial3d(material))
+            .remove::<PendingVoxelMaterial>();
+    }
+}
+
+/// Returns the material built for `standard_material`, building it if there isn't one yet. Returns `None` if the
+/// context or the standard material haven't loaded.
+fn custom_material<F: VoxelMaterialFactory>(
+    materials: &mut VoxelMaterials<F>,
+    custom_materials: &mut Assets<F::Material>,
+    contexts: &Assets<VoxelContext>,
+    standard_materials: &Assets<StandardMaterial>,
+    standard_material: &Handle<StandardMaterial>,
+    context: &Handle<VoxelContext>,
+) -> Option<Handle<F::Material>> {
+    if let Some(handle) = materials
+        .materials
+        .get(&standard_material.id())
+        .and_then(|id| custom_materials.get_strong_handle(*id))
+    {
+        return Some(handle);
+    }
+    // forgets the materials that have been dropped since
+    materials
+        .materials
+        .retain(|_, id| custom_materials.contains(*id));
+    let palette = &contexts.get(context)?.palette;
+    let standard = standard_materials.get(standard_material)?;
+    let handle = custom_materials.add(materials.factory.create_material(palette, standard));
+    materials
+        .materials
+        .insert(standard_material.id(), handle.id());
+    Some(handle)
+}
@@ -0,0 +1,122 @@
+use bevy::math::{IVec3, UVec3};
+use block_mesh::{OrientedBlockFace, UnorientedQuad, VoxelVisibility, RIGHT_HANDED_Y_UP_CONFIG};
+use ndshape::{RuntimeShape, Shape};
+
+use super::voxel::VisibleVoxel;
+
+/// The vertex color brightness for each level of occlusion, from a corner enclosed on both sides to a fully exposed one
+const AO_BRIGHTNESS: [f32; 4] = [0.4, 0.6, 0.8, 1.0];
+
+/// The occlusion of each corner of the quad, in the order of [`OrientedBlockFace::quad_corners`].
+///
+/// Each corner is occluded by the opaque voxels in front of the face that share the corner: 3 if there are none,
+/// down to 0 if the corner is enclosed by voxels on both sides.
+pub(super) fn quad_occlusion(
+    voxels: &[VisibleVoxel],
+    shape: &RuntimeShape<u32, 3>,
+    face: &OrientedBlockFace,
+    quad: &UnorientedQuad,
+) -> [u8; 4] {
+    // block_mesh uses its own version of glam
+    let corners = face
+        .quad_corners(quad)
+        .map(|corner| IVec3::from(corner.as_ivec3().to_array()));
+    let normal = IVec3::from(face.signed_normal().to_array());
+    let u = (corners[1] - corners[0]) / quad.width as i32;
+    let v = (corners[2] - corners[0]) / quad.height as i32;
+    // the corners of faces pointing along a negative axis lie on the far side of the layer in front of the face
+    let layer_offset = normal.min(IVec3::ZERO);
+    let is_opaque = |point: IVec3| is_opaque(voxels, shape, point);
+    let mut occlusion = [0; 4];
+    for (index, (corner, (u_sign, v_sign))) in corners
+        .iter()
+        .zip([(-1, -1), (1, -1), (-1, 1), (1, 1)])
+        .enumerate()
+    {
+        // the voxel in front of the face at this corner, and the directions away from the quad
+        let front = *corner + layer_offset - u * (u_sign + 1) / 2 - v * (v_sign + 1) / 2;
+        let du = u * u_sign;
+        let dv = v * v_sign;
+        let side_1 = is_opaque(front + du);
+        let side_2 = is_opaque(front + dv);
+        let diagonal = is_opaque(front + du + dv);
+        occlusion[index] = if side_1 && side_2 {
+            0
+        } else {
+            3 - side_1 as u8 - side_2 as u8 - diagonal as u8
+        };
+    }
+    occlusion
+}
+
+/// The brightness of the corners of a quad with the given occlusion
+pub(super) fn vertex_brightness(occlusion: [u8; 4]) -> [f32; 4] {
+    occlusion.map(|level| AO_BRIGHTNESS[level as usize])
+}
+
+/// Quads are split along the diagonal between corners 1 and 2. When the other pair of corners is brighter, the quad is
+/// split between those instead, so that the occlusion is interpolated evenly across the face.
+pub(super) fn quad_indices(face: &OrientedBlockFace, start: u32, occlusion: [u8; 4]) -> [u32; 6] {
+    let indices = face.quad_mesh_indices(start);
+    if occlusion[0] + occlusion[3] <= occlusion[1] + occlusion[2] {
+        return indices;
+    }
+    let is_counter_clockwise = indices[1] == start + 1;
+    if is_counter_clockwise {
+        [start, start + 1, start + 3, start, start + 3, start + 2]
+    } else {
+        [start, start + 3, start + 1, start, start + 2, start + 3]
+    }
+}
+
+/// Greedy meshing merges faces of voxels with the same merge value, which would stretch the occlusion of the corners
+/// of the merged quad across it. Faces are only merged if they have the same occlusion at all four corners, and
+/// voxels with any face that is unevenly occluded are meshed on their own.
+pub(crate) fn restrict_merging(voxels: &mut [VisibleVoxel], shape: &RuntimeShape<u32, 3>) {
+    let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
+    let keys: Vec<u32> = (0..voxels.len())
+        .map(|index| {
+            if voxels[index].visibility == VoxelVisibility::Empty {
+                return 0;
+            }
+            let minimum = shape.delinearize(index as u32);
+            let position = UVec3::from(minimum).as_ivec3();
+            let quad = UnorientedQuad {
+                minimum,
+                width: 1,
+                height: 1,
+            };
+            let mut key = 0;
+            for (face_index, face) in faces.iter().enumerate() {
+                if is_opaque(
+                    voxels,
+                    shape,
+                    position + IVec3::from(face.signed_normal().to_array()),
+                ) {
+                    // hidden faces aren't meshed
+                    continue;
+                }
+                let occlusion = quad_occlusion(voxels, shape, face, &quad);
+                if occlusion.iter().any(|level| *level != occlusion[0]) {
+                    // unique to this voxel, so that it is never merged
+                    return (1 << 31) | index as u32;
+                }
+                key |= (occlusion[0] as u32) << (face_index * 2);
+            }
+            key
+        })
+        .collect();
+    for (voxel, key) in voxels.iter_mut().zip(keys) {
+        voxel.occlusion_key = key;
+    }
+}
+
+fn is_opaque(voxels: &[VisibleVoxel], shape: &RuntimeShape<u32, 3>, point: IVec3) -> bool {
+    let Ok(point) = UVec3::try_from(point) else {
+        return false;
+    };
+    if point.cmpge(UVec3::from(shape.as_array())).any() {
+        return false;
+    }
+    voxels[shape.linearize(point.into()) as usize].visibility == VoxelVisibility::Opaque
+}
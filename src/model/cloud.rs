@@ -8,7 +8,7 @@ use ndshape::Shape;
 
 use super::VoxelData;
 
-pub(crate) fn create_cloud_image(cloud_voxels: &Vec<f32>, data: &VoxelData) -> Image {
+pub(crate) fn create_cloud_image(cloud_voxels: &[f32], data: &VoxelData) -> Image {
     let model_size: UVec3 = data.shape.as_array().map(|v| v - 2).into();
     let image_size = Extent3d {
         width: model_size.x,
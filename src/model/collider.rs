@@ -0,0 +1,281 @@
+use bevy::{
+    asset::{AssetEvent, Assets},
+    ecs::{
+        change_detection::DetectChanges,
+        component::Component,
+        event::EventReader,
+        system::{Query, Res},
+        world::Ref,
+    },
+    math::{UVec3, Vec3},
+    utils::HashSet,
+};
+use block_mesh::{
+    greedy_quads, GreedyQuadsBuffer, MergeVoxel, Voxel as BlockyVoxel, VoxelVisibility,
+    RIGHT_HANDED_Y_UP_CONFIG,
+};
+use ndshape::{RuntimeShape, Shape};
+
+use super::{RawVoxel, VoxelContext, VoxelModel, VoxelPalette, VoxelQueryable};
+use crate::VoxelModelInstance;
+
+/// An axis-aligned box covering a region of solid voxels, in the local space of the model's mesh
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxelColliderBox {
+    /// The center of the box
+    pub center: Vec3,
+    /// Half of the length of each side of the box
+    pub half_size: Vec3,
+}
+
+/// A triangle mesh enclosing the solid voxels of a model, in the local space of the model's mesh
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VoxelColliderMesh {
+    /// The positions of the vertices
+    pub vertices: Vec<Vec3>,
+    /// The vertex indices of each triangle, wound counter-clockwise when viewed from outside the model
+    pub indices: Vec<[u32; 3]>,
+}
+
+impl VoxelModel {
+    /// Merges the solid voxels of the model into as few axis-aligned boxes as possible, for building a compound collider.
+    ///
+    /// ### Arguments
+    /// * `palette` - the palette of the model's [`VoxelContext`]. Cloud voxels are never solid.
+    /// * `include_translucent` - whether translucent voxels such as glass are solid
+    pub fn collider_boxes(
+        &self,
+        palette: &VoxelPalette,
+        include_translucent: bool,
+    ) -> Vec<VoxelColliderBox> {
+        let solid = SolidVoxels::new(self, palette, include_translucent);
+        let size = solid.size;
+        let mut covered = vec![false; solid.voxels.len()];
+        let mut boxes = Vec::new();
+        let is_free =
+            |covered: &[bool], point: UVec3| solid.is_solid(point) && !covered[solid.index(point)];
+        for z in 0..size.z {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let start = UVec3::new(x, y, z);
+                    if !is_free(&covered, start) {
+                        continue;
+                    }
+                    // grow the box along x, then y, then z, for as long as the whole face is free
+                    let mut end = start + UVec3::ONE;
+                    while end.x < size.x && is_free(&covered, UVec3::new(end.x, y, z)) {
+                        end.x += 1;
+                    }
+                    while end.y < size.y
+                        && (start.x..end.x).all(|x| is_free(&covered, UVec3::new(x, end.y, z)))
+                    {
+                        end.y += 1;
+                    }
+                    while end.z < size.z
+                        && (start.x..end.x).all(|x| {
+                            (start.y..end.y).all(|y| is_free(&covered, UVec3::new(x, y, end.z)))
+                        })
+                    {
+                        end.z += 1;
+                    }
+                    for cz in start.z..end.z {
+                        for cy in start.y..end.y {
+                            for cx in start.x..end.x {
+                                covered[solid.index(UVec3::new(cx, cy, cz))] = true;
+                            }
+                        }
+                    }
+                    let min = solid.local_position(start.as_vec3());
+                    let max = solid.local_position(end.as_vec3());
+                    boxes.push(VoxelColliderBox {
+                        center: (min + max) * 0.5,
+                        half_size: (max - min) * 0.5,
+                    });
+                }
+            }
+        }
+        boxes
+    }
+
+    /// Generates a triangle mesh of the outer faces of the model's solid voxels, with coplanar faces merged.
+    ///
+    /// ### Arguments
+    /// * `palette` - the palette of the model's [`VoxelContext`]. Cloud voxels are never solid.
+    /// * `include_translucent` - whether translucent voxels such as glass are solid
+    pub fn collider_mesh(
+        &self,
+        palette: &VoxelPalette,
+        include_translucent: bool,
+    ) -> VoxelColliderMesh {
+        let solid = SolidVoxels::new(self, palette, include_translucent);
+        // the mesher only meshes faces between voxels, so the grid has an extra layer of empty voxels on each side
+        let padded_size = solid.size + UVec3::splat(2);
+        let shape = RuntimeShape::<u32, 3>::new(padded_size.into());
+        let voxels: Vec<ColliderVoxel> = (0..shape.size())
+            .map(|index| {
+                let point = UVec3::from(shape.delinearize(index));
+                let is_solid = point.cmpge(UVec3::ONE).all()
+                    && point.cmple(solid.size).all()
+                    && solid.is_solid(point - UVec3::ONE);
+                ColliderVoxel(is_solid)
+            })
+            .collect();
+        let mut buffer = GreedyQuadsBuffer::new(voxels.len());
+        let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
+        greedy_quads(
+            &voxels,
+            &shape,
+            [0; 3],
+            (padded_size - UVec3::ONE).into(),
+            &faces,
+            &mut buffer,
+        );
+        let mut mesh = VoxelColliderMesh::default();
+        for (group, face) in buffer.quads.groups.iter().zip(faces.iter()) {
+            for quad in group.iter() {
+                let first = mesh.vertices.len() as u32;
+                mesh.vertices.extend(
+                    face.quad_mesh_positions(quad, 1.0)
+                        .map(|position| solid.local_position(Vec3::from(position) - Vec3::ONE)),
+                );
+                let [a, b, c, d, e, f] = face.quad_mesh_indices(first);
+                mesh.indices.push([a, b, c]);
+                mesh.indices.push([d, e, f]);
+            }
+        }
+        mesh
+    }
+}
+
+/// The collider shapes of a [`VoxelModelInstance`], kept up to date with its voxel data.
+///
+/// Insert this into an instance (such as from a [`crate::VoxelInstanceSpawned`] observer), and the plugin will fill
+/// in [`VoxelCollider::boxes`] and [`VoxelCollider::mesh`] from the first model of the instance. They are regenerated
+/// whenever the model is modified, such as by [`crate::ModifyVoxelCommandsExt::modify_voxel_model`], so a physics
+/// integration can rebuild its collider when this component changes.
+#[derive(Component, Clone, Debug, Default)]
+pub struct VoxelCollider {
+    /// Whether translucent voxels such as glass are solid. Cloud voxels are never solid.
+    pub include_translucent: bool,
+    /// The solid voxels merged into axis-aligned boxes. See [`VoxelModel::collider_boxes`].
+    pub boxes: Vec<VoxelColliderBox>,
+    /// A triangle mesh of the solid voxels. See [`VoxelModel::collider_mesh`].
+    pub mesh: VoxelColliderMesh,
+}
+
+impl VoxelCollider {
+    /// A collider that will be filled in once it is inserted into a [`VoxelModelInstance`]
+    pub fn new(include_translucent: bool) -> Self {
+        Self {
+            include_translucent,
+            ..Default::default()
+        }
+    }
+}
+
+pub(crate) fn update_colliders(
+    mut events: EventReader<AssetEvent<VoxelModel>>,
+    models: Res<Assets<VoxelModel>>,
+    contexts: Res<Assets<VoxelContext>>,
+    mut query: Query<(&mut VoxelCollider, Ref<VoxelModelInstance>)>,
+) {
+    let changed: HashSet<_> = events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+    for (mut collider, instance) in query.iter_mut() {
+        let Some(model_handle) = instance.models.first() else {
+            continue;
+        };
+        let needs_update =
+            collider.is_added() || instance.is_changed() || changed.contains(&model_handle.id());
+        if !needs_update {
+            continue;
+        }
+        let (Some(model), Some(context)) =
+            (models.get(model_handle), contexts.get(&instance.context))
+        else {
+            continue;
+        };
+        let include_translucent = collider.include_translucent;
+        collider.boxes = model.collider_boxes(&context.palette, include_translucent);
+        collider.mesh = model.collider_mesh(&context.palette, include_translucent);
+    }
+}
+
+/// Which of the model's voxels are solid, without the padding that the model's data may have
+struct SolidVoxels {
+    size: UVec3,
+    voxels: Vec<bool>,
+    voxel_size: f32,
+    offset: Vec3,
+}
+
+impl SolidVoxels {
+    fn new(model: &VoxelModel, palette: &VoxelPalette, include_translucent: bool) -> Self {
+        let data = &model.data;
+        let size = data.size().as_uvec3();
+        let leading_padding = UVec3::splat(data.padding() / 2);
+        let mut voxels = vec![false; (size.x * size.y * size.z) as usize];
+        for z in 0..size.z {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let point = UVec3::new(x, y, z);
+                    let raw = &data.voxels
+                        [data.shape.linearize((point + leading_padding).into()) as usize];
+                    if *raw == RawVoxel::EMPTY {
+                        continue;
+                    }
+                    let index = raw.0 as usize;
+                    let is_cloud = palette.density_for_voxel[index].is_some();
+                    let is_translucent = palette.indices_of_refraction[index].is_some();
+                    voxels[(x + size.x * (y + size.y * z)) as usize] =
+                        !is_cloud && (include_translucent || !is_translucent);
+                }
+            }
+        }
+        Self {
+            size,
+            voxels,
+            voxel_size: data.settings.voxel_size,
+            offset: data.model_size() * data.settings.mesh_offset.0,
+        }
+    }
+
+    fn index(&self, point: UVec3) -> usize {
+        (point.x + self.size.x * (point.y + self.size.y * point.z)) as usize
+    }
+
+    fn is_solid(&self, point: UVec3) -> bool {
+        self.voxels[self.index(point)]
+    }
+
+    /// Converts a position in voxel space to the local space of the model's mesh
+    fn local_position(&self, voxel_position: Vec3) -> Vec3 {
+        voxel_position * self.voxel_size - self.offset
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct ColliderVoxel(bool);
+
+impl BlockyVoxel for ColliderVoxel {
+    fn get_visibility(&self) -> VoxelVisibility {
+        if self.0 {
+            VoxelVisibility::Opaque
+        } else {
+            VoxelVisibility::Empty
+        }
+    }
+}
+
+impl MergeVoxel for ColliderVoxel {
+    type MergeValue = bool;
+
+    fn merge_value(&self) -> Self::MergeValue {
+        self.0
+    }
+}
@@ -1,15 +1,16 @@
-use bevy::{
-    image::Image,
-    math::{IVec3, UVec3},
-    render::mesh::Mesh,
-};
+use bevy::math::{IVec3, UVec3};
+#[cfg(feature = "render")]
 use block_mesh::VoxelVisibility;
 use ndshape::{RuntimeShape, Shape};
 use std::fmt::Debug;
 
 use crate::VoxLoaderSettings;
+#[cfg(feature = "render")]
+use crate::{MeshingMode, UnitOffset};
 
-use super::{voxel::VisibleVoxel, RawVoxel};
+#[cfg(feature = "render")]
+use super::voxel::VisibleVoxel;
+use super::RawVoxel;
 
 /// The voxel data used to create a mesh and a material.
 #[derive(Clone)]
@@ -71,16 +72,20 @@ impl VoxelData {
         }
     }
 
+    #[cfg(feature = "render")]
     pub(crate) fn remesh(
         &self,
-        ior_for_voxel: &[Option<f32>],
-        density_for_voxel: &[Option<f32>],
-    ) -> (Option<Mesh>, Option<f32>, Option<Image>) {
+        palette: &super::VoxelPalette,
+    ) -> (
+        Option<bevy::render::mesh::Mesh>,
+        Option<f32>,
+        Option<bevy::image::Image>,
+    ) {
         let (visible_voxels, average_ior, needs_meshing) =
-            self.visible_voxels(ior_for_voxel, density_for_voxel);
-        let (cloud_voxels, has_cloud) = self.cloud_voxels(density_for_voxel);
+            self.visible_voxels(&palette.indices_of_refraction, &palette.density_for_voxel);
+        let (cloud_voxels, has_cloud) = self.cloud_voxels(&palette.density_for_voxel);
         let maybe_mesh = if needs_meshing {
-            Some(super::mesh::mesh_model(&visible_voxels, self))
+            Some(super::mesh::mesh_model(&visible_voxels, self, palette))
         } else {
             None
         };
@@ -92,19 +97,94 @@ impl VoxelData {
         (maybe_mesh, average_ior, maybe_image)
     }
 
+    /// Meshes each level of detail requested by [`VoxLoaderSettings::lod_levels`], with each level halving the resolution
+    /// of the one before. Stops early once a level is reduced to a single voxel.
+    #[cfg(feature = "render")]
+    pub(crate) fn lod_meshes(
+        &self,
+        palette: &super::VoxelPalette,
+    ) -> Vec<bevy::render::mesh::Mesh> {
+        let max_size = self._size().max_element().max(1) as u32;
+        (1..=self.settings.lod_levels)
+            .map_while(|level| 1u32.checked_shl(level))
+            .take_while(|factor| max_size > factor / 2)
+            .map(|factor| {
+                let lod = self.downsampled(factor);
+                let (visible_voxels, _, _) =
+                    lod.visible_voxels(&palette.indices_of_refraction, &palette.density_for_voxel);
+                super::mesh::mesh_model(&visible_voxels, &lod, palette)
+            })
+            .collect()
+    }
+
+    /// A copy of the model at a lower resolution, where each cell of `factor`³ voxels becomes a single voxel that is
+    /// `factor` times larger. The cell takes the most common palette index among its voxels, and is filled if any of
+    /// its voxels are, so that thin features keep their silhouette.
+    ///
+    /// The cells at the maximum corner extend past the model if its size isn't a multiple of `factor`, so the mesh
+    /// offset is scaled to keep the minimum corner of the copy in the same place as the model's.
+    #[cfg(feature = "render")]
+    pub(crate) fn downsampled(&self, factor: u32) -> VoxelData {
+        let size = self._size().as_uvec3();
+        let lod_size = (size + UVec3::splat(factor - 1)) / factor;
+        let mut settings = self.settings.clone();
+        settings.voxel_size *= factor as f32;
+        let covered_size = (lod_size * factor).max(UVec3::ONE).as_vec3();
+        settings.mesh_offset = UnitOffset(settings.mesh_offset.0 * size.as_vec3() / covered_size);
+        let mut lod = VoxelData::new(lod_size, settings);
+        let leading_padding = UVec3::splat(self.padding() / 2);
+        let lod_leading_padding = UVec3::splat(lod.padding() / 2);
+        for x in 0..lod_size.x {
+            for y in 0..lod_size.y {
+                for z in 0..lod_size.z {
+                    let cell = UVec3::new(x, y, z);
+                    let start = cell * factor;
+                    let end = (start + UVec3::splat(factor)).min(size);
+                    let mut counts = [0u32; 255];
+                    for cx in start.x..end.x {
+                        for cy in start.y..end.y {
+                            for cz in start.z..end.z {
+                                let point = UVec3::new(cx, cy, cz) + leading_padding;
+                                let voxel =
+                                    &self.voxels[self.shape.linearize(point.into()) as usize];
+                                if *voxel != RawVoxel::EMPTY {
+                                    counts[voxel.0 as usize] += 1;
+                                }
+                            }
+                        }
+                    }
+                    let Some((index, _)) = counts
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, count)| **count > 0)
+                        .max_by_key(|(_, count)| **count)
+                    else {
+                        continue;
+                    };
+                    let point = cell + lod_leading_padding;
+                    let lod_index = lod.shape.linearize(point.into()) as usize;
+                    lod.voxels[lod_index] = RawVoxel(index as u8);
+                }
+            }
+        }
+        lod
+    }
+
     /// Returns the [`VoxelVisibility`] of each Voxel, and, if the model contains
     /// translucent voxels, the average Index of Refraction.
+    #[cfg(feature = "render")]
     pub(crate) fn visible_voxels(
         &self,
         ior_for_voxel: &[Option<f32>],
         density_for_voxel: &[Option<f32>],
     ) -> (Vec<VisibleVoxel>, Option<f32>, bool) {
         let mut refraction_indices: Vec<f32> = Vec::new();
-        let voxels: Vec<VisibleVoxel> = self
+        let mut voxels: Vec<VisibleVoxel> = self
             .voxels
             .iter()
             .map(|v| VisibleVoxel {
                 index: v.0,
+                occlusion_key: 0,
                 visibility: if *v == RawVoxel::EMPTY {
                     VoxelVisibility::Empty
                 } else if let Some(ior) = ior_for_voxel[v.0 as usize] {
@@ -131,9 +211,13 @@ impl VoxelData {
         let needs_meshing = voxels
             .iter()
             .any(|&v| v.visibility != VoxelVisibility::Empty);
+        if self.settings.ambient_occlusion && self.settings.meshing_mode == MeshingMode::Blocky {
+            super::ambient_occlusion::restrict_merging(&mut voxels, &self.shape);
+        }
         (voxels, average_ior, needs_meshing)
     }
 
+    #[cfg(feature = "render")]
     pub(crate) fn cloud_voxels(&self, density_for_voxel: &[Option<f32>]) -> (Vec<f32>, bool) {
         let mut has_cloud: bool = false;
         let max_bound = self.shape.as_array().map(|v| v - 1);
@@ -1,17 +1,120 @@
 use bevy::{
     math::Vec3,
     render::{
-        mesh::{Indices, Mesh, VertexAttributeValues},
+        mesh::{Indices, Mesh, MeshVertexAttribute, VertexAttributeValues},
         render_asset::RenderAssetUsages,
-        render_resource::PrimitiveTopology,
+        render_resource::{PrimitiveTopology, VertexFormat},
     },
 };
 use block_mesh::{greedy_quads, GreedyQuadsBuffer, RIGHT_HANDED_Y_UP_CONFIG};
 use ndshape::Shape;
 
-use super::{voxel::VisibleVoxel, VoxelData, VoxelQueryable};
+use super::{
+    ambient_occlusion::{quad_indices, quad_occlusion, vertex_brightness},
+    surface_nets::mesh_surface,
+    voxel::VisibleVoxel,
+    VoxelData, VoxelPalette, VoxelQueryable,
+};
+use crate::{MeshColorMode, MeshingMode};
+
+/// A vertex attribute holding the index of the [`crate::VoxelElement`] in the [`VoxelPalette`] that each vertex was
+/// meshed from. Note that this is one less than the value of the [`crate::Voxel`], as the empty voxel isn't in the
+/// palette. Only written when [`crate::VoxLoaderSettings::color_mode`] is [`MeshColorMode::VertexColors`].
+pub const ATTRIBUTE_PALETTE_INDEX: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_PaletteIndex", 2_417_905_117, VertexFormat::Uint32);
+
+pub(crate) fn mesh_model(
+    voxels: &[VisibleVoxel],
+    data: &VoxelData,
+    palette: &VoxelPalette,
+) -> Mesh {
+    let buffers = match data.settings.meshing_mode {
+        MeshingMode::Blocky => mesh_blocks(voxels, data),
+        MeshingMode::SurfaceNets => mesh_surface(voxels, data),
+    };
+    buffers.into_mesh(data, palette)
+}
+
+/// The vertices and triangles produced by a mesher, before the colors are applied
+pub(super) struct MeshBuffers {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+    /// The palette index of the voxel that each vertex belongs to
+    pub palette_indices: Vec<u8>,
+    /// The brightness of each vertex from baked ambient occlusion. Empty if ambient occlusion isn't baked.
+    pub brightness: Vec<f32>,
+}
+
+impl MeshBuffers {
+    fn into_mesh(self, data: &VoxelData, palette: &VoxelPalette) -> Mesh {
+        let uvs: Vec<[f32; 2]> = self
+            .palette_indices
+            .iter()
+            .map(|index| palette_uv(*index))
+            .collect();
+        let brightness = |vertex: usize| self.brightness.get(vertex).copied().unwrap_or(1.0);
+        let colors: Option<Vec<[f32; 4]>> = match data.settings.color_mode {
+            MeshColorMode::PaletteTexture => (!self.brightness.is_empty()).then(|| {
+                self.brightness
+                    .iter()
+                    .map(|brightness| [*brightness, *brightness, *brightness, 1.0])
+                    .collect()
+            }),
+            MeshColorMode::VertexColors => Some(
+                self.palette_indices
+                    .iter()
+                    .enumerate()
+                    .map(|(vertex, index)| {
+                        let color = palette.elements[*index as usize].color.to_linear();
+                        let brightness = brightness(vertex);
+                        [
+                            color.red * brightness,
+                            color.green * brightness,
+                            color.blue * brightness,
+                            color.alpha,
+                        ]
+                    })
+                    .collect(),
+            ),
+        };
+
+        let mut render_mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::RENDER_WORLD,
+        );
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            VertexAttributeValues::Float32x3(self.positions),
+        );
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            VertexAttributeValues::Float32x3(self.normals),
+        );
+        render_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::Float32x2(uvs));
+        if let Some(colors) = colors {
+            render_mesh.insert_attribute(
+                Mesh::ATTRIBUTE_COLOR,
+                VertexAttributeValues::Float32x4(colors),
+            );
+        }
+        if data.settings.color_mode == MeshColorMode::VertexColors {
+            render_mesh.insert_attribute(
+                ATTRIBUTE_PALETTE_INDEX,
+                VertexAttributeValues::Uint32(
+                    self.palette_indices
+                        .iter()
+                        .map(|index| *index as u32)
+                        .collect(),
+                ),
+            );
+        }
+        render_mesh.insert_indices(Indices::U32(self.indices));
+        render_mesh
+    }
+}
 
-pub(crate) fn mesh_model(voxels: &[VisibleVoxel], data: &VoxelData) -> Mesh {
+fn mesh_blocks(voxels: &[VisibleVoxel], data: &VoxelData) -> MeshBuffers {
     let mut greedy_quads_buffer = GreedyQuadsBuffer::new(data.shape.size() as usize);
     let quads_config = RIGHT_HANDED_Y_UP_CONFIG;
     greedy_quads(
@@ -22,22 +125,22 @@ pub(crate) fn mesh_model(voxels: &[VisibleVoxel], data: &VoxelData) -> Mesh {
         &quads_config.faces,
         &mut greedy_quads_buffer,
     );
-    let offset = data.model_size() * data.settings.mesh_offset.0; // center the mesh
-    let leading_padding = (data.padding() / 2) as f32 * data.settings.voxel_size; // corrects the 1 offset introduced by the meshing.
-    let position_offset = offset + Vec3::splat(leading_padding);
+    let position_offset = position_offset(data);
 
     let num_indices = greedy_quads_buffer.quads.num_quads() * 6;
     let num_vertices = greedy_quads_buffer.quads.num_quads() * 4;
 
-    let mut indices = Vec::with_capacity(num_indices);
-    let mut positions = Vec::with_capacity(num_vertices);
-    let mut normals = Vec::with_capacity(num_vertices);
-    let mut uvs = Vec::with_capacity(num_vertices);
-
-    let mut render_mesh = Mesh::new(
-        PrimitiveTopology::TriangleList,
-        RenderAssetUsages::RENDER_WORLD,
-    );
+    let mut buffers = MeshBuffers {
+        positions: Vec::with_capacity(num_vertices),
+        normals: Vec::with_capacity(num_vertices),
+        indices: Vec::with_capacity(num_indices),
+        palette_indices: Vec::with_capacity(num_vertices),
+        brightness: Vec::with_capacity(if data.settings.ambient_occlusion {
+            num_vertices
+        } else {
+            0
+        }),
+    };
 
     for (group, face) in greedy_quads_buffer
         .quads
@@ -47,8 +150,21 @@ pub(crate) fn mesh_model(voxels: &[VisibleVoxel], data: &VoxelData) -> Mesh {
     {
         for quad in group.iter() {
             let palette_index = voxels[data.shape.linearize(quad.minimum) as usize].index;
-            indices.extend_from_slice(&face.quad_mesh_indices(positions.len() as u32));
-            positions.extend_from_slice(
+            let start = buffers.positions.len() as u32;
+            if data.settings.ambient_occlusion {
+                let occlusion = quad_occlusion(voxels, &data.shape, face, quad);
+                buffers
+                    .indices
+                    .extend_from_slice(&quad_indices(face, start, occlusion));
+                buffers
+                    .brightness
+                    .extend_from_slice(&vertex_brightness(occlusion));
+            } else {
+                buffers
+                    .indices
+                    .extend_from_slice(&face.quad_mesh_indices(start));
+            }
+            buffers.positions.extend_from_slice(
                 &face
                     .quad_mesh_positions(quad, data.settings.voxel_size)
                     .map(|position| {
@@ -59,25 +175,26 @@ pub(crate) fn mesh_model(voxels: &[VisibleVoxel], data: &VoxelData) -> Mesh {
                         ]
                     }),
             );
-            let u = ((palette_index % 16) as f32 + 0.5) / 16.0;
-            let v = ((palette_index / 16) as f32 + 0.5) / 16.0;
-            uvs.extend_from_slice(&[[u, v], [u, v], [u, v], [u, v]]);
-            normals.extend_from_slice(&face.quad_mesh_normals());
+            buffers
+                .palette_indices
+                .extend_from_slice(&[palette_index; 4]);
+            buffers.normals.extend_from_slice(&face.quad_mesh_normals());
         }
     }
+    buffers
+}
 
-    render_mesh.insert_attribute(
-        Mesh::ATTRIBUTE_POSITION,
-        VertexAttributeValues::Float32x3(positions),
-    );
-
-    render_mesh.insert_attribute(
-        Mesh::ATTRIBUTE_NORMAL,
-        VertexAttributeValues::Float32x3(normals),
-    );
-    render_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::Float32x2(uvs));
-
-    render_mesh.insert_indices(Indices::U32(indices.clone()));
+/// The offset to subtract from positions in the padded voxel grid, scaled by the voxel size, to place them in the local
+/// space of the mesh
+pub(super) fn position_offset(data: &VoxelData) -> Vec3 {
+    let offset = data.model_size() * data.settings.mesh_offset.0; // center the mesh
+    let leading_padding = (data.padding() / 2) as f32 * data.settings.voxel_size; // corrects the 1 offset introduced by the meshing.
+    offset + Vec3::splat(leading_padding)
+}
 
-    render_mesh
+/// The UV of the palette texture's texel for `palette_index`
+fn palette_uv(palette_index: u8) -> [f32; 2] {
+    let u = ((palette_index % 16) as f32 + 0.5) / 16.0;
+    let v = ((palette_index / 16) as f32 + 0.5) / 16.0;
+    [u, v]
 }
@@ -1,31 +1,46 @@
+#[cfg(any(feature = "render", feature = "generate_voxels"))]
+use bevy::asset::Assets;
+#[cfg(any(feature = "render", feature = "generate_voxels"))]
+use bevy::asset::Handle;
+use bevy::{asset::Asset, reflect::TypePath};
+#[cfg(feature = "generate_voxels")]
 use bevy::{
-    asset::{Asset, Assets, Handle},
     ecs::{
         system::{In, ResMut},
         world::World,
     },
-    image::Image,
-    pbr::StandardMaterial,
     prelude::Res,
-    reflect::TypePath,
-    render::mesh::Mesh,
 };
+#[cfg(feature = "render")]
+use bevy::{image::Image, pbr::StandardMaterial, render::mesh::Mesh};
 
 pub use self::{data::VoxelData, voxel::Voxel};
-pub(crate) use palette::MaterialProperty;
+#[cfg(feature = "render")]
+use crate::MeshColorMode;
+#[cfg(feature = "render")]
+pub(crate) use palette::{material_for_color_mode, MaterialProperty};
 pub(crate) use voxel::RawVoxel;
+#[cfg(feature = "render")]
+mod ambient_occlusion;
+#[cfg(feature = "colliders")]
+pub(super) mod collider;
 pub(super) mod data;
+#[cfg(feature = "render")]
 pub(super) mod mesh;
 #[cfg(feature = "modify_voxels")]
 pub(super) mod modify;
-#[cfg(feature = "modify_voxels")]
 pub(super) mod queryable;
 #[cfg(feature = "generate_voxels")]
 pub(super) mod sdf;
-#[cfg(feature = "modify_voxels")]
-pub use self::queryable::VoxelQueryable;
+#[cfg(feature = "render")]
+mod surface_nets;
+pub use self::queryable::{VoxelQueryable, VoxelRayHit};
 mod palette;
+#[cfg(feature = "render")]
+pub(super) mod palette_animation;
+pub(crate) use palette::color_from_rgba8;
 pub use palette::{VoxelElement, VoxelPalette};
+#[cfg(feature = "render")]
 pub(super) mod cloud;
 mod voxel;
 
@@ -36,14 +51,122 @@ pub struct VoxelModel {
     pub name: String,
     /// The voxel data used to generate the mesh
     pub(crate) data: VoxelData,
-    /// Optional handle to the model's mesh if the VoxelData contains solid or transmissive voxels
+    /// Optional handle to the model's mesh if the VoxelData contains solid or transmissive voxels.
+    /// Always `None` if the model was loaded with [`crate::VoxLoaderSettings::data_only`].
+    #[cfg(feature = "render")]
     pub mesh: Option<Handle<Mesh>>,
-    /// Optional handle to the model's material if the VoxelData contains solid or transmissive voxels
+    /// Handles to the meshes for each level of detail requested by [`crate::VoxLoaderSettings::lod_levels`], starting at half
+    /// the resolution of [`VoxelModel::mesh`]. Empty if the model has no mesh.
+    #[cfg(feature = "render")]
+    pub lods: Vec<Handle<Mesh>>,
+    /// Optional handle to the model's material if the VoxelData contains solid or transmissive voxels.
+    /// Always `None` if the model was loaded with [`crate::VoxLoaderSettings::data_only`].
+    #[cfg(feature = "render")]
     pub material: Option<Handle<StandardMaterial>>,
-    /// Optional handle to the 3D cloud image if the VoxelData contains cloud voxels
+    /// Optional handle to the 3D cloud image if the VoxelData contains cloud voxels.
+    /// Always `None` if the model was loaded with [`crate::VoxLoaderSettings::data_only`].
+    #[cfg(feature = "render")]
     pub cloud_image: Option<Handle<Image>>,
     /// True if the model contains translucent voxels.
+    #[cfg(feature = "render")]
+    #[cfg_attr(not(feature = "modify_voxels"), allow(dead_code))]
     pub(crate) has_translucency: bool,
+    /// True if the model was loaded with [`crate::VoxLoaderSettings::lazy_meshing`] and has not been spawned yet.
+    #[cfg(feature = "render")]
+    pub(crate) awaiting_mesh: bool,
+}
+
+impl VoxelModel {
+    /// A model that is loaded without a mesh, material or cloud image
+    pub(crate) fn without_mesh(name: String, data: VoxelData) -> Self {
+        Self {
+            name,
+            data,
+            #[cfg(feature = "render")]
+            mesh: None,
+            #[cfg(feature = "render")]
+            lods: Vec::new(),
+            #[cfg(feature = "render")]
+            material: None,
+            #[cfg(feature = "render")]
+            cloud_image: None,
+            #[cfg(feature = "render")]
+            has_translucency: false,
+            #[cfg(feature = "render")]
+            awaiting_mesh: false,
+        }
+    }
+}
+
+#[cfg(feature = "render")]
+impl VoxelModel {
+    /// A model that will be meshed the first time an instance of it is spawned
+    pub(crate) fn awaiting_mesh(name: String, data: VoxelData) -> Self {
+        Self {
+            awaiting_mesh: true,
+            ..Self::without_mesh(name, data)
+        }
+    }
+
+    /// The material for the model's mesh when it is rendered with the palette of `context`, rather than the context it
+    /// was meshed with. Translucent models get a copy of the context's transmissive material, with the index of
+    /// refraction and thickness of their current material. Returns `None` if the model has no material, or if the
+    /// materials aren't loaded.
+    pub(crate) fn material_for_context(
+        &self,
+        context: &VoxelContext,
+        materials: &mut Assets<StandardMaterial>,
+    ) -> Option<Handle<StandardMaterial>> {
+        let current = self.material.as_ref()?;
+        let color_mode = self.data.settings.color_mode;
+        if !self.has_translucency {
+            return Some(context.opaque_material(color_mode));
+        }
+        let (ior, thickness) = materials
+            .get(current)
+            .map(|material| (material.ior, material.thickness))?;
+        let mut transmissive_material = context.transmissive_material(materials, color_mode)?;
+        transmissive_material.ior = ior;
+        transmissive_material.thickness = thickness;
+        Some(materials.add(transmissive_material))
+    }
+
+    /// Meshes the voxel data, adding the mesh, levels of detail, material and cloud image to their asset collections.
+    /// Returns `None` if the context's transmissive material isn't loaded.
+    pub(crate) fn create_render_assets(
+        &mut self,
+        context: &VoxelContext,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+        images: &mut Assets<Image>,
+    ) -> Option<()> {
+        let (maybe_mesh, average_ior, maybe_cloud) = self.data.remesh(&context.palette);
+        self.material = match (&maybe_mesh, average_ior) {
+            (None, _) => None,
+            (Some(_), Some(ior)) => {
+                let mut transmissive_material =
+                    context.transmissive_material(materials, self.data.settings.color_mode)?;
+                transmissive_material.ior = ior;
+                transmissive_material.thickness = self.data.size().min_element() as f32;
+                Some(materials.add(transmissive_material))
+            }
+            (Some(_), None) => Some(context.opaque_material(self.data.settings.color_mode)),
+        };
+        self.lods = if maybe_mesh.is_some() {
+            self.data
+                .lod_meshes(&context.palette)
+                .into_iter()
+                .map(|mesh| meshes.add(mesh))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        self.mesh = maybe_mesh.map(|mesh| meshes.add(mesh));
+        self.cloud_image = maybe_cloud.map(|image| images.add(image));
+        self.has_translucency = average_ior.is_some();
+        self.awaiting_mesh = false;
+        Some(())
+    }
 }
 
 #[cfg(feature = "generate_voxels")]
@@ -62,53 +185,75 @@ impl VoxelModel {
 
     fn add_model(
         In((data, name, context_handle)): In<(VoxelData, String, Handle<VoxelContext>)>,
-        mut meshes: ResMut<Assets<Mesh>>,
-        mut materials: ResMut<Assets<StandardMaterial>>,
-        mut images: ResMut<Assets<Image>>,
+        #[cfg(feature = "render")] mut meshes: ResMut<Assets<Mesh>>,
+        #[cfg(feature = "render")] mut materials: ResMut<Assets<StandardMaterial>>,
+        #[cfg(feature = "render")] mut images: ResMut<Assets<Image>>,
         mut models: ResMut<Assets<VoxelModel>>,
         contexts: Res<Assets<VoxelContext>>,
     ) -> Option<(Handle<VoxelModel>, VoxelModel)> {
+        #[cfg_attr(not(feature = "render"), allow(unused_variables))]
         let context = contexts.get(&context_handle)?;
-        let (maybe_mesh, average_ior, maybe_cloud) = data.remesh(
-            &context.palette.indices_of_refraction,
-            &context.palette.density_for_voxel,
-        );
-        let mesh = maybe_mesh.map(|mesh| meshes.add(mesh));
-        let cloud_image = maybe_cloud.map(|image| images.add(image));
-        let material = if mesh.is_some() {
-            if let Some(ior) = average_ior {
-                let mut transmissive_material =
-                    materials.get(context.transmissive_material.id())?.clone();
-                transmissive_material.ior = ior;
-                transmissive_material.thickness = data.size().min_element() as f32;
-                Some(materials.add(transmissive_material))
-            } else {
-                Some(context.opaque_material.clone())
-            }
-        } else {
-            None
-        };
-        let model = VoxelModel {
-            name: name.clone(),
-            data,
-            mesh,
-            material,
-            cloud_image,
-            has_translucency: average_ior.is_some(),
-        };
+        #[cfg_attr(not(feature = "render"), allow(unused_mut))]
+        let mut model = VoxelModel::without_mesh(name, data);
+        #[cfg(feature = "render")]
+        if !model.data.settings.data_only {
+            model.create_render_assets(context, &mut meshes, &mut materials, &mut images)?;
+        }
         let model_handle = models.add(model.clone());
         Some((model_handle, model))
     }
 }
 
-/// A [`VoxelPalette`] that can be shared by multiple models, and handles to the [`StandardMaterial`]s derived from the palette.
+/// A [`VoxelPalette`] that can be shared by multiple models, and handles to the `StandardMaterial`s derived from the palette.
 #[derive(Asset, TypePath, Clone, Debug)]
 pub struct VoxelContext {
     /// The palette used by the models
     pub palette: VoxelPalette,
 
+    #[cfg(feature = "render")]
     pub(crate) opaque_material: Handle<StandardMaterial>,
+    #[cfg(feature = "render")]
     pub(crate) transmissive_material: Handle<StandardMaterial>,
+    /// The opaque material without the palette's base color texture, for meshes with vertex colors
+    #[cfg(feature = "render")]
+    pub(crate) vertex_color_material: Handle<StandardMaterial>,
+}
+
+impl VoxelContext {
+    /// A context for models that are loaded without meshes or materials
+    pub(crate) fn without_materials(palette: VoxelPalette) -> Self {
+        Self {
+            palette,
+            #[cfg(feature = "render")]
+            opaque_material: Handle::default(),
+            #[cfg(feature = "render")]
+            transmissive_material: Handle::default(),
+            #[cfg(feature = "render")]
+            vertex_color_material: Handle::default(),
+        }
+    }
+}
+
+#[cfg(feature = "render")]
+impl VoxelContext {
+    /// The material shared by opaque meshes with the given color mode
+    pub(crate) fn opaque_material(&self, color_mode: MeshColorMode) -> Handle<StandardMaterial> {
+        match color_mode {
+            MeshColorMode::PaletteTexture => self.opaque_material.clone(),
+            MeshColorMode::VertexColors => self.vertex_color_material.clone(),
+        }
+    }
+
+    /// A copy of the transmissive material for a mesh with the given color mode, to be given the mesh's index of
+    /// refraction. Returns `None` if the material isn't loaded.
+    pub(crate) fn transmissive_material(
+        &self,
+        materials: &Assets<StandardMaterial>,
+        color_mode: MeshColorMode,
+    ) -> Option<StandardMaterial> {
+        let material = materials.get(self.transmissive_material.id())?.clone();
+        Some(material_for_color_mode(material, color_mode))
+    }
 }
 
 #[cfg(feature = "generate_voxels")]
@@ -120,23 +265,54 @@ impl VoxelContext {
             .ok()
     }
 
+    /// Create a new context with a recolored copy of the palette of `context`. See [`VoxelPalette::remapped`].
+    ///
+    /// Assign the new context to [`crate::VoxelModelInstance::context`] to render an instance with the new colors,
+    /// sharing the meshes of the original models. Returns `None` if `context` isn't loaded.
+    pub fn recolored(
+        world: &mut World,
+        context: &Handle<VoxelContext>,
+        remap: &[(Voxel, Voxel)],
+    ) -> Option<Handle<VoxelContext>> {
+        let palette = world
+            .resource::<Assets<VoxelContext>>()
+            .get(context)?
+            .palette
+            .remapped(remap);
+        Self::new(world, palette)
+    }
+
     fn new_context(
         In(palette): In<VoxelPalette>,
-        mut images: ResMut<Assets<Image>>,
-        mut materials: ResMut<Assets<StandardMaterial>>,
+        #[cfg(feature = "render")] mut images: ResMut<Assets<Image>>,
+        #[cfg(feature = "render")] mut materials: ResMut<Assets<StandardMaterial>>,
         mut contexts: ResMut<Assets<VoxelContext>>,
     ) -> Handle<VoxelContext> {
-        let material = palette.create_material(&mut images);
-        let mut opaque_material = material.clone();
-        #[cfg(feature = "pbr_transmission_textures")]
-        {
-            opaque_material.specular_transmission_texture = None;
-        }
-        opaque_material.specular_transmission = 0.0;
+        #[cfg(feature = "render")]
+        let (opaque_material, transmissive_material, vertex_color_material) = {
+            let material = palette.create_material(&mut images);
+            let mut opaque_material = material.clone();
+            #[cfg(feature = "pbr_transmission_textures")]
+            {
+                opaque_material.specular_transmission_texture = None;
+            }
+            opaque_material.specular_transmission = 0.0;
+            let vertex_color_material =
+                material_for_color_mode(opaque_material.clone(), MeshColorMode::VertexColors);
+            (
+                materials.add(opaque_material),
+                materials.add(material),
+                materials.add(vertex_color_material),
+            )
+        };
         let context = VoxelContext {
             palette,
-            opaque_material: materials.add(opaque_material),
-            transmissive_material: materials.add(material),
+            #[cfg(feature = "render")]
+            opaque_material,
+            #[cfg(feature = "render")]
+            transmissive_material,
+            #[cfg(feature = "render")]
+            vertex_color_material,
         };
         contexts.add(context)
     }
@@ -1,11 +1,15 @@
 use bevy::{
-    asset::{Assets, Handle},
+    asset::Assets,
     ecs::{
-        system::{Commands, ResMut, SystemState},
+        system::Commands,
         world::{Command, World},
     },
-    image::Image,
     math::{IVec3, Vec3},
+};
+#[cfg(feature = "render")]
+use bevy::{
+    ecs::system::{ResMut, SystemState},
+    image::Image,
     pbr::StandardMaterial,
     prelude::Res,
     render::mesh::Mesh,
@@ -14,7 +18,9 @@ use ndshape::Shape;
 
 use crate::VoxelModelInstance;
 
-use super::{RawVoxel, Voxel, VoxelContext, VoxelModel, VoxelQueryable};
+#[cfg(feature = "render")]
+use super::VoxelContext;
+use super::{RawVoxel, Voxel, VoxelModel, VoxelQueryable};
 
 /// Command that programmatically modifies the voxels in a model.
 ///
@@ -100,48 +106,21 @@ struct ModifyVoxelModel {
 
 impl Command for ModifyVoxelModel {
     fn apply(self, world: &mut World) {
-        let mut perform = || -> Option<()> {
-            let mut system_state: SystemState<(
-                ResMut<Assets<Mesh>>,
-                ResMut<Assets<StandardMaterial>>,
-                ResMut<Assets<Image>>,
-                ResMut<Assets<VoxelModel>>,
-                Res<Assets<VoxelContext>>,
-            )> = SystemState::new(world);
-            let (mut meshes, mut materials, mut images, mut models, contexts) =
-                system_state.get_mut(world);
-            let context = contexts.get(self.instance.context.id())?;
-            let model = models.get_mut(self.instance.models[0].id())?;
-            let refraction_indices = &context.palette.indices_of_refraction;
-            let density_for_voxel = &context.palette.density_for_voxel;
-            self.modify_model(
-                model,
-                &mut meshes,
-                &mut materials,
-                &mut images,
-                context.opaque_material.clone(),
-                context.transmissive_material.clone(),
-                refraction_indices,
-                density_for_voxel,
-            );
-            Some(())
+        let mut models = world.resource_mut::<Assets<VoxelModel>>();
+        let Some(model) = models.get_mut(self.instance.models[0].id()) else {
+            return;
         };
-        perform();
+        self.modify_model(model);
+        #[cfg(feature = "render")]
+        // models awaiting their first spawn are meshed with the modified data when they are spawned
+        if !model.data.settings.data_only && !model.awaiting_mesh {
+            self.update_render_assets(world);
+        }
     }
 }
 
 impl ModifyVoxelModel {
-    fn modify_model(
-        &self,
-        model: &mut VoxelModel,
-        meshes: &mut Assets<Mesh>,
-        materials: &mut Assets<StandardMaterial>,
-        images: &mut Assets<Image>,
-        opaque_material: Handle<StandardMaterial>,
-        transmissive_material: Handle<StandardMaterial>,
-        refraction_indices: &[Option<f32>],
-        density_for_voxel: &[Option<f32>],
-    ) {
+    fn modify_model(&self, model: &mut VoxelModel) {
         let leading_padding = IVec3::splat(model.data.padding() as i32 / 2);
         let model_size = model.size();
         let region = self.region.clamped(model_size);
@@ -162,30 +141,68 @@ impl ModifyVoxelModel {
             }
         }
         model.data.voxels = updated;
-        let (maybe_mesh, average_ior, maybe_cloud) =
-            model.data.remesh(refraction_indices, &density_for_voxel);
+    }
+
+    /// Remeshes the modified model, and updates its material if its translucency has changed
+    #[cfg(feature = "render")]
+    fn update_render_assets(&self, world: &mut World) {
+        let mut perform = || -> Option<()> {
+            let mut system_state: SystemState<(
+                ResMut<Assets<Mesh>>,
+                ResMut<Assets<StandardMaterial>>,
+                ResMut<Assets<Image>>,
+                ResMut<Assets<VoxelModel>>,
+                Res<Assets<VoxelContext>>,
+            )> = SystemState::new(world);
+            let (mut meshes, mut materials, mut images, mut models, contexts) =
+                system_state.get_mut(world);
+            let context = contexts.get(self.instance.context.id())?;
+            let model = models.get_mut(self.instance.models[0].id())?;
+            Self::remesh_model(model, &mut meshes, &mut materials, &mut images, context);
+            Some(())
+        };
+        perform();
+    }
+
+    #[cfg(feature = "render")]
+    fn remesh_model(
+        model: &mut VoxelModel,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+        images: &mut Assets<Image>,
+        context: &VoxelContext,
+    ) {
+        let (maybe_mesh, average_ior, maybe_cloud) = model.data.remesh(&context.palette);
         if let Some(mesh_handle) = &model.mesh {
             // TODO handle handle being nil.
             if let Some(mesh) = maybe_mesh {
                 meshes.insert(mesh_handle, mesh);
             }
         }
+        for (lod_handle, lod) in model
+            .lods
+            .iter()
+            .zip(model.data.lod_meshes(&context.palette))
+        {
+            meshes.insert(lod_handle, lod);
+        }
         if let Some(image_handle) = &model.cloud_image {
             if let Some(cloud) = maybe_cloud {
                 images.insert(image_handle, cloud);
             }
         }
 
+        let color_mode = model.data.settings.color_mode;
         let has_translucency_old_value = model.has_translucency;
         model.has_translucency = average_ior.is_some();
         match (has_translucency_old_value, average_ior) {
             (true, Some(..)) | (false, None) => (), // no change in model's translucency
             (true, None) => {
-                model.material = Some(opaque_material);
+                model.material = Some(context.opaque_material(color_mode));
             }
             (false, Some(ior)) => {
                 let Some(mut translucent_material) =
-                    materials.get(transmissive_material.id()).cloned()
+                    context.transmissive_material(materials, color_mode)
                 else {
                     return;
                 };
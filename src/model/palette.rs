@@ -1,18 +1,36 @@
 use bevy::{
-    asset::{Assets, Handle, LoadContext},
-    color::{Color, ColorToComponents, ColorToPacked, LinearRgba},
-    image::Image,
+    asset::Asset,
+    color::{Color, ColorToComponents, LinearRgba},
     math::FloatExt,
+    reflect::TypePath,
+};
+#[cfg(feature = "render")]
+use bevy::{
+    asset::{Handle, LoadContext},
+    color::ColorToPacked,
+    image::Image,
     pbr::StandardMaterial,
     render::{
         render_asset::RenderAssetUsages,
         render_resource::{Extent3d, TextureDimension, TextureFormat},
     },
 };
-use dot_vox::DotVoxData;
+use dot_vox::{DotVoxData, Material};
 
-/// Container for all of the [`VoxelElement`]s that can be used in a [`super::VoxelModel`]
-#[derive(Clone, Debug)]
+use super::{RawVoxel, Voxel};
+
+#[cfg(feature = "render")]
+use crate::MeshColorMode;
+
+/// Container for all of the [`VoxelElement`]s that can be used in a [`super::VoxelModel`].
+///
+/// Palettes kept outside of Magica Voxel can be loaded as assets from GIMP `.gpl`, Paint.NET `.palette.txt`,
+/// `.palette.hex` and JASC `.pal` files, as well as `.palette.png` images with up to 256 pixels when the
+/// `palette_images` feature is enabled. The generic `.txt`, `.hex` and `.png` extensions are left to other loaders, so
+/// those palettes need the `.palette` prefix. The first color is given to [`Voxel`]`(1)`. Pass a clone of the loaded
+/// palette to [`super::VoxelContext::new`] to use it.
+#[derive(Asset, TypePath, Clone, Debug)]
+#[cfg_attr(not(feature = "render"), allow(dead_code))]
 pub struct VoxelPalette {
     pub(crate) elements: Vec<VoxelElement>,
     pub(crate) emission: MaterialProperty,
@@ -161,6 +179,25 @@ impl VoxelPalette {
         VoxelPalette::new(elements, uses_srgb)
     }
 
+    /// Create a recolored copy of this palette, where the first [`Voxel`] of each pair in `remap` takes the
+    /// [`VoxelElement`] of the second. The other elements are unchanged.
+    ///
+    /// Use this with [`super::VoxelContext::recolored`] to render a model with team colors or seasonal variants.
+    /// Voxels that are translucent or cloudy should stay that way, as whether a voxel is translucent or cloudy is
+    /// decided when the model is meshed.
+    pub fn remapped(&self, remap: &[(Voxel, Voxel)]) -> Self {
+        let mut elements = self.elements.clone();
+        for (from, to) in remap {
+            if *from == Voxel::EMPTY || *to == Voxel::EMPTY {
+                continue;
+            }
+            let from = RawVoxel::from(from.clone()).0 as usize;
+            let to = RawVoxel::from(to.clone()).0 as usize;
+            elements[from] = self.elements[to].clone();
+        }
+        VoxelPalette::new(elements, self.uses_srgb)
+    }
+
     pub(crate) fn from_data(
         data: &DotVoxData,
         diffuse_roughness: f32,
@@ -170,44 +207,69 @@ impl VoxelPalette {
         VoxelPalette::new(
             data.palette
                 .iter()
-                .zip(data.materials.iter())
-                .map(|(color, material)| VoxelElement {
-                    color: if uses_srgb {
-                        Color::srgba_u8(color.r, color.g, color.b, color.a)
-                    } else {
-                        Color::linear_rgba(
-                            color.r as f32 / 255.,
-                            color.g as f32 / 255.,
-                            color.b as f32 / 255.,
-                            color.a as f32 / 255.,
-                        )
-                    },
-                    emission: material.emission().unwrap_or(0.0)
-                        * (material.radiant_flux().unwrap_or(0.0) + 1.0)
-                        * emission_strength,
-                    roughness: if material.material_type() == Some("_diffuse") {
-                        diffuse_roughness
-                    } else {
-                        material.roughness().unwrap_or(0.0)
-                    },
-                    metalness: material.metalness().unwrap_or(0.0),
-                    translucency: material.opacity().unwrap_or(0.0),
-                    refraction_index: if material.material_type() == Some("_glass") {
-                        1.0 + material.refractive_index().unwrap_or(0.0)
-                    } else {
-                        0.0
-                    },
-                    density: if material.material_type() == Some("_media") {
-                        material.density().unwrap_or(0.0) * 10.0
-                    } else {
-                        0.0
-                    },
+                .enumerate()
+                .map(|(index, color)| {
+                    // files written by other editors may not have materials, so default to diffuse
+                    let material = data.materials.get(index);
+                    let material_type = material.and_then(Material::material_type);
+                    VoxelElement {
+                        color: color_from_rgba8([color.r, color.g, color.b, color.a], uses_srgb),
+                        emission: material.and_then(Material::emission).unwrap_or(0.0)
+                            * (material.and_then(Material::radiant_flux).unwrap_or(0.0) + 1.0)
+                            * emission_strength,
+                        roughness: match material_type {
+                            None | Some("_diffuse") => diffuse_roughness,
+                            _ => material.and_then(Material::roughness).unwrap_or(0.0),
+                        },
+                        metalness: material.and_then(Material::metalness).unwrap_or(0.0),
+                        translucency: material.and_then(Material::opacity).unwrap_or(0.0),
+                        refraction_index: if material_type == Some("_glass") {
+                            1.0 + material.and_then(Material::refractive_index).unwrap_or(0.0)
+                        } else {
+                            0.0
+                        },
+                        density: if material_type == Some("_media") {
+                            material.and_then(Material::density).unwrap_or(0.0) * 10.0
+                        } else {
+                            0.0
+                        },
+                    }
                 })
                 .collect(),
             uses_srgb,
         )
     }
+}
+
+/// The color of an 8-bit RGBA value, which is either in sRGB or linear space
+pub(crate) fn color_from_rgba8([red, green, blue, alpha]: [u8; 4], uses_srgb: bool) -> Color {
+    if uses_srgb {
+        Color::srgba_u8(red, green, blue, alpha)
+    } else {
+        Color::linear_rgba(
+            red as f32 / 255.,
+            green as f32 / 255.,
+            blue as f32 / 255.,
+            alpha as f32 / 255.,
+        )
+    }
+}
 
+/// Adapts a material created from the palette to meshes with the given [`MeshColorMode`]. The base color texture is
+/// left out for meshes with vertex colors, as it would tint the vertex colors a second time.
+#[cfg(feature = "render")]
+pub(crate) fn material_for_color_mode(
+    mut material: StandardMaterial,
+    color_mode: MeshColorMode,
+) -> StandardMaterial {
+    if color_mode == MeshColorMode::VertexColors {
+        material.base_color_texture = None;
+    }
+    material
+}
+
+#[cfg(feature = "render")]
+impl VoxelPalette {
     pub(crate) fn create_material_in_load_context(
         &self,
         load_context: &mut LoadContext,
@@ -215,10 +277,42 @@ impl VoxelPalette {
         self._create_material(|name, image| load_context.add_labeled_asset(name.to_string(), image))
     }
 
-    pub(crate) fn create_material(&self, images: &mut Assets<Image>) -> StandardMaterial {
+    #[cfg(feature = "generate_voxels")]
+    pub(crate) fn create_material(
+        &self,
+        images: &mut bevy::asset::Assets<Image>,
+    ) -> StandardMaterial {
         self._create_material(|_, image| images.add(image))
     }
 
+    /// The texels of the `material_color` image for `elements`
+    pub(crate) fn color_image_data(&self, elements: &[VoxelElement]) -> Vec<u8> {
+        elements
+            .iter()
+            .flat_map(|e| {
+                if self.uses_srgb {
+                    e.color.to_srgba().to_u8_array()
+                } else {
+                    e.color.to_linear().to_u8_array()
+                }
+            })
+            .collect()
+    }
+
+    /// The texels of the `material_emission` image for `elements`
+    pub(crate) fn emission_image_data(elements: &[VoxelElement]) -> Vec<u8> {
+        elements
+            .iter()
+            .flat_map(|e| {
+                (e.color.to_linear() * e.emission)
+                    .to_f32_array()
+                    .iter()
+                    .flat_map(|c| c.to_le_bytes())
+                    .collect::<Vec<u8>>()
+            })
+            .collect()
+    }
+
     fn _create_material(
         &self,
         mut get_handle: impl FnMut(&str, Image) -> Handle<Image>,
@@ -228,18 +322,7 @@ impl VoxelPalette {
             height: 16,
             depth_or_array_layers: 1,
         };
-        let color_data: Vec<u8> = self
-            .elements
-            .iter()
-            .flat_map(|e| {
-                if self.uses_srgb {
-                    e.color.to_srgba().to_u8_array()
-                } else {
-                    e.color.to_linear().to_u8_array()
-                }
-            })
-            .collect();
-        let emission_data: Vec<f32> = self.elements.iter().map(|e| e.emission).collect();
+        let color_data = self.color_image_data(&self.elements);
         let roughness_data: Vec<f32> = self.elements.iter().map(|e| e.roughness).collect();
         let metalness_data: Vec<f32> = self.elements.iter().map(|e| e.metalness).collect();
         #[cfg(feature = "pbr_transmission_textures")]
@@ -252,6 +335,7 @@ impl VoxelPalette {
         let has_roughness = self.roughness == MaterialProperty::VariesPerElement;
         let has_metalness = self.metalness == MaterialProperty::VariesPerElement;
         let has_roughness_metalness = has_roughness || has_metalness;
+        #[cfg(feature = "pbr_transmission_textures")]
         let has_translucency = self.transmission == MaterialProperty::VariesPerElement;
 
         let base_color_texture = Some(get_handle(
@@ -270,17 +354,7 @@ impl VoxelPalette {
         ));
 
         let emissive_texture = if has_emission {
-            let emission_bytes: Vec<u8> = emission_data
-                .iter()
-                .zip(self.elements.iter().map(|e| e.color))
-                .flat_map(|(emission, color)| {
-                    (color.to_linear() * *emission)
-                        .to_f32_array()
-                        .iter()
-                        .flat_map(|c| c.to_le_bytes())
-                        .collect::<Vec<u8>>()
-                })
-                .collect();
+            let emission_bytes = Self::emission_image_data(&self.elements);
             Some(get_handle(
                 "material_emission",
                 Image::new(
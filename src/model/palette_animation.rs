@@ -0,0 +1,282 @@
+use std::{f32::consts::TAU, time::Duration};
+
+use bevy::{
+    asset::{AssetEvent, AssetId, Assets, Handle},
+    color::{Color, Mix},
+    ecs::{
+        component::Component,
+        event::EventReader,
+        system::{Query, Res, ResMut, Resource},
+    },
+    image::Image,
+    pbr::StandardMaterial,
+    time::{Stopwatch, Time},
+    utils::{HashMap, HashSet},
+};
+
+use super::{RawVoxel, Voxel, VoxelContext, VoxelElement, VoxelPalette};
+
+/// Animates the palette of a [`VoxelContext`] over time by rewriting the `material_color` and `material_emission`
+/// images generated from it, for classic palette effects such as flowing water, glowing lava and blinking lights.
+///
+/// The images are shared by every model rendered with the context, so all of their instances are animated together,
+/// and the animation can be added to any entity. Only the images are changed, not the [`VoxelContext::palette`].
+///
+/// Colors are animated on meshes that sample the palette texture, and not on those meshed with
+/// [`crate::MeshColorMode::VertexColors`]. Emission is only animated if the palette had emissive voxels when the context
+/// was created, as its materials otherwise have no emissive texture.
+///
+/// ### Example
+/// ```no_run
+/// # use std::time::Duration;
+/// # use bevy::prelude::*;
+/// # use bevy_vox_scene::{PaletteEffect, Voxel, VoxelContext, VoxelPaletteAnimation};
+/// fn animate_water(mut commands: Commands, context: Handle<VoxelContext>) {
+///     commands.spawn(VoxelPaletteAnimation::new(
+///         context,
+///         vec![PaletteEffect::Cycle {
+///             first: Voxel(120),
+///             last: Voxel(127),
+///             step: Duration::from_millis(150),
+///         }],
+///     ));
+/// }
+/// ```
+#[derive(Component, Clone, Debug)]
+pub struct VoxelPaletteAnimation {
+    /// Handle to the context whose palette is animated
+    pub context: Handle<VoxelContext>,
+    /// The effects applied to the palette, in order
+    pub effects: Vec<PaletteEffect>,
+    stopwatch: Stopwatch,
+}
+
+/// An effect applied to a range or selection of [`VoxelElement`]s by a [`VoxelPaletteAnimation`]
+#[derive(Clone, Debug)]
+pub enum PaletteEffect {
+    /// Shifts the elements of the voxels from `first` to `last` along by one every `step`, with the last element
+    /// wrapping around to `first`.
+    Cycle {
+        /// The first voxel of the range
+        first: Voxel,
+        /// The last voxel of the range, inclusive
+        last: Voxel,
+        /// How long each step of the cycle is held for
+        step: Duration,
+    },
+    /// Fades the color and emission of `voxels` to `color` and `emission`, and back again, every `period`.
+    Pulse {
+        /// The voxels that pulse
+        voxels: Vec<Voxel>,
+        /// The color at the peak of the pulse
+        color: Color,
+        /// The emissive strength at the peak of the pulse
+        emission: f32,
+        /// The duration of each pulse
+        period: Duration,
+    },
+    /// Switches the emission of `voxels` off for part of every `period`.
+    Blink {
+        /// The voxels that blink
+        voxels: Vec<Voxel>,
+        /// The duration of each blink
+        period: Duration,
+        /// The fraction of each period, from 0.0 to 1.0, that the voxels are lit for
+        duty: f32,
+    },
+}
+
+impl VoxelPaletteAnimation {
+    /// Create a new animation applying `effects` to the palette of `context`
+    pub fn new(context: Handle<VoxelContext>, effects: Vec<PaletteEffect>) -> Self {
+        Self {
+            context,
+            effects,
+            stopwatch: Stopwatch::new(),
+        }
+    }
+
+    /// The elements of `palette` at the current time of the animation
+    fn animated_elements(&self, palette: &VoxelPalette) -> Vec<VoxelElement> {
+        let mut elements = palette.elements.clone();
+        let elapsed = self.stopwatch.elapsed();
+        for effect in self.effects.iter() {
+            effect.apply(&mut elements, elapsed);
+        }
+        elements
+    }
+}
+
+impl PaletteEffect {
+    fn apply(&self, elements: &mut [VoxelElement], elapsed: Duration) {
+        match self {
+            PaletteEffect::Cycle { first, last, step } => {
+                let (Some(first), Some(last)) = (palette_index(first), palette_index(last)) else {
+                    return;
+                };
+                if last <= first || step.is_zero() {
+                    return;
+                }
+                let range = &mut elements[first..=last];
+                let steps = elapsed.as_nanos() / step.as_nanos();
+                range.rotate_right((steps % range.len() as u128) as usize);
+            }
+            PaletteEffect::Pulse {
+                voxels,
+                color,
+                emission,
+                period,
+            } => {
+                // eases from 0.0 to 1.0 and back over each period
+                let amount = 0.5 - 0.5 * (phase(elapsed, period) * TAU).cos();
+                for index in voxels.iter().filter_map(palette_index) {
+                    let element = &mut elements[index];
+                    element.color = Color::LinearRgba(
+                        element.color.to_linear().mix(&color.to_linear(), amount),
+                    );
+                    element.emission += (emission - element.emission) * amount;
+                }
+            }
+            PaletteEffect::Blink {
+                voxels,
+                period,
+                duty,
+            } => {
+                if phase(elapsed, period) < *duty {
+                    return;
+                }
+                for index in voxels.iter().filter_map(palette_index) {
+                    elements[index].emission = 0.0;
+                }
+            }
+        }
+    }
+}
+
+/// The index of the [`VoxelElement`] for `voxel` in the palette, or `None` for [`Voxel::EMPTY`]
+fn palette_index(voxel: &Voxel) -> Option<usize> {
+    if *voxel == Voxel::EMPTY {
+        return None;
+    }
+    Some(RawVoxel::from(voxel.clone()).0 as usize)
+}
+
+/// How far through the current `period` the animation is, from 0.0 to 1.0
+fn phase(elapsed: Duration, period: &Duration) -> f32 {
+    if period.is_zero() {
+        return 0.0;
+    }
+    (elapsed.as_nanos() % period.as_nanos()) as f32 / period.as_nanos() as f32
+}
+
+/// The standard materials that use each image as their base color or emissive texture, so that animating a palette only
+/// rebinds the materials that sample its images
+#[derive(Resource, Default)]
+pub(crate) struct PaletteImageMaterials {
+    materials: HashMap<AssetId<Image>, HashSet<AssetId<StandardMaterial>>>,
+    images: HashMap<AssetId<StandardMaterial>, Vec<AssetId<Image>>>,
+}
+
+impl PaletteImageMaterials {
+    fn remove(&mut self, material: AssetId<StandardMaterial>) {
+        for image in self.images.remove(&material).into_iter().flatten() {
+            if let Some(materials) = self.materials.get_mut(&image) {
+                materials.remove(&material);
+                if materials.is_empty() {
+                    self.materials.remove(&image);
+                }
+            }
+        }
+    }
+}
+
+/// Keeps [`PaletteImageMaterials`] up to date as standard materials are added, changed and dropped
+pub(crate) fn index_palette_materials(
+    mut events: EventReader<AssetEvent<StandardMaterial>>,
+    materials: Res<Assets<StandardMaterial>>,
+    mut index: ResMut<PaletteImageMaterials>,
+) {
+    for event in events.read() {
+        match *event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => {
+                index.remove(id);
+                let Some(material) = materials.get(id) else {
+                    continue;
+                };
+                let images: Vec<AssetId<Image>> =
+                    [&material.base_color_texture, &material.emissive_texture]
+                        .into_iter()
+                        .flatten()
+                        .map(Handle::id)
+                        .collect();
+                if images.is_empty() {
+                    continue;
+                }
+                for image in images.iter() {
+                    index.materials.entry(*image).or_default().insert(id);
+                }
+                index.images.insert(id, images);
+            }
+            AssetEvent::Removed { id } => index.remove(id),
+            _ => (),
+        }
+    }
+}
+
+pub(crate) fn animate_palettes(
+    time: Res<Time>,
+    mut animations: Query<&mut VoxelPaletteAnimation>,
+    contexts: Res<Assets<VoxelContext>>,
+    index: Res<PaletteImageMaterials>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for mut animation in animations.iter_mut() {
+        animation.stopwatch.tick(time.delta());
+        let Some(context) = contexts.get(&animation.context) else {
+            continue;
+        };
+        let Some(material) = materials.get(&context.opaque_material) else {
+            continue;
+        };
+        let color_image = material.base_color_texture.as_ref().map(Handle::id);
+        let emission_image = material.emissive_texture.as_ref().map(Handle::id);
+        let elements = animation.animated_elements(&context.palette);
+        let mut changed = false;
+        if let Some(id) = color_image {
+            changed |= update_image(&mut images, id, context.palette.color_image_data(&elements));
+        }
+        if let Some(id) = emission_image {
+            changed |= update_image(
+                &mut images,
+                id,
+                VoxelPalette::emission_image_data(&elements),
+            );
+        }
+        if !changed {
+            continue;
+        }
+        // materials aren't rebound to the new textures unless they change too
+        let stale: HashSet<AssetId<StandardMaterial>> = [color_image, emission_image]
+            .into_iter()
+            .flatten()
+            .filter_map(|image| index.materials.get(&image))
+            .flatten()
+            .copied()
+            .collect();
+        for id in stale {
+            materials.get_mut(id);
+        }
+    }
+}
+
+/// Replaces the texels of the image, unless they are unchanged. Returns true if the image was changed.
+fn update_image(images: &mut Assets<Image>, id: AssetId<Image>, data: Vec<u8>) -> bool {
+    if images.get(id).is_none_or(|image| image.data == data) {
+        return false;
+    }
+    if let Some(image) = images.get_mut(id) {
+        image.data = data;
+    }
+    true
+}
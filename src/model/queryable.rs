@@ -1,6 +1,6 @@
 use super::{RawVoxel, Voxel, VoxelData, VoxelModel};
 use bevy::{
-    math::{BVec3, IVec3, UVec3, Vec3},
+    math::{BVec3, IVec3, Ray3d, UVec3, Vec3},
     transform::components::GlobalTransform,
 };
 use ndshape::Shape;
@@ -8,6 +8,22 @@ use ndshape::Shape;
 #[derive(Debug, Clone, PartialEq)]
 pub struct OutOfBoundsError;
 
+/// The first non-empty voxel hit by a ray, as returned by [`VoxelQueryable::raycast`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxelRayHit {
+    /// The coordinate of the voxel that was hit, in voxel space
+    pub coord: IVec3,
+    /// The voxel that was hit
+    pub voxel: Voxel,
+    /// The normal of the face that the ray entered the voxel through, in voxel space. Adding this to [`VoxelRayHit::coord`]
+    /// gives the empty voxel in front of the face, for placing new voxels. Zero if the ray starts inside the voxel.
+    pub normal: IVec3,
+    /// The point at which the ray hit the voxel, in the same space as the ray
+    pub point: Vec3,
+    /// The distance along the ray to [`VoxelRayHit::point`], in the same space as the ray
+    pub distance: f32,
+}
+
 /// Methods for converting from global and local to voxel-space coordinates, getting the size of a voxel model, and the voxel at a given point
 pub trait VoxelQueryable {
     /// The size of the voxel model.
@@ -38,6 +54,9 @@ pub trait VoxelQueryable {
 
     /// Converts a local point to a point in voxel coordinates
     ///
+    /// The local origin is placed according to the model's [`crate::VoxLoaderSettings::mesh_offset`], matching its mesh,
+    /// and the coordinate is rounded down, so points outside the model's minimum corner return negative coordinates.
+    ///
     /// ### Arguments
     /// * `local_point` - the point in the local space of the entity that owns this [`crate::VoxelModelInstance`]
     ///
@@ -47,6 +66,9 @@ pub trait VoxelQueryable {
 
     /// Converts a voxel coordinate to a point in local space
     ///
+    /// The point is the minimum corner of the voxel, with the local origin placed according to the model's
+    /// [`crate::VoxLoaderSettings::mesh_offset`].
+    ///
     /// ### Arguments
     /// * `voxel_coord` - a voxel coordinate
     ///
@@ -69,6 +91,133 @@ pub trait VoxelQueryable {
     /// ### Returns
     /// the voxel at this point. If the point lies outside the bounds of the model, it will return [`OutOfBoundsError`].
     fn get_voxel_at_point(&self, position: IVec3) -> Result<Voxel, OutOfBoundsError>;
+
+    /// Walks the voxel grid along a ray, returning the first non-empty voxel that it passes through
+    ///
+    /// ### Arguments
+    /// * `ray` - the ray in the local space of the entity that owns this [`crate::VoxelModelInstance`]
+    /// * `max_distance` - the maximum distance along the ray to search
+    ///
+    /// ### Returns
+    /// the first voxel hit, or `None` if the ray misses the model or only passes through empty voxels
+    fn raycast(&self, ray: Ray3d, max_distance: f32) -> Option<VoxelRayHit> {
+        let size = self.size();
+        if size.cmple(IVec3::ZERO).any() {
+            return None;
+        }
+        // Traverse the grid in voxel space, where each voxel is a unit cube and the model spans 0..size.
+        // As the direction is scaled by the voxel size, `t` is still measured in the space of the ray.
+        let voxel_size = self.model_size() / size.as_vec3();
+        let origin = self.voxel_coord_to_local_space(IVec3::ZERO);
+        let start = (ray.origin - origin) / voxel_size;
+        let direction = *ray.direction / voxel_size;
+
+        // Clip the ray to the bounds of the model
+        let mut t_enter = 0.0_f32;
+        let mut t_exit = max_distance;
+        let mut normal = IVec3::ZERO;
+        for axis in 0..3 {
+            if direction[axis] == 0.0 {
+                if start[axis] < 0.0 || start[axis] > size[axis] as f32 {
+                    return None;
+                }
+                continue;
+            }
+            let t_near = (0.0 - start[axis]) / direction[axis];
+            let t_far = (size[axis] as f32 - start[axis]) / direction[axis];
+            let (t_near, t_far) = (t_near.min(t_far), t_near.max(t_far));
+            if t_near > t_enter {
+                t_enter = t_near;
+                normal = IVec3::ZERO;
+                normal[axis] = -(direction[axis].signum() as i32);
+            }
+            t_exit = t_exit.min(t_far);
+        }
+        if t_enter > t_exit {
+            return None;
+        }
+
+        // Amanatidis-Woo traversal: step into whichever neighbouring voxel the ray reaches first
+        let entry = start + direction * t_enter;
+        let mut coord = entry
+            .floor()
+            .as_ivec3()
+            .clamp(IVec3::ZERO, size - IVec3::ONE);
+        let step = direction.signum().as_ivec3();
+        let mut t_max = Vec3::splat(f32::INFINITY);
+        let mut t_delta = Vec3::splat(f32::INFINITY);
+        for axis in 0..3 {
+            if direction[axis] == 0.0 {
+                continue;
+            }
+            let boundary = if step[axis] > 0 {
+                coord[axis] + 1
+            } else {
+                coord[axis]
+            };
+            t_max[axis] = (boundary as f32 - start[axis]) / direction[axis];
+            t_delta[axis] = 1.0 / direction[axis].abs();
+        }
+        let mut t = t_enter;
+        loop {
+            let voxel = self.get_voxel_at_point(coord).ok()?;
+            if voxel != Voxel::EMPTY {
+                return Some(VoxelRayHit {
+                    coord,
+                    voxel,
+                    normal,
+                    point: ray.get_point(t),
+                    distance: t,
+                });
+            }
+            let axis = if t_max.x < t_max.y && t_max.x < t_max.z {
+                0
+            } else if t_max.y < t_max.z {
+                1
+            } else {
+                2
+            };
+            t = t_max[axis];
+            if t > t_exit {
+                return None;
+            }
+            coord[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+        }
+    }
+
+    /// Walks the voxel grid along a ray in global space, returning the first non-empty voxel that it passes through
+    ///
+    /// ### Arguments
+    /// * `ray` - the ray in global space
+    /// * `global_xform` - the [`bevy::transform::components::GlobalTransform`] of the entity that owns this [`crate::VoxelModelInstance`]
+    /// * `max_distance` - the maximum distance along the ray to search, in global space
+    ///
+    /// ### Returns
+    /// the first voxel hit, with its point and distance in global space, or `None` if nothing was hit
+    fn global_raycast(
+        &self,
+        ray: Ray3d,
+        global_xform: &GlobalTransform,
+        max_distance: f32,
+    ) -> Option<VoxelRayHit> {
+        let inverse = global_xform.affine().inverse();
+        let local_direction = inverse.transform_vector3(*ray.direction);
+        // a scaled transform changes the length of the ray
+        let scale = local_direction.length();
+        let local_ray = Ray3d::new(
+            inverse.transform_point3(ray.origin),
+            local_direction.try_into().ok()?,
+        );
+        let hit = self.raycast(local_ray, max_distance * scale)?;
+        Some(VoxelRayHit {
+            point: global_xform.transform_point(hit.point),
+            distance: hit.distance / scale,
+            ..hit
+        })
+    }
 }
 
 impl VoxelQueryable for VoxelModel {
@@ -104,14 +253,14 @@ impl VoxelQueryable for VoxelData {
     }
 
     fn local_point_to_voxel_space(&self, local_point: Vec3) -> IVec3 {
-        let half_extents = self.size().as_vec3() * 0.5;
-        let voxel_postition = (local_point / self.settings.voxel_size) + half_extents;
-        voxel_postition.as_ivec3()
+        let offset = self.size().as_vec3() * self.settings.mesh_offset.0;
+        let voxel_postition = (local_point / self.settings.voxel_size) + offset;
+        voxel_postition.floor().as_ivec3()
     }
 
     fn voxel_coord_to_local_space(&self, voxel_coord: IVec3) -> Vec3 {
-        let half_extents = self.size().as_vec3() * 0.5;
-        (voxel_coord.as_vec3() - half_extents) * self.settings.voxel_size
+        let offset = self.size().as_vec3() * self.settings.mesh_offset.0;
+        (voxel_coord.as_vec3() - offset) * self.settings.voxel_size
     }
 
     fn get_voxel_at_point(&self, position: IVec3) -> Result<Voxel, OutOfBoundsError> {
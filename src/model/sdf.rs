@@ -33,6 +33,7 @@ impl SDF {
     }
 
     /// Add operation (logical OR)
+    #[allow(clippy::should_implement_trait)]
     pub fn add(self, other: SDF) -> Self {
         Self::new(move |point| self.distance(point).min(other.distance(point)))
     }
@@ -0,0 +1,207 @@
+use bevy::math::{UVec3, Vec3};
+use block_mesh::VoxelVisibility;
+use ndshape::Shape;
+
+use super::{
+    mesh::{position_offset, MeshBuffers},
+    voxel::VisibleVoxel,
+    VoxelData,
+};
+
+/// The number of times each vertex is moved towards the average of its neighbours
+const SMOOTHING_ITERATIONS: usize = 3;
+
+/// Meshes the boundary between the filled and empty voxels with naive Surface Nets.
+///
+/// The voxel centers form a grid, and each cell of that grid that has both filled and empty corners gets a single vertex,
+/// placed at the average of the points where the cell's edges cross the surface. Each pair of neighbouring voxels where
+/// one is filled and the other is empty is joined by a quad between the vertices of the four cells around them. The
+/// vertices are then smoothed, while being kept within their cells so that thin features aren't lost.
+///
+/// Each quad has its own copy of its vertices, taking the palette index of the filled voxel, so that colors aren't blended
+/// across the palette texture. The positions and normals of the copies are the same, so the surface is still smooth.
+pub(super) fn mesh_surface(voxels: &[VisibleVoxel], data: &VoxelData) -> MeshBuffers {
+    let size = UVec3::from(data.shape.as_array());
+    let voxel_at = |point: UVec3| &voxels[data.shape.linearize(point.into()) as usize];
+    let is_filled = |point: UVec3| voxel_at(point).visibility != VoxelVisibility::Empty;
+    let cells = size.saturating_sub(UVec3::ONE);
+    let cell_index = |cell: UVec3| (cell.x + cells.x * (cell.y + cells.y * cell.z)) as usize;
+
+    // a vertex for each cell that the surface passes through, in the space of the voxel grid
+    let mut cell_vertices = vec![u32::MAX; (cells.x * cells.y * cells.z) as usize];
+    let mut cell_minimums: Vec<UVec3> = Vec::new();
+    let mut grid_positions: Vec<Vec3> = Vec::new();
+    for z in 0..cells.z {
+        for y in 0..cells.y {
+            for x in 0..cells.x {
+                let cell = UVec3::new(x, y, z);
+                let corners: [bool; 8] = std::array::from_fn(|i| is_filled(cell + corner(i)));
+                if corners.iter().all(|filled| *filled == corners[0]) {
+                    continue;
+                }
+                let mut sum = Vec3::ZERO;
+                let mut count = 0;
+                for (start, end) in cell_edges() {
+                    if corners[start] != corners[end] {
+                        // the surface crosses halfway between a filled and an empty voxel
+                        sum += (corner(start) + corner(end)).as_vec3() * 0.5;
+                        count += 1;
+                    }
+                }
+                cell_vertices[cell_index(cell)] = grid_positions.len() as u32;
+                cell_minimums.push(cell);
+                grid_positions.push(cell.as_vec3() + sum / count as f32);
+            }
+        }
+    }
+
+    // a quad for each pair of neighbouring voxels on opposite sides of the surface, wound counter-clockwise when
+    // viewed from the empty side, along with the palette index of the filled voxel
+    let mut quads: Vec<([u32; 4], u8)> = Vec::new();
+    for z in 0..size.z {
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let point = UVec3::new(x, y, z);
+                for axis in 0..3 {
+                    let (a, b, c) = (UVec3::AXES[axis], axis_after(axis, 1), axis_after(axis, 2));
+                    let neighbour = point + a;
+                    let is_inside = |p: UVec3| p.cmplt(size).all();
+                    let has_cells_around = point.dot(b) >= 1
+                        && point.dot(c) >= 1
+                        && (point + b).cmplt(size).all()
+                        && (point + c).cmplt(size).all();
+                    if !is_inside(neighbour) || !has_cells_around {
+                        continue;
+                    }
+                    let filled = is_filled(point);
+                    if filled == is_filled(neighbour) {
+                        continue;
+                    }
+                    let mut vertices = [point - b - c, point - c, point, point - b]
+                        .map(|cell| cell_vertices[cell_index(cell)]);
+                    let palette_index = if filled {
+                        voxel_at(point).index
+                    } else {
+                        vertices.reverse();
+                        voxel_at(neighbour).index
+                    };
+                    quads.push((vertices, palette_index));
+                }
+            }
+        }
+    }
+
+    smooth(&mut grid_positions, &cell_minimums, &quads);
+
+    let mut vertex_normals = vec![Vec3::ZERO; grid_positions.len()];
+    for (vertices, _) in quads.iter() {
+        let [p0, p1, p2, p3] = vertices.map(|vertex| grid_positions[vertex as usize]);
+        let normal = (p2 - p0).cross(p3 - p1);
+        for vertex in vertices {
+            vertex_normals[*vertex as usize] += normal;
+        }
+    }
+
+    let voxel_size = data.settings.voxel_size;
+    let position_offset = position_offset(data);
+    let num_vertices = quads.len() * 4;
+    let mut buffers = MeshBuffers {
+        positions: Vec::with_capacity(num_vertices),
+        normals: Vec::with_capacity(num_vertices),
+        indices: Vec::with_capacity(quads.len() * 6),
+        palette_indices: Vec::with_capacity(num_vertices),
+        brightness: Vec::new(),
+    };
+    for (vertices, palette_index) in quads.iter() {
+        let start = buffers.positions.len() as u32;
+        let grid = vertices.map(|vertex| grid_positions[vertex as usize]);
+        // split the quad along its shorter diagonal
+        if grid[0].distance_squared(grid[2]) <= grid[1].distance_squared(grid[3]) {
+            buffers.indices.extend_from_slice(&[
+                start,
+                start + 1,
+                start + 2,
+                start,
+                start + 2,
+                start + 3,
+            ]);
+        } else {
+            buffers.indices.extend_from_slice(&[
+                start,
+                start + 1,
+                start + 3,
+                start + 1,
+                start + 2,
+                start + 3,
+            ]);
+        }
+        for (vertex, grid_position) in vertices.iter().zip(grid) {
+            // grid points are at the centers of the voxels
+            let position = (grid_position + Vec3::splat(0.5)) * voxel_size - position_offset;
+            buffers.positions.push(position.to_array());
+            buffers.normals.push(
+                vertex_normals[*vertex as usize]
+                    .normalize_or_zero()
+                    .to_array(),
+            );
+            buffers.palette_indices.push(*palette_index);
+        }
+    }
+    buffers
+}
+
+/// Moves each vertex towards the average of the vertices it shares a quad edge with, keeping it within its cell
+fn smooth(positions: &mut [Vec3], cell_minimums: &[UVec3], quads: &[([u32; 4], u8)]) {
+    let mut neighbours: Vec<Vec<u32>> = vec![Vec::new(); positions.len()];
+    for (vertices, _) in quads.iter() {
+        for i in 0..4 {
+            let (from, to) = (vertices[i], vertices[(i + 1) % 4]);
+            neighbours[from as usize].push(to);
+            neighbours[to as usize].push(from);
+        }
+    }
+    for list in neighbours.iter_mut() {
+        list.sort_unstable();
+        list.dedup();
+    }
+    for _ in 0..SMOOTHING_ITERATIONS {
+        let previous = positions.to_vec();
+        for (index, position) in positions.iter_mut().enumerate() {
+            let list = &neighbours[index];
+            if list.is_empty() {
+                continue;
+            }
+            let average = list
+                .iter()
+                .map(|neighbour| previous[*neighbour as usize])
+                .sum::<Vec3>()
+                / list.len() as f32;
+            let minimum = cell_minimums[index].as_vec3();
+            *position = average.clamp(minimum, minimum + Vec3::ONE);
+        }
+    }
+}
+
+/// The offset of the `index`th corner of a cell from its minimum corner
+fn corner(index: usize) -> UVec3 {
+    UVec3::new(
+        index as u32 & 1,
+        (index as u32 >> 1) & 1,
+        (index as u32 >> 2) & 1,
+    )
+}
+
+/// The pairs of corners joined by the 12 edges of a cell
+fn cell_edges() -> impl Iterator<Item = (usize, usize)> {
+    (0..8).flat_map(|start| {
+        [1, 2, 4]
+            .into_iter()
+            .filter(move |bit| start & bit == 0)
+            .map(move |bit| (start, start | bit))
+    })
+}
+
+/// The unit vector of the axis `steps` after `axis`, wrapping from z back to x
+fn axis_after(axis: usize, steps: usize) -> UVec3 {
+    UVec3::AXES[(axis + steps) % 3]
+}
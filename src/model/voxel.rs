@@ -1,3 +1,4 @@
+#[cfg(feature = "render")]
 use block_mesh::{MergeVoxel, Voxel as BlockyVoxel, VoxelVisibility};
 
 /// A Voxel. The value is its index in the Magica Voxel palette (1-255), with 0 reserved for [`Voxel::EMPTY`].
@@ -30,18 +31,23 @@ impl From<RawVoxel> for Voxel {
     }
 }
 
+#[cfg(feature = "render")]
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub(crate) struct VisibleVoxel {
     pub index: u8,
     pub visibility: VoxelVisibility,
+    /// Distinguishes voxels whose faces are occluded differently, so that they aren't merged when baking ambient occlusion
+    pub occlusion_key: u32,
 }
 
+#[cfg(feature = "render")]
 impl BlockyVoxel for VisibleVoxel {
     fn get_visibility(&self) -> VoxelVisibility {
         self.visibility
     }
 }
 
+#[cfg(feature = "render")]
 impl MergeVoxel for VisibleVoxel {
     type MergeValue = VisibleVoxel;
 
@@ -1,16 +1,25 @@
+#[cfg(feature = "render")]
 use bevy::{
-    asset::Assets,
-    core::Name,
-    pbr::{FogVolume, MeshMaterial3d},
+    asset::{Assets, Handle},
+    ecs::system::SystemParam,
+    image::Image,
+    pbr::{FogVolume, MeshMaterial3d, StandardMaterial},
     prelude::{
-        BuildChildren, ChildBuild, Commands, Component, Entity, Event, Mesh3d, OnAdd, Parent,
-        Query, Res, Transform, Trigger, Visibility,
+        BuildChildren, ChildBuild, EntityCommands, Mesh, Mesh3d, Res, ResMut, Transform, Visibility,
     },
+    render::view::VisibilityRange,
+};
+use bevy::{
+    core::Name,
+    prelude::{Commands, Component, Entity, Event, OnAdd, Parent, Query, Trigger},
 };
 
+#[cfg(feature = "render")]
 use crate::{
-    load::VoxelAnimationFrame, VoxelLayer, VoxelModel, VoxelModelInstance, VoxelQueryable,
+    load::VoxelAnimationFrame, material::CustomMaterialInserter, VoxelContext, VoxelModel,
+    VoxelQueryable,
 };
+use crate::{VoxelLayer, VoxelModelInstance};
 
 /// An Event triggered when a [`VoxelModelInstance`] is spawned.
 ///
@@ -81,15 +90,85 @@ impl Event for VoxelInstanceSpawned {
 
 pub(crate) fn on_voxel_instance_spawned(
     trigger: Trigger<OnAdd, VoxelModelInstance>,
-    models: Res<Assets<VoxelModel>>,
+    #[cfg(feature = "render")] mut assets: ModelAssets,
+    #[cfg(feature = "render")] instance_query: Query<&VoxelModelInstance>,
     mut commands: Commands,
-    model_query: Query<(&VoxelModelInstance, Option<&Name>, Option<&VoxelLayer>)>,
+    name_query: Query<(Option<&Name>, Option<&VoxelLayer>)>,
 ) {
-    let Ok((model_instance, maybe_name, maybe_layer)) = model_query.get(trigger.entity()) else {
+    let Ok((maybe_name, maybe_layer)) = name_query.get(trigger.entity()) else {
         return;
     };
+    #[cfg(feature = "render")]
+    if let Ok(model_instance) = instance_query.get(trigger.entity()) {
+        assets.mesh_awaiting_models(model_instance);
+        spawn_meshes(
+            trigger.entity(),
+            model_instance,
+            &assets.models,
+            assets.material_inserter.as_deref(),
+            &mut commands,
+        );
+    }
+    let event = VoxelInstanceSpawned {
+        entity: trigger.entity(),
+        model_name: maybe_name.map(|name| name.to_string()),
+        layer_name: maybe_layer.and_then(|layer| layer.name.clone()),
+    };
+    commands.trigger_targets(event, trigger.entity());
+}
+
+/// The assets needed to mesh models loaded with [`crate::VoxLoaderSettings::lazy_meshing`] when they are first spawned,
+/// and to give them the materials of a [`crate::VoxelMaterialFactory`]
+#[cfg(feature = "render")]
+#[derive(SystemParam)]
+pub(crate) struct ModelAssets<'w> {
+    models: ResMut<'w, Assets<VoxelModel>>,
+    contexts: Res<'w, Assets<VoxelContext>>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    images: ResMut<'w, Assets<Image>>,
+    material_inserter: Option<Res<'w, CustomMaterialInserter>>,
+}
+
+#[cfg(feature = "render")]
+impl ModelAssets<'_> {
+    fn mesh_awaiting_models(&mut self, model_instance: &VoxelModelInstance) {
+        let Some(context) = self.contexts.get(&model_instance.context) else {
+            return;
+        };
+        for model_handle in model_instance.models.iter() {
+            // only borrow the models mutably if there is work to do, so that spawning doesn't mark them as modified
+            if !self
+                .models
+                .get(model_handle)
+                .is_some_and(|model| model.awaiting_mesh)
+            {
+                continue;
+            }
+            let Some(model) = self.models.get_mut(model_handle) else {
+                continue;
+            };
+            model.create_render_assets(
+                context,
+                &mut self.meshes,
+                &mut self.materials,
+                &mut self.images,
+            );
+        }
+    }
+}
+
+/// Adds the meshes, materials and fog volumes of the instance's models, with a child entity for each frame if it is animated
+#[cfg(feature = "render")]
+fn spawn_meshes(
+    entity: Entity,
+    model_instance: &VoxelModelInstance,
+    models: &Assets<VoxelModel>,
+    material_inserter: Option<&CustomMaterialInserter>,
+    commands: &mut Commands,
+) {
     if model_instance.has_animation() {
-        commands.entity(trigger.entity()).with_children(|builder| {
+        commands.entity(entity).with_children(|builder| {
             for (index, model_handle) in model_instance.models.iter().enumerate() {
                 let Some(model) = models.get(model_handle) else {
                     continue;
@@ -102,51 +181,85 @@ pub(crate) fn on_voxel_instance_spawned(
                         Visibility::Hidden
                     },
                 ));
-                if let Some(handle) = model.mesh.clone() {
-                    frame.insert(Mesh3d(handle));
-                };
-                if let Some(handle) = model.material.clone() {
-                    frame.insert(MeshMaterial3d(handle));
-                };
-                if model.cloud_image.is_some() {
-                    frame.with_child((
-                        FogVolume {
-                            density_texture: model.cloud_image.clone(),
-                            absorption: 0.1,
-                            ..Default::default()
-                        },
-                        Transform::from_scale(model.model_size()),
-                    ));
-                };
+                insert_model(
+                    &mut frame,
+                    model,
+                    &model_instance.context,
+                    material_inserter,
+                );
             }
         });
     } else if let Some(model_handle) = model_instance.models.first() {
         let Some(model) = models.get(model_handle) else {
             return;
         };
-        if let Some(handle) = model.mesh.clone() {
-            commands.entity(trigger.entity()).insert(Mesh3d(handle));
-        };
-        if let Some(handle) = model.material.clone() {
-            commands
-                .entity(trigger.entity())
-                .insert(MeshMaterial3d(handle));
-        };
-        if model.cloud_image.is_some() {
-            commands.entity(trigger.entity()).with_child((
-                FogVolume {
-                    density_texture: model.cloud_image.clone(),
-                    absorption: 0.1,
-                    ..Default::default()
-                },
-                Transform::from_scale(model.model_size()),
-            ));
-        };
+        insert_model(
+            &mut commands.entity(entity),
+            model,
+            &model_instance.context,
+            material_inserter,
+        );
     }
-    let event = VoxelInstanceSpawned {
-        entity: trigger.entity(),
-        model_name: maybe_name.map(|name| name.to_string()),
-        layer_name: maybe_layer.map(|layer| layer.name.clone()).flatten(),
+}
+
+/// Inserts the model's mesh and material, with a child entity for each level of detail and for the cloud's fog volume
+#[cfg(feature = "render")]
+fn insert_model(
+    entity: &mut EntityCommands,
+    model: &VoxelModel,
+    context: &Handle<VoxelContext>,
+    material_inserter: Option<&CustomMaterialInserter>,
+) {
+    if let Some(handle) = model.mesh.clone() {
+        entity.insert(Mesh3d(handle));
     };
-    commands.trigger_targets(event, trigger.entity());
+    if let Some(handle) = model.material.clone() {
+        insert_material(entity, handle, context, material_inserter);
+    };
+    if !model.lods.is_empty() {
+        let distance = model.data.settings.lod_distance;
+        entity.insert(VisibilityRange::abrupt(0.0, distance));
+        entity.with_children(|builder| {
+            for (index, handle) in model.lods.iter().enumerate() {
+                let start = distance * (1 << index) as f32;
+                let end = if index + 1 == model.lods.len() {
+                    f32::INFINITY
+                } else {
+                    start * 2.0
+                };
+                let mut lod =
+                    builder.spawn((Mesh3d(handle.clone()), VisibilityRange::abrupt(start, end)));
+                if let Some(material) = model.material.clone() {
+                    insert_material(&mut lod, material, context, material_inserter);
+                }
+            }
+        });
+    }
+    if model.cloud_image.is_some() {
+        entity.with_child((
+            FogVolume {
+                density_texture: model.cloud_image.clone(),
+                absorption: 0.1,
+                ..Default::default()
+            },
+            Transform::from_scale(model.model_size()),
+        ));
+    };
+}
+
+/// Inserts the material built by the registered [`crate::VoxelMaterialFactory`] if there is one, otherwise the model's
+/// [`StandardMaterial`]
+#[cfg(feature = "render")]
+pub(crate) fn insert_material(
+    entity: &mut EntityCommands,
+    material: Handle<StandardMaterial>,
+    context: &Handle<VoxelContext>,
+    material_inserter: Option<&CustomMaterialInserter>,
+) {
+    match material_inserter {
+        Some(inserter) => (inserter.0)(entity, material, context.clone()),
+        None => {
+            entity.insert(MeshMaterial3d(material));
+        }
+    }
 }
@@ -0,0 +1,152 @@
+use bevy::{
+    app::{App, Plugin, PreUpdate},
+    asset::Assets,
+    ecs::{
+        entity::Entity,
+        event::EventWriter,
+        schedule::IntoSystemConfigs,
+        system::{Query, Res, ResMut, Resource, SystemParam},
+    },
+    math::{Ray3d, Vec3},
+    picking::{
+        backend::{ray::RayMap, HitData, PointerHits},
+        pointer::PointerId,
+        PickSet, PickingBehavior,
+    },
+    prelude::{Camera, GlobalTransform, InheritedVisibility},
+    render::view::RenderLayers,
+    utils::HashMap,
+};
+
+use crate::{VoxelAnimationPlayer, VoxelModel, VoxelModelInstance, VoxelQueryable, VoxelRayHit};
+
+/// A picking backend that hit-tests [`VoxelModelInstance`]s by walking their voxel grids, rather than their meshes.
+/// Requires the `picking` feature, which is not enabled by default.
+///
+/// Add this alongside bevy's picking plugins (which `DefaultPlugins` includes). Hits are reported with the position and
+/// normal of the face of the voxel under the pointer, and the voxel itself can be read from [`VoxelPickingHits`] in a
+/// pointer event observer:
+///
+/// ### Example
+/// ```no_run
+/// # use bevy::prelude::*;
+/// # use bevy_vox_scene::{VoxScenePlugin, VoxelPickingHits, VoxelPickingPlugin};
+/// #
+/// # fn main() {
+/// #     App::new()
+/// #         .add_plugins((DefaultPlugins, VoxScenePlugin::default(), VoxelPickingPlugin))
+/// #         .add_systems(Startup, setup)
+/// #         .run();
+/// # }
+/// fn setup(mut commands: Commands, assets: Res<AssetServer>) {
+///     commands.spawn(SceneRoot(assets.load("study.vox#workstation")))
+///         .observe(|trigger: Trigger<Pointer<Click>>, hits: Res<VoxelPickingHits>| {
+///             if let Some(hit) = hits.get(trigger.pointer_id, trigger.target) {
+///                 info!("Clicked voxel {:?} at {}", hit.voxel, hit.coord);
+///             }
+///         });
+/// }
+/// ```
+pub struct VoxelPickingPlugin;
+
+impl Plugin for VoxelPickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<VoxelPickingHits>()
+            .add_systems(PreUpdate, update_hits.in_set(PickSet::Backend));
+    }
+}
+
+/// The voxels under each pointer, as found by the [`VoxelPickingPlugin`] on the current frame
+#[derive(Resource, Default, Debug)]
+pub struct VoxelPickingHits {
+    hits: HashMap<(PointerId, Entity), VoxelRayHit>,
+}
+
+impl VoxelPickingHits {
+    /// The voxel of the [`VoxelModelInstance`] `entity` that is under `pointer`. The point and distance of the hit
+    /// are in global space.
+    pub fn get(&self, pointer: PointerId, entity: Entity) -> Option<&VoxelRayHit> {
+        self.hits.get(&(pointer, entity))
+    }
+}
+
+/// Casts rays against the voxel grids of the visible [`VoxelModelInstance`]s
+#[derive(SystemParam)]
+pub(crate) struct VoxelInstanceRayCast<'w, 's> {
+    models: Res<'w, Assets<VoxelModel>>,
+    instances: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static VoxelModelInstance,
+            &'static GlobalTransform,
+            &'static InheritedVisibility,
+            Option<&'static VoxelAnimationPlayer>,
+            Option<&'static RenderLayers>,
+            Option<&'static PickingBehavior>,
+        ),
+    >,
+}
+
+impl VoxelInstanceRayCast<'_, '_> {
+    /// Every instance hit by the global-space `ray` that can be seen by a camera on `camera_layers`, along with the
+    /// normal of the face that was hit in global space
+    pub(crate) fn cast_ray(
+        &self,
+        ray: Ray3d,
+        camera_layers: &RenderLayers,
+    ) -> Vec<(Entity, VoxelRayHit, Vec3)> {
+        self.instances
+            .iter()
+            .filter_map(
+                |(entity, instance, global_xform, visibility, player, layers, behavior)| {
+                    let is_pickable = behavior.is_none_or(|behavior| behavior.is_hoverable);
+                    let layers_match =
+                        camera_layers.intersects(&layers.cloned().unwrap_or_default());
+                    if !visibility.get() || !is_pickable || !layers_match {
+                        return None;
+                    }
+                    // animated instances are hit-tested against the model that is currently shown
+                    let index = player.map_or(0, |player| player.current_model_index());
+                    let model = self.models.get(instance.models.get(index)?)?;
+                    let hit = model.global_raycast(ray, global_xform, f32::MAX)?;
+                    let normal = global_xform
+                        .affine()
+                        .transform_vector3(hit.normal.as_vec3())
+                        .normalize_or_zero();
+                    Some((entity, hit, normal))
+                },
+            )
+            .collect()
+    }
+}
+
+fn update_hits(
+    ray_map: Res<RayMap>,
+    cameras: Query<(&Camera, Option<&RenderLayers>)>,
+    ray_cast: VoxelInstanceRayCast,
+    mut voxel_hits: ResMut<VoxelPickingHits>,
+    mut output: EventWriter<PointerHits>,
+) {
+    voxel_hits.hits.clear();
+    for (&ray_id, &ray) in ray_map.map().iter() {
+        let Ok((camera, camera_layers)) = cameras.get(ray_id.camera) else {
+            continue;
+        };
+        let hits = ray_cast.cast_ray(ray, &camera_layers.cloned().unwrap_or_default());
+        if hits.is_empty() {
+            continue;
+        }
+        let picks = hits
+            .into_iter()
+            .map(|(entity, hit, normal)| {
+                let hit_data =
+                    HitData::new(ray_id.camera, hit.distance, Some(hit.point), Some(normal));
+                voxel_hits.hits.insert((ray_id.pointer, entity), hit);
+                (entity, hit_data)
+            })
+            .collect();
+        output.send(PointerHits::new(ray_id.pointer, picks, camera.order as f32));
+    }
+}
@@ -1,11 +1,21 @@
 use bevy::{
-    prelude::{Children, Commands, DespawnRecursiveExt, Entity, Query, Res, Visibility},
+    animation::AnimationPlayer,
+    asset::{AssetId, Assets, Handle},
+    ecs::system::SystemParam,
+    pbr::StandardMaterial,
+    prelude::{
+        Added, Children, Commands, DespawnRecursiveExt, DetectChanges, Entity, Local, Mesh3d,
+        Query, Ref, RemovedComponents, Res, ResMut, Visibility, With,
+    },
     time::Time,
+    utils::{HashMap, HashSet},
 };
 
 use crate::{
     load::{AnimationUpdate, VoxelAnimationFrame},
-    VoxelAnimationPlayer,
+    material::CustomMaterialInserter,
+    observers::insert_material,
+    VoxelAnimationPlayer, VoxelContext, VoxelModel, VoxelModelInstance, VoxelTransformAnimation,
 };
 
 pub(super) fn update_animations(
@@ -15,20 +25,17 @@ pub(super) fn update_animations(
     time: Res<Time>,
 ) {
     for (entity, mut animation, children) in animation_query.iter_mut() {
+        if animation.is_added() {
+            // frames are spawned showing the first model, which might not be the first frame of the timeline
+            if let Some(first_frame) = animation.frames.first() {
+                show_frame(*first_frame, children, &mut frame_query);
+            }
+        }
         let update = animation.did_advance_frame(time.delta());
         match update {
             AnimationUpdate::SameFrame => (),
             AnimationUpdate::AdvanceFrame(new_frame) => {
-                for child in children {
-                    let Ok((frame, mut visibility)) = frame_query.get_mut(*child) else {
-                        continue;
-                    };
-                    *visibility = if frame.0 == new_frame {
-                        Visibility::Inherited
-                    } else {
-                        Visibility::Hidden
-                    };
-                }
+                show_frame(new_frame, children, &mut frame_query);
             }
             AnimationUpdate::ReachedEnd => {
                 if animation.despawn_on_finish {
@@ -38,3 +45,142 @@ pub(super) fn update_animations(
         };
     }
 }
+
+fn show_frame(
+    new_frame: usize,
+    children: &Children,
+    frame_query: &mut Query<(&VoxelAnimationFrame, &mut Visibility)>,
+) {
+    for child in children {
+        let Ok((frame, mut visibility)) = frame_query.get_mut(*child) else {
+            continue;
+        };
+        *visibility = if frame.0 == new_frame {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+pub(super) fn start_transform_animations(
+    mut query: Query<
+        (&VoxelTransformAnimation, &mut AnimationPlayer),
+        Added<VoxelTransformAnimation>,
+    >,
+) {
+    for (animation, mut player) in query.iter_mut() {
+        player.play(animation.node).repeat();
+    }
+}
+
+/// The context that each instance was last seen with, and the instances whose new context is still loading
+#[derive(Default)]
+pub(super) struct InstanceContexts {
+    seen: HashMap<Entity, AssetId<VoxelContext>>,
+    pending: HashSet<Entity>,
+}
+
+/// The assets that the materials of a model in a new context are built from
+#[derive(SystemParam)]
+pub(super) struct ContextMaterials<'w> {
+    models: Res<'w, Assets<VoxelModel>>,
+    contexts: Res<'w, Assets<VoxelContext>>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    material_inserter: Option<Res<'w, CustomMaterialInserter>>,
+}
+
+/// Gives the meshes of instances whose [`VoxelModelInstance::context`] has changed the materials of the new context.
+/// Instances whose new context is still loading are retried on later frames.
+pub(super) fn swap_instance_contexts(
+    mut commands: Commands,
+    mut state: Local<InstanceContexts>,
+    mut removed: RemovedComponents<VoxelModelInstance>,
+    instances: Query<(Entity, Ref<VoxelModelInstance>, Option<&Children>)>,
+    frames: Query<(&VoxelAnimationFrame, Option<&Children>)>,
+    meshes: Query<(), With<Mesh3d>>,
+    assets: ContextMaterials,
+) {
+    let InstanceContexts { seen, pending } = &mut *state;
+    for entity in removed.read() {
+        seen.remove(&entity);
+        pending.remove(&entity);
+    }
+    for (entity, instance, _) in instances.iter() {
+        if !instance.is_changed() {
+            continue;
+        }
+        // other changes to the instance, such as to its models, keep the materials it has
+        let context = instance.context.id();
+        if seen
+            .insert(entity, context)
+            .is_some_and(|previous| previous != context)
+        {
+            pending.insert(entity);
+        }
+    }
+    let ContextMaterials {
+        models,
+        contexts,
+        mut materials,
+        material_inserter,
+    } = assets;
+    // instances that change to the same context share the materials of translucent models
+    let mut swapped: HashMap<
+        (AssetId<VoxelModel>, AssetId<VoxelContext>),
+        Handle<StandardMaterial>,
+    > = HashMap::new();
+    pending.retain(|entity| {
+        let Ok((entity, instance, children)) = instances.get(*entity) else {
+            return false;
+        };
+        let Some(context) = contexts.get(&instance.context) else {
+            return true;
+        };
+        let mut swap_material = |target: Entity, model_index: usize| {
+            let Some(model_handle) = instance.models.get(model_index) else {
+                return;
+            };
+            let key = (model_handle.id(), instance.context.id());
+            let material = match swapped.get(&key) {
+                Some(material) => material.clone(),
+                None => {
+                    let Some(material) = models
+                        .get(model_handle)
+                        .and_then(|model| model.material_for_context(context, &mut materials))
+                    else {
+                        return;
+                    };
+                    swapped.insert(key, material.clone());
+                    material
+                }
+            };
+            insert_material(
+                &mut commands.entity(target),
+                material,
+                &instance.context,
+                material_inserter.as_deref(),
+            );
+        };
+        // the meshes of each model are on the entity of the instance, or of its animation frame, and its levels of detail
+        let mut swap_meshes = |target: Entity, children: Option<&Children>, model_index: usize| {
+            for mesh_entity in
+                std::iter::once(target).chain(children.into_iter().flatten().copied())
+            {
+                if meshes.contains(mesh_entity) {
+                    swap_material(mesh_entity, model_index);
+                }
+            }
+        };
+        if instance.has_animation() {
+            for child in children.into_iter().flatten() {
+                if let Ok((frame, frame_children)) = frames.get(*child) {
+                    swap_meshes(*child, frame_children, frame.0);
+                }
+            }
+        } else {
+            swap_meshes(entity, children, 0);
+        }
+        false
+    });
+}
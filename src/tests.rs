@@ -4,20 +4,34 @@ use super::*;
 
 #[cfg(feature = "modify_voxels")]
 use crate::{model::queryable::OutOfBoundsError, VoxelRegion};
+#[cfg(feature = "export_gltf")]
+use bevy::prelude::Entity;
 
 use crate::{model::RawVoxel, VoxScenePlugin, VoxelModelInstance};
 use bevy::{
+    animation::{
+        graph::{AnimationGraph, AnimationGraphHandle},
+        AnimationClip, AnimationPlayer, AnimationTarget, AnimationTargetId,
+    },
     app::App,
     asset::{AssetApp, AssetPlugin, AssetServer, Assets, Handle, LoadState},
+    color::{Color, LinearRgba},
     core::Name,
     hierarchy::Children,
+    image::Image,
     math::{IVec3, Quat, UVec3, Vec3, Vec3A},
-    pbr::{FogVolume, MeshMaterial3d, StandardMaterial},
+    pbr::{FogVolume, Material, MeshMaterial3d, StandardMaterial},
     prelude::{
-        Commands, GlobalTransform, HierarchyPlugin, InheritedVisibility, Mesh3d, OnAdd, Query,
-        Transform, Trigger, ViewVisibility, Visibility,
+        Camera, Commands, DespawnRecursiveExt, GlobalTransform, HierarchyPlugin,
+        InheritedVisibility, Mesh3d, OnAdd, Projection, Query, Transform, Trigger, ViewVisibility,
+        Visibility, With,
+    },
+    render::{
+        mesh::{Indices, Mesh, VertexAttributeValues},
+        render_resource::AsBindGroup,
+        texture::ImagePlugin,
+        view::VisibilityRange,
     },
-    render::{mesh::Mesh, texture::ImagePlugin},
     scene::{Scene, ScenePlugin, SceneRoot},
     utils::{default, hashbrown::HashSet},
     MinimalPlugins,
@@ -58,6 +72,268 @@ async fn test_load_scene() {
     );
 }
 
+#[test]
+fn test_deserialize_settings_missing_fields() {
+    // a meta file written before the newer settings were added
+    let settings: VoxLoaderSettings = bevy::asset::ron::from_str(
+        "(
+            voxel_size: 0.5,
+            mesh_outer_faces: false,
+            mesh_offset: ((0.5, 0.0, 0.5)),
+            emission_strength: 10.0,
+            uses_srgb: false,
+            diffuse_roughness: 0.5,
+        )",
+    )
+    .expect("Settings without the newer fields deserialize");
+    assert_eq!(settings.voxel_size, 0.5);
+    assert!(!settings.mesh_outer_faces);
+    assert_eq!(settings.mesh_offset.0, Vec3::new(0.5, 0.0, 0.5));
+    assert!(!settings.uses_srgb);
+    assert!(!settings.data_only);
+    assert!(!settings.strict);
+    assert_eq!(settings.lod_levels, 0);
+    assert_eq!(settings.meshing_mode, MeshingMode::Blocky);
+}
+
+#[async_std::test]
+async fn test_load_data_only() {
+    let mut app = App::new();
+    setup_app_with_plugin(
+        &mut app,
+        VoxScenePlugin {
+            global_settings: Some(VoxLoaderSettings {
+                data_only: true,
+                ..Default::default()
+            }),
+        },
+    );
+    let assets = app.world().resource::<AssetServer>();
+    let handle: Handle<Scene> = assets
+        .load_untyped_async("test.vox")
+        .await
+        .expect("Loaded test.vox")
+        .typed();
+    app.update();
+    assert!(
+        app.world().resource::<Assets<Mesh>>().is_empty(),
+        "No meshes are generated"
+    );
+    assert!(app
+        .world()
+        .resource::<Assets<StandardMaterial>>()
+        .is_empty());
+    let models = app.world().resource::<Assets<VoxelModel>>();
+    assert_eq!(models.len(), 4);
+    let (_, model) = models.iter().next().expect("a model");
+    assert!(model.mesh.is_none());
+    assert!(model.size().min_element() > 0, "Voxel data is loaded");
+
+    app.world_mut().spawn(SceneRoot(handle));
+    app.update();
+    let mut query = app
+        .world_mut()
+        .query_filtered::<(&Name, &Transform), With<VoxelModelInstance>>();
+    assert!(query.iter(app.world()).count() > 0);
+    let mut mesh_query = app.world_mut().query::<&Mesh3d>();
+    assert_eq!(mesh_query.iter(app.world()).count(), 0);
+}
+
+#[async_std::test]
+async fn test_lazy_meshing() {
+    let mut app = App::new();
+    setup_app_with_plugin(
+        &mut app,
+        VoxScenePlugin {
+            global_settings: Some(VoxLoaderSettings {
+                lazy_meshing: true,
+                ..Default::default()
+            }),
+        },
+    );
+    let assets = app.world().resource::<AssetServer>();
+    let handle: Handle<Scene> = assets
+        .load_untyped_async("test.vox#outer-group/inner-group/dice")
+        .await
+        .expect("Loaded dice")
+        .typed();
+    app.update();
+    assert!(
+        app.world().resource::<Assets<Mesh>>().is_empty(),
+        "No meshes are generated at load time"
+    );
+    assert!(
+        !app.world()
+            .resource::<Assets<StandardMaterial>>()
+            .is_empty(),
+        "The palette materials are still loaded"
+    );
+
+    app.world_mut().spawn(SceneRoot(handle));
+    app.update();
+    let mut mesh_query = app.world_mut().query::<&Mesh3d>();
+    assert_eq!(mesh_query.iter(app.world()).count(), 1);
+    assert_eq!(
+        app.world().resource::<Assets<Mesh>>().len(),
+        1,
+        "Only the spawned model is meshed"
+    );
+    let models = app.world().resource::<Assets<VoxelModel>>();
+    let dice = models
+        .iter()
+        .find_map(|(_, model)| (model.name == "outer-group/inner-group/dice").then_some(model))
+        .expect("dice model");
+    assert!(dice.mesh.is_some());
+    assert!(dice.material.is_some());
+}
+
+#[async_std::test]
+async fn test_lod_meshes() {
+    let mut app = App::new();
+    setup_app_with_plugin(
+        &mut app,
+        VoxScenePlugin {
+            global_settings: Some(VoxLoaderSettings {
+                lod_levels: 2,
+                lod_distance: 10.0,
+                ..Default::default()
+            }),
+        },
+    );
+    let assets = app.world().resource::<AssetServer>();
+    let handle: Handle<Scene> = assets
+        .load_untyped_async("test.vox#outer-group/inner-group/dice")
+        .await
+        .expect("Loaded dice")
+        .typed();
+    let lod: Handle<Mesh> = assets
+        .load_untyped_async("test.vox#outer-group/inner-group/dice@mesh-lod2")
+        .await
+        .expect("Loaded the second level of detail")
+        .typed();
+    app.update();
+    assert!(app.world().resource::<Assets<Mesh>>().contains(&lod));
+
+    app.world_mut().spawn(SceneRoot(handle));
+    app.update();
+    let mut query = app.world_mut().query::<(&Mesh3d, &VisibilityRange)>();
+    let mut ranges: Vec<(f32, f32)> = query
+        .iter(app.world())
+        .map(|(_, range)| (range.start_margin.start, range.end_margin.end))
+        .collect();
+    ranges.sort_by(|a, b| a.0.total_cmp(&b.0));
+    assert_eq!(
+        ranges,
+        vec![(0.0, 10.0), (10.0, 20.0), (20.0, f32::INFINITY)]
+    );
+}
+
+#[test]
+fn test_ambient_occlusion() {
+    let palette = VoxelPalette::from_colors(vec![Color::WHITE], false);
+    let mut data = VoxelData::new(
+        UVec3::new(2, 2, 1),
+        VoxLoaderSettings {
+            ambient_occlusion: true,
+            ..Default::default()
+        },
+    );
+    // an L shape, with a crevice where the top of the lower arm meets the upright
+    data.set_voxel(Voxel(1), UVec3::new(0, 0, 0));
+    data.set_voxel(Voxel(1), UVec3::new(1, 0, 0));
+    data.set_voxel(Voxel(1), UVec3::new(0, 1, 0));
+    let (mesh, ..) = data.remesh(&palette);
+    let mesh = mesh.expect("Meshed model");
+    let Some(VertexAttributeValues::Float32x4(colors)) = mesh.attribute(Mesh::ATTRIBUTE_COLOR)
+    else {
+        panic!("Mesh should have vertex colors");
+    };
+    assert_eq!(colors.len(), mesh.count_vertices());
+    assert!(
+        colors.iter().any(|color| color[0] < 1.0),
+        "The crevice should be occluded"
+    );
+    assert!(colors.iter().all(|color| color[0] > 0.0 && color[3] == 1.0));
+
+    data.settings.ambient_occlusion = false;
+    let (mesh, ..) = data.remesh(&palette);
+    assert!(mesh
+        .expect("Meshed model")
+        .attribute(Mesh::ATTRIBUTE_COLOR)
+        .is_none());
+}
+
+#[test]
+fn test_surface_nets_meshing() {
+    let palette = VoxelPalette::from_colors(vec![Color::WHITE], false);
+    let mut data = VoxelData::new(
+        UVec3::splat(3),
+        VoxLoaderSettings {
+            meshing_mode: MeshingMode::SurfaceNets,
+            ..Default::default()
+        },
+    );
+    for z in 0..3 {
+        for y in 0..3 {
+            for x in 0..3 {
+                data.set_voxel(Voxel(7), UVec3::new(x, y, z));
+            }
+        }
+    }
+    let (mesh, ..) = data.remesh(&palette);
+    let mesh = mesh.expect("Meshed model");
+    let positions: Vec<Vec3> = mesh
+        .attribute(Mesh::ATTRIBUTE_POSITION)
+        .and_then(|values| values.as_float3())
+        .expect("Positions")
+        .iter()
+        .map(|position| Vec3::from(*position))
+        .collect();
+    let normals = mesh
+        .attribute(Mesh::ATTRIBUTE_NORMAL)
+        .and_then(|values| values.as_float3())
+        .expect("Normals");
+    let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0) else {
+        panic!("Mesh should have palette UVs");
+    };
+    assert!(!positions.is_empty());
+    assert!(
+        positions
+            .iter()
+            .all(|position| position.abs().max_element() <= 1.5),
+        "The surface stays within the model's bounds"
+    );
+    assert!(
+        positions
+            .iter()
+            .zip(normals)
+            .all(|(position, normal)| position.dot(Vec3::from(*normal)) > 0.0),
+        "Normals point away from the center of the model"
+    );
+    assert!(
+        normals
+            .iter()
+            .any(|normal| normal.iter().filter(|n| n.abs() > 0.01).count() > 1),
+        "The corners of the cube are smoothed"
+    );
+    let palette_index: u8 = RawVoxel::from(Voxel(7)).0;
+    let expected_uv = [
+        ((palette_index % 16) as f32 + 0.5) / 16.0,
+        ((palette_index / 16) as f32 + 0.5) / 16.0,
+    ];
+    assert!(uvs.iter().all(|uv| *uv == expected_uv));
+    let Some(Indices::U32(indices)) = mesh.indices() else {
+        panic!("Mesh should have indices");
+    };
+    for triangle in indices.chunks(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| positions[triangle[i] as usize]);
+        assert!(
+            (b - a).cross(c - a).dot(a + b + c) > 0.0,
+            "Triangles are wound counter-clockwise when viewed from outside"
+        );
+    }
+}
+
 #[async_std::test]
 async fn test_load_spawn_cloud() {
     let mut app = App::new();
@@ -283,24 +559,24 @@ async fn test_spawn_system() {
     assert_eq!(
         app.world_mut()
             .query::<&VoxelLayer>()
-            .iter(&app.world())
+            .iter(app.world())
             .len(),
         6,
         "6 voxel nodes spawned in this scene slice"
     );
     assert_eq!(
-        app.world_mut().query::<&Name>().iter(&app.world()).len(),
+        app.world_mut().query::<&Name>().iter(app.world()).len(),
         4,
         "But only 4 of the voxel nodes are named"
     );
     let mut instance_query = app.world_mut().query::<&VoxelModelInstance>();
     assert_eq!(
-        instance_query.iter(&app.world()).len(),
+        instance_query.iter(app.world()).len(),
         5,
         "5 model instances spawned in this scene slice"
     );
     let models: HashSet<String> = instance_query
-        .iter(&app.world())
+        .iter(app.world())
         .map(|c| c.models[0].id().to_string().clone())
         .collect();
     assert_eq!(models.len(), 3, "Instances point to 3 unique models");
@@ -333,6 +609,214 @@ async fn test_spawn_system() {
     app.update(); // fire the hooks
 }
 
+#[derive(bevy::asset::Asset, bevy::reflect::TypePath, AsBindGroup, Clone)]
+struct TintedMaterial {
+    #[uniform(0)]
+    tint: LinearRgba,
+    #[texture(1)]
+    #[sampler(2)]
+    palette: Option<Handle<Image>>,
+}
+
+impl Material for TintedMaterial {}
+
+#[derive(Clone)]
+struct TintedMaterialFactory;
+
+impl VoxelMaterialFactory for TintedMaterialFactory {
+    type Material = TintedMaterial;
+
+    fn create_material(
+        &self,
+        palette: &VoxelPalette,
+        standard_material: &StandardMaterial,
+    ) -> Self::Material {
+        TintedMaterial {
+            tint: palette.elements[0].color.to_linear(),
+            palette: standard_material.base_color_texture.clone(),
+        }
+    }
+}
+
+#[async_std::test]
+async fn test_custom_material() {
+    let mut app = App::new();
+    let handle = setup_and_load_voxel_scene(&mut app, "test.vox#outer-group/inner-group").await;
+    app.add_plugins(VoxelMaterialPlugin::new(TintedMaterialFactory))
+        .init_asset::<TintedMaterial>();
+    app.update();
+    let scene_root = app.world_mut().spawn(SceneRoot(handle)).id();
+    app.update();
+
+    assert_eq!(
+        app.world_mut()
+            .query::<&MeshMaterial3d<StandardMaterial>>()
+            .iter(app.world())
+            .len(),
+        0,
+        "Models aren't spawned with standard materials"
+    );
+    let mesh_count = app.world_mut().query::<&Mesh3d>().iter(app.world()).len();
+    let custom_materials: HashSet<_> = app
+        .world_mut()
+        .query::<&MeshMaterial3d<TintedMaterial>>()
+        .iter(app.world())
+        .map(|material| material.id())
+        .collect();
+    assert!(mesh_count > 0);
+    assert_eq!(
+        app.world_mut()
+            .query::<&MeshMaterial3d<TintedMaterial>>()
+            .iter(app.world())
+            .len(),
+        mesh_count,
+        "Every mesh gets a custom material"
+    );
+    let materials = app.world().resource::<Assets<TintedMaterial>>();
+    assert_eq!(
+        materials.len(),
+        custom_materials.len(),
+        "Custom materials are shared between models with the same standard material"
+    );
+    assert!(materials
+        .iter()
+        .all(|(_, material)| material.palette.is_some()));
+
+    app.world_mut().entity_mut(scene_root).despawn_recursive();
+    app.update();
+    app.update();
+    assert_eq!(
+        app.world().resource::<Assets<TintedMaterial>>().len(),
+        0,
+        "Custom materials are dropped along with the models using them"
+    );
+}
+
+#[async_std::test]
+async fn test_custom_material_waits_for_context() {
+    let mut app = App::new();
+    let _scene = setup_and_load_voxel_scene(&mut app, "test.vox#outer-group/inner-group").await;
+    app.add_plugins(VoxelMaterialPlugin::new(TintedMaterialFactory))
+        .init_asset::<TintedMaterial>();
+    app.update();
+    let model = app
+        .world()
+        .resource::<Assets<VoxelModel>>()
+        .iter()
+        .find(|(_, model)| model.material.is_some())
+        .map(|(id, _)| id)
+        .expect("a model with a material");
+    let model = app
+        .world_mut()
+        .resource_mut::<Assets<VoxelModel>>()
+        .get_strong_handle(model)
+        .expect("model handle");
+    let loaded_context = app
+        .world()
+        .resource::<Assets<VoxelContext>>()
+        .iter()
+        .next()
+        .map(|(_, context)| context.clone())
+        .expect("a loaded context");
+    let context = app
+        .world()
+        .resource::<Assets<VoxelContext>>()
+        .reserve_handle();
+    let instance = app
+        .world_mut()
+        .spawn(VoxelModelInstance::new(model, context.clone()))
+        .id();
+    app.update();
+    assert!(
+        app.world()
+            .get::<MeshMaterial3d<TintedMaterial>>(instance)
+            .is_none(),
+        "The custom material can't be built before the context loads"
+    );
+
+    app.world_mut()
+        .resource_mut::<Assets<VoxelContext>>()
+        .insert(&context, loaded_context);
+    app.update();
+    app.update();
+    assert!(
+        app.world()
+            .get::<MeshMaterial3d<TintedMaterial>>(instance)
+            .is_some(),
+        "The custom material is inserted once the context loads"
+    );
+}
+
+#[cfg(feature = "picking")]
+#[async_std::test]
+async fn test_picking_ray_cast() {
+    use crate::picking::VoxelInstanceRayCast;
+    use bevy::{
+        ecs::system::RunSystemOnce,
+        math::{Dir3, Ray3d},
+        render::view::RenderLayers,
+    };
+
+    let mut app = App::new();
+    setup_app_with_plugin(
+        &mut app,
+        VoxScenePlugin {
+            global_settings: Some(VoxLoaderSettings {
+                mesh_offset: UnitOffset::ZERO,
+                ..Default::default()
+            }),
+        },
+    );
+    let assets = app.world().resource::<AssetServer>();
+    let handle: Handle<Scene> = assets
+        .load_untyped_async("test.vox#outer-group/inner-group/dice")
+        .await
+        .expect("Loaded dice")
+        .typed();
+    app.update();
+    let scene_root = app.world_mut().spawn(SceneRoot(handle)).id();
+    app.update();
+    let entity = *app
+        .world()
+        .get::<Children>(scene_root)
+        .expect("children")
+        .first()
+        .expect("scene root");
+    // the test app doesn't propagate visibility
+    app.world_mut()
+        .entity_mut(entity)
+        .insert(InheritedVisibility::VISIBLE);
+
+    let hits = app
+        .world_mut()
+        .run_system_once(|ray_cast: VoxelInstanceRayCast| {
+            let ray = Ray3d::new(Vec3::new(-10.0, 2.5, 2.5), Dir3::X);
+            ray_cast.cast_ray(ray, &RenderLayers::default())
+        })
+        .expect("ran ray cast");
+    let [(hit_entity, hit, global_normal)] = &hits[..] else {
+        panic!("expected a single hit, got {:?}", hits);
+    };
+    assert_eq!(*hit_entity, entity);
+    assert_eq!(hit.normal, IVec3::NEG_X);
+    assert_eq!(*global_normal, Vec3::NEG_X);
+    assert_ne!(hit.voxel, Voxel::EMPTY);
+    assert_eq!(
+        hit.point,
+        Vec3::new(hit.coord.x as f32, 2.5, 2.5),
+        "The mesh offset places the model's minimum corner at the origin"
+    );
+
+    let misses = app
+        .world_mut()
+        .run_system_once(|ray_cast: VoxelInstanceRayCast| {
+            let ray = Ray3d::new(Vec3::new(-10.0, 2.5, 2.5), Dir3::NEG_X);
+            ray_cast.cast_ray(ray, &RenderLayers::default())
+        })
+        .expect("ran ray cast");
+    assert!(misses.is_empty());
+}
+
 #[cfg(feature = "modify_voxels")]
 #[async_std::test]
 async fn test_modify_voxels() {
@@ -385,6 +869,48 @@ async fn test_modify_voxels() {
     assert_eq!(voxel.0, 7, "Voxel material should've been changed to 7");
 }
 
+#[cfg(all(feature = "colliders", feature = "modify_voxels"))]
+#[async_std::test]
+async fn test_collider_follows_modifications() {
+    use crate::VoxelCollider;
+
+    let mut app = App::new();
+    let handle =
+        setup_and_load_voxel_scene(&mut app, "test.vox#outer-group/inner-group/dice").await;
+    app.update();
+    let scene_root = app.world_mut().spawn(SceneRoot(handle)).id();
+    app.update();
+    let entity = *app
+        .world()
+        .get::<Children>(scene_root)
+        .expect("children")
+        .first()
+        .expect("scene root");
+    app.world_mut()
+        .entity_mut(entity)
+        .insert(VoxelCollider::default());
+    app.update();
+    let collider = app.world().get::<VoxelCollider>(entity).expect("collider");
+    assert!(!collider.boxes.is_empty());
+    assert!(!collider.mesh.indices.is_empty());
+
+    let model_instance = app
+        .world()
+        .get::<VoxelModelInstance>(entity)
+        .expect("voxel model instance")
+        .clone();
+    app.world_mut().commands().modify_voxel_model(
+        model_instance,
+        VoxelRegionMode::All,
+        |_pos, _voxel, _model| Voxel::EMPTY,
+    );
+    app.update();
+    app.update();
+    let collider = app.world().get::<VoxelCollider>(entity).expect("collider");
+    assert!(collider.boxes.is_empty(), "Collider is regenerated");
+    assert!(collider.mesh.indices.is_empty());
+}
+
 #[cfg(feature = "generate_voxels")]
 #[test]
 fn test_generate_voxels() {
@@ -403,7 +929,7 @@ fn test_generate_voxels() {
     let (_, tall_box_model) =
         VoxelModel::new(world, tall_box, "tall box".to_string(), context).expect("Add box model");
     assert_eq!(tall_box_model.name, "tall box");
-    assert_eq!(tall_box_model.has_translucency, false);
+    assert!(!tall_box_model.has_translucency);
     let mesh_handle = tall_box_model
         .mesh
         .clone()
@@ -424,6 +950,134 @@ fn test_generate_voxels() {
     );
 }
 
+#[cfg(feature = "generate_voxels")]
+#[test]
+fn test_vertex_color_mode() {
+    use bevy::color::ColorToComponents;
+
+    let mut app = App::new();
+    setup_app(&mut app);
+    let green = bevy::color::palettes::css::GREEN;
+    let palette = VoxelPalette::from_colors(vec![Color::WHITE, green.into()], true);
+    let data = SDF::cuboid(Vec3::splat(1.0)).voxelize(
+        UVec3::splat(4),
+        VoxLoaderSettings {
+            color_mode: MeshColorMode::VertexColors,
+            ..Default::default()
+        },
+        Voxel(2),
+    );
+    let world = app.world_mut();
+    let context_handle = VoxelContext::new(world, palette).expect("Context has been created");
+    let (_, model) =
+        VoxelModel::new(world, data, "box".to_string(), context_handle.clone()).expect("Add model");
+    let context = app
+        .world()
+        .resource::<Assets<VoxelContext>>()
+        .get(&context_handle)
+        .expect("context");
+    let materials = app.world().resource::<Assets<StandardMaterial>>();
+    let material_handle = model.material.expect("Model has a material");
+    assert_eq!(material_handle, context.vertex_color_material);
+    let material = materials.get(&material_handle).expect("material");
+    assert!(
+        material.base_color_texture.is_none(),
+        "The vertex colors aren't tinted by the palette texture"
+    );
+    assert!(materials
+        .get(&context.opaque_material)
+        .expect("palette texture material")
+        .base_color_texture
+        .is_some());
+
+    let meshes = app.world().resource::<Assets<Mesh>>();
+    let mesh = meshes.get(&model.mesh.expect("mesh handle")).expect("mesh");
+    let Some(VertexAttributeValues::Float32x4(colors)) = mesh.attribute(Mesh::ATTRIBUTE_COLOR)
+    else {
+        panic!("Mesh should have vertex colors");
+    };
+    let expected = Color::from(green).to_linear().to_f32_array();
+    assert!(colors.iter().all(|color| *color == expected));
+    let Some(VertexAttributeValues::Uint32(indices)) = mesh.attribute(ATTRIBUTE_PALETTE_INDEX)
+    else {
+        panic!("Mesh should have palette indices");
+    };
+    assert!(
+        indices.iter().all(|index| *index == 1),
+        "The palette index is one less than the voxel"
+    );
+    assert_eq!(indices.len(), mesh.count_vertices());
+}
+
+#[cfg(feature = "generate_voxels")]
+#[test]
+fn test_palette_swap() {
+    let mut app = App::new();
+    setup_app(&mut app);
+    let red = Color::srgb(1.0, 0.0, 0.0);
+    let blue = Color::srgb(0.0, 0.0, 1.0);
+    let palette = VoxelPalette::from_colors(vec![red, blue], true);
+    let data = SDF::cuboid(Vec3::splat(1.0)).voxelize(
+        UVec3::splat(4),
+        VoxLoaderSettings::default(),
+        Voxel(1),
+    );
+    let world = app.world_mut();
+    let context = VoxelContext::new(world, palette).expect("Context has been created");
+    let (model_handle, model) =
+        VoxelModel::new(world, data, "box".to_string(), context.clone()).expect("Add model");
+    let entity = world
+        .spawn(VoxelModelInstance::new(model_handle, context.clone()))
+        .id();
+    app.update();
+
+    let recolored = VoxelContext::recolored(app.world_mut(), &context, &[(Voxel(1), Voxel(2))])
+        .expect("Recolored context");
+    let contexts = app.world().resource::<Assets<VoxelContext>>();
+    let new_context = contexts.get(&recolored).expect("new context");
+    assert_eq!(new_context.palette.elements[0].color, blue);
+    assert_eq!(new_context.palette.elements[1].color, blue);
+    let new_material = new_context.opaque_material.clone();
+
+    app.world_mut()
+        .get_mut::<VoxelModelInstance>(entity)
+        .expect("instance")
+        .context = recolored;
+    app.update();
+    let material = app
+        .world()
+        .get::<MeshMaterial3d<StandardMaterial>>(entity)
+        .expect("material");
+    assert_eq!(
+        material.0, new_material,
+        "The material of the new context is used"
+    );
+    let mesh = app.world().get::<Mesh3d>(entity).expect("mesh");
+    assert_eq!(Some(mesh.0.clone()), model.mesh, "The mesh is shared");
+
+    let replaced = app
+        .world_mut()
+        .resource_mut::<Assets<StandardMaterial>>()
+        .add(StandardMaterial::default());
+    let world = app.world_mut();
+    world
+        .entity_mut(entity)
+        .insert(MeshMaterial3d(replaced.clone()));
+    let mut instance = world
+        .get_mut::<VoxelModelInstance>(entity)
+        .expect("instance");
+    instance.models = instance.models.clone();
+    app.update();
+    let material = app
+        .world()
+        .get::<MeshMaterial3d<StandardMaterial>>(entity)
+        .expect("material");
+    assert_eq!(
+        material.0, replaced,
+        "Changes that keep the context don't swap the materials"
+    );
+}
+
 #[cfg(feature = "generate_voxels")]
 #[test]
 fn test_sdf_intersect() {
@@ -481,28 +1135,1253 @@ fn test_voxel_queryable() {
     );
 }
 
-async fn setup_and_load_voxel_scene(app: &mut App, filename: &'static str) -> Handle<Scene> {
-    setup_app(app);
-    let assets = app.world().resource::<AssetServer>();
-    assets
-        .load_untyped_async(filename)
-        .await
-        .expect(format!("Loaded {filename}").as_str())
-        .typed::<Scene>()
+#[test]
+fn test_voxel_space_offset() {
+    let data = VoxelData::new(
+        UVec3::splat(4),
+        VoxLoaderSettings {
+            mesh_offset: UnitOffset::ZERO,
+            ..Default::default()
+        },
+    );
+    assert_eq!(
+        data.local_point_to_voxel_space(Vec3::splat(0.5)),
+        IVec3::ZERO
+    );
+    // points just outside the minimum corner are outside the model, rather than rounded into it
+    assert_eq!(
+        data.local_point_to_voxel_space(Vec3::splat(-0.2)),
+        IVec3::NEG_ONE
+    );
+    assert!(data.point_in_model(IVec3::NEG_ONE).is_err());
+    assert_eq!(
+        data.voxel_coord_to_local_space(IVec3::new(1, 2, 3)),
+        Vec3::new(1.0, 2.0, 3.0)
+    );
+
+    let data = VoxelData::new(
+        UVec3::splat(4),
+        VoxLoaderSettings {
+            mesh_offset: UnitOffset::CENTER_BASE,
+            voxel_size: 0.5,
+            ..Default::default()
+        },
+    );
+    assert_eq!(
+        data.local_point_to_voxel_space(Vec3::new(-0.75, 0.25, -0.1)),
+        IVec3::new(0, 0, 1)
+    );
+    assert_eq!(
+        data.local_point_to_voxel_space(Vec3::new(0.0, -0.1, -1.25)),
+        IVec3::new(2, -1, -1)
+    );
+    assert_eq!(
+        data.voxel_coord_to_local_space(IVec3::new(0, 1, 3)),
+        Vec3::new(-1.0, 0.5, 0.5)
+    );
 }
 
-fn setup_app(app: &mut App) {
+#[test]
+fn test_downsampled() {
+    let mut data = VoxelData::new(UVec3::new(4, 4, 3), VoxLoaderSettings::default());
+    data.set_voxel(Voxel(1), UVec3::new(0, 0, 0));
+    data.set_voxel(Voxel(2), UVec3::new(1, 0, 0));
+    data.set_voxel(Voxel(2), UVec3::new(0, 1, 1));
+    data.set_voxel(Voxel(3), UVec3::new(3, 3, 2));
+    let lod = data.downsampled(2);
+    assert_eq!(lod.size(), IVec3::new(2, 2, 2));
+    assert_eq!(lod.model_size(), Vec3::new(4.0, 4.0, 4.0));
+    assert_eq!(
+        lod.get_voxel_at_point(IVec3::new(0, 0, 0)).unwrap(),
+        Voxel(2),
+        "The most common voxel in the cell is chosen"
+    );
+    assert_eq!(
+        lod.get_voxel_at_point(IVec3::new(1, 1, 1)).unwrap(),
+        Voxel(3),
+        "A single voxel fills the cell"
+    );
+    assert_eq!(
+        lod.get_voxel_at_point(IVec3::new(1, 0, 0)).unwrap(),
+        Voxel::EMPTY
+    );
+}
+
+#[test]
+fn test_downsampled_offset() {
+    use bevy::render::mesh::MeshAabb;
+    let settings = VoxLoaderSettings {
+        lod_levels: 1,
+        ..Default::default()
+    };
+    let palette = VoxelPalette::from_colors(vec![Color::WHITE], false);
+    let mut data = VoxelData::new(UVec3::splat(3), settings);
+    data.set_voxel(Voxel(1), UVec3::ZERO);
+    data.set_voxel(Voxel(1), UVec3::splat(2));
+    let (mesh, ..) = data.remesh(&palette);
+    let aabb = mesh.expect("mesh").compute_aabb().expect("aabb");
+    let lod_aabb = data.lod_meshes(&palette)[0]
+        .compute_aabb()
+        .expect("lod aabb");
+    assert_eq!(
+        lod_aabb.min(),
+        aabb.min(),
+        "The level of detail starts at the same corner as the model"
+    );
+    assert_eq!(lod_aabb.max(), aabb.max() + Vec3A::ONE);
+}
+
+#[test]
+fn test_raycast() {
+    use bevy::math::{Dir3, Ray3d};
+
+    let mut data = VoxelData::new(UVec3::splat(4), VoxLoaderSettings::default());
+    data.set_voxel(Voxel(5), UVec3::new(2, 1, 3));
+    data.set_voxel(Voxel(6), UVec3::new(3, 1, 3));
+    let ray = Ray3d::new(Vec3::new(-10.0, -0.5, 1.5), Dir3::X);
+    let hit = data.raycast(ray, 100.0).expect("hit");
+    assert_eq!(hit.coord, IVec3::new(2, 1, 3), "The first voxel is hit");
+    assert_eq!(hit.voxel, Voxel(5));
+    assert_eq!(hit.normal, IVec3::NEG_X);
+    assert_eq!(hit.distance, 10.0);
+    assert_eq!(hit.point, Vec3::new(0.0, -0.5, 1.5));
+    assert_eq!(data.raycast(ray, 5.0), None, "The hit is out of range");
+    assert_eq!(
+        data.raycast(Ray3d::new(Vec3::new(-10.0, 0.5, 1.5), Dir3::X), 100.0),
+        None,
+        "The ray only passes through empty voxels"
+    );
+    assert_eq!(
+        data.raycast(Ray3d::new(Vec3::new(0.5, -0.5, 1.5), Dir3::NEG_X), 100.0),
+        Some(VoxelRayHit {
+            coord: IVec3::new(2, 1, 3),
+            voxel: Voxel(5),
+            normal: IVec3::ZERO,
+            point: Vec3::new(0.5, -0.5, 1.5),
+            distance: 0.0,
+        }),
+        "A ray starting inside a voxel hits it immediately"
+    );
+    let diagonal = Ray3d::new(
+        Vec3::new(-2.5, 3.0, 1.5),
+        Dir3::new(Vec3::new(1.0, -1.0, 0.0)).unwrap(),
+    );
+    let hit = data.raycast(diagonal, 100.0).expect("diagonal hit");
+    assert_eq!(hit.coord, IVec3::new(2, 1, 3));
+    assert_eq!(hit.normal, IVec3::Y, "The ray enters through the top face");
+
+    let global_xform =
+        GlobalTransform::from(Transform::from_xyz(100.0, 0.0, 0.0).with_scale(Vec3::splat(2.0)));
+    let hit = data
+        .global_raycast(
+            Ray3d::new(Vec3::new(80.0, -1.0, 3.0), Dir3::X),
+            &global_xform,
+            100.0,
+        )
+        .expect("global hit");
+    assert_eq!(hit.coord, IVec3::new(2, 1, 3));
+    assert_eq!(hit.distance, 20.0);
+    assert_eq!(hit.point, Vec3::new(100.0, -1.0, 3.0));
+}
+
+#[cfg(feature = "colliders")]
+#[test]
+fn test_collider_shapes() {
+    let mut data = VoxelData::new(UVec3::new(3, 2, 1), VoxLoaderSettings::default());
+    for x in 0..3 {
+        data.set_voxel(Voxel(1), UVec3::new(x, 0, 0));
+    }
+    data.set_voxel(Voxel(2), UVec3::new(0, 1, 0));
+    data.set_voxel(Voxel(3), UVec3::new(1, 1, 0));
+    let palette = VoxelPalette::new(
+        vec![
+            VoxelElement::default(),
+            VoxelElement {
+                translucency: 0.5,
+                ..default()
+            },
+            VoxelElement {
+                density: 1.0,
+                ..default()
+            },
+        ],
+        true,
+    );
+    let model = VoxelModel::without_mesh("bar".to_string(), data);
+
+    let boxes = model.collider_boxes(&palette, false);
+    assert_eq!(
+        boxes,
+        vec![crate::VoxelColliderBox {
+            center: Vec3::new(0.0, -0.5, 0.0),
+            half_size: Vec3::new(1.5, 0.5, 0.5),
+        }],
+        "The solid row is merged into a single box, skipping glass and cloud voxels"
+    );
+    assert_eq!(
+        model.collider_boxes(&palette, true).len(),
+        2,
+        "Glass is included if requested, but clouds never are"
+    );
+
+    let mesh = model.collider_mesh(&palette, false);
+    assert_eq!(mesh.indices.len(), 12, "Each side of the bar is one quad");
+    assert_eq!(mesh.vertices.len(), 24);
+    assert!(mesh
+        .vertices
+        .iter()
+        .all(|vertex| vertex.abs().cmple(Vec3::new(1.5, 1.0, 0.5)).all()));
+}
+
+#[cfg(all(feature = "export_voxels", feature = "modify_voxels"))]
+#[test]
+fn test_write_vox_round_trip() {
+    let settings = VoxLoaderSettings::default();
+    let mut data = VoxelData::new(UVec3::new(3, 4, 5), settings.clone());
+    data.set_voxel(Voxel(1), UVec3::new(0, 0, 0));
+    data.set_voxel(Voxel(2), UVec3::new(2, 1, 0));
+    data.set_voxel(Voxel(3), UVec3::new(1, 3, 4));
+    let palette = VoxelPalette::new(
+        vec![
+            VoxelElement {
+                color: bevy::color::palettes::css::GREEN.into(),
+                roughness: settings.diffuse_roughness,
+                ..default()
+            },
+            VoxelElement {
+                emission: 30.0,
+                ..default()
+            },
+            VoxelElement {
+                translucency: 0.5,
+                refraction_index: 1.3,
+                ..default()
+            },
+        ],
+        true,
+    );
+    let mut bytes: Vec<u8> = Vec::new();
+    data.write_vox(&palette, &mut bytes).expect("write vox");
+
+    let file = dot_vox::load_bytes(&bytes).expect("parse written vox");
+    assert_eq!(file.models.len(), 1);
+    let loaded = VoxelData::from_model(&file.models[0], settings.clone());
+    assert_eq!(loaded.size(), data.size(), "size survives the round trip");
+    assert_eq!(loaded.voxels, data.voxels, "voxels survive the round trip");
+
+    let loaded_palette = VoxelPalette::from_data(
+        &file,
+        settings.diffuse_roughness,
+        settings.emission_strength,
+        settings.uses_srgb,
+    );
+    assert_eq!(
+        loaded_palette.elements[0].color.to_srgba(),
+        palette.elements[0].color.to_srgba()
+    );
+    assert!((loaded_palette.elements[1].emission - 30.0).abs() < 0.001);
+    assert_eq!(loaded_palette.elements[2].translucency, 0.5);
+    assert!((loaded_palette.elements[2].refraction_index - 1.3).abs() < 0.001);
+}
+
+#[async_std::test]
+async fn test_animation_frame_timing() {
+    let mut app = App::new();
+    let handle = setup_and_load_voxel_scene(&mut app, "frame-timing.vox").await;
+    app.update();
+    app.world_mut().spawn(SceneRoot(handle));
+    app.update();
+    let mut query = app
+        .world_mut()
+        .query::<(&VoxelModelInstance, &VoxelAnimationPlayer)>();
+    let (instance, player) = query.single(app.world());
+    assert_eq!(instance.models.len(), 3);
+    assert_eq!(
+        player.frames,
+        vec![1, 1, 1, 0, 0, 2],
+        "Models are held until the next keyframe, and the first keyframe is shown before it starts"
+    );
+}
+
+#[async_std::test]
+async fn test_keyframe_out_of_range() {
+    let mut app = App::new();
+    let handle = setup_and_load_voxel_scene(&mut app, "far-keyframe.vox").await;
+    app.update();
+    app.world_mut().spawn(SceneRoot(handle));
+    app.update();
+    let mut query = app.world_mut().query::<&VoxelAnimationPlayer>();
+    let player = query.single(app.world());
+    assert_eq!(
+        player.frames.len(),
+        65_536,
+        "The keyframe is moved to the last supported frame"
+    );
+    assert_eq!(player.frames.last(), Some(&1));
+
+    let mut app = App::new();
+    setup_app_with_plugin(
+        &mut app,
+        VoxScenePlugin {
+            global_settings: Some(VoxLoaderSettings {
+                strict: true,
+                ..Default::default()
+            }),
+        },
+    );
+    let error = load_error(&app, "far-keyframe.vox").await;
+    assert!(
+        error.contains("shape node 3 has keyframe 4000000000, past the last supported frame 65535"),
+        "{error}"
+    );
+}
+
+#[async_std::test]
+async fn test_transform_animation() {
+    let mut app = App::new();
+    let handle = setup_and_load_voxel_scene(&mut app, "keyframes.vox").await;
+    app.update();
+    let scene_root = app.world_mut().spawn(SceneRoot(handle)).id();
+    app.update();
+    app.update(); // start the animation
+    let root = *app
+        .world()
+        .get::<Children>(scene_root)
+        .expect("children")
+        .first()
+        .expect("scene root");
+    let player = app
+        .world()
+        .get::<AnimationPlayer>(root)
+        .expect("Animation player on the root entity");
+    assert_eq!(player.playing_animations().count(), 1);
+    assert!(app.world().get::<AnimationGraphHandle>(root).is_some());
+
+    let mut query = app
+        .world_mut()
+        .query::<(&Name, &AnimationTarget, &Transform)>();
+    let (name, target, transform) = query.single(app.world());
+    assert_eq!(name.as_str(), "platform");
+    assert_eq!(target.id, AnimationTargetId::from_name(name));
+    assert_eq!(target.player, root);
+    assert_eq!(
+        transform.translation,
+        Vec3::ZERO,
+        "Entity is spawned at the first keyframe"
+    );
+
+    let clip_handle = app
+        .world()
+        .resource::<AssetServer>()
+        .get_handle::<AnimationClip>("keyframes.vox#animation")
+        .expect("animation clip");
+    let clip = app
+        .world()
+        .resource::<Assets<AnimationClip>>()
+        .get(&clip_handle)
+        .expect("animation clip");
+    assert_eq!(clip.duration(), 1.0, "8 frames of 1/8 second");
+    assert_eq!(
+        clip.curves_for_target(target.id).map(|curves| curves.len()),
+        Some(3),
+        "translation, rotation and scale curves"
+    );
+}
+
+#[async_std::test]
+async fn test_load_camera() {
+    let mut app = App::new();
+    let handle = setup_and_load_voxel_scene(&mut app, "test.vox#camera-0").await;
+    app.update();
+    let mut scenes = app.world_mut().resource_mut::<Assets<Scene>>();
+    let scene = scenes.get_mut(&handle).expect("camera scene");
+    let mut query = scene
+        .world
+        .query::<(&Name, &Camera, &Projection, &Transform)>();
+    let (name, camera, projection, transform) = query.single(&scene.world);
+    assert_eq!(name.as_str(), "camera-0");
+    assert!(camera.is_active);
+    let Projection::Perspective(perspective) = projection else {
+        panic!("Expected a perspective projection");
+    };
+    assert!((perspective.fov - 45_f32.to_radians()).abs() < 0.001);
+    // Default camera: radius 1 from the origin, looking along Magica Voxel's +Y (bevy's +Z)
+    assert!(transform
+        .translation
+        .abs_diff_eq(Vec3::new(0.0, 0.0, -1.0), 0.001));
+    assert!(transform.forward().abs_diff_eq(Vec3::Z, 0.001));
+    assert!(transform.up().abs_diff_eq(Vec3::Y, 0.001));
+}
+
+#[async_std::test]
+async fn test_load_render_settings() {
+    let mut app = App::new();
+    setup_app(&mut app);
+    let handle = app
+        .world()
+        .resource::<AssetServer>()
+        .load_untyped_async("test.vox#render-settings")
+        .await
+        .expect("Loaded render settings")
+        .typed::<VoxelRenderSettings>();
+    app.update();
+    let settings = app
+        .world()
+        .resource::<Assets<VoxelRenderSettings>>()
+        .get(&handle)
+        .expect("render settings");
+    let sun = settings.sun.as_ref().expect("sun");
+    assert_eq!(sun.intensity, 0.7);
+    assert!(sun.direction.y < 0.0, "The sun shines downwards");
+    let (light, _) = settings.directional_light().expect("directional light");
+    assert_eq!(light.illuminance, 0.7 * SUN_ILLUMINANCE);
+    let ambient = settings.ambient_light().expect("ambient light");
+    assert_eq!(ambient.brightness, 0.7 * SKY_BRIGHTNESS);
+    assert!(settings.distance_fog().is_none(), "Fog has zero density");
+    assert_eq!(settings.background, Some(Color::srgb_u8(0, 0, 0)));
+}
+
+#[cfg(feature = "export_voxels")]
+#[async_std::test]
+async fn test_write_vox_scene_round_trip() {
+    let mut app = App::new();
+    let handle = setup_and_load_voxel_scene(&mut app, "test.vox").await;
+    app.update();
+    let scene_root = app.world_mut().spawn(SceneRoot(handle)).id();
+    app.update();
+    let root = *app
+        .world()
+        .get::<Children>(scene_root)
+        .expect("children")
+        .first()
+        .expect("scene root");
+
+    let mut bytes: Vec<u8> = Vec::new();
+    write_vox_scene(app.world(), root, &mut bytes).expect("write vox scene");
+    let written = dot_vox::load_bytes(&bytes).expect("parse written vox");
+    let original = dot_vox::load("assets/test.vox").expect("parse original vox");
+    assert_eq!(
+        written.models.len(),
+        original.models.len(),
+        "Models shared between instances are written once"
+    );
+
+    // (name, translation, rotation, hidden) of every transform node except the root
+    let transform_nodes = |data: &dot_vox::DotVoxData| {
+        let mut nodes: Vec<(Option<String>, String, Option<String>, bool)> = data
+            .scenes
+            .iter()
+            .skip(1)
+            .filter_map(|node| match node {
+                dot_vox::SceneNode::Transform {
+                    attributes, frames, ..
+                } => Some((
+                    attributes.get("_name").cloned(),
+                    frames[0]
+                        .attributes
+                        .get("_t")
+                        .cloned()
+                        .unwrap_or("0 0 0".to_string()),
+                    frames[0].attributes.get("_r").cloned(),
+                    attributes
+                        .get("_hidden")
+                        .is_some_and(|hidden| hidden == "1"),
+                )),
+                _ => None,
+            })
+            .collect();
+        nodes.sort();
+        // the original file instances the inner group twice by sharing its node, whereas the written file duplicates it
+        nodes.dedup();
+        nodes
+    };
+    assert_eq!(
+        transform_nodes(&written),
+        transform_nodes(&original),
+        "Names, transforms and hidden flags survive the round trip"
+    );
+}
+
+#[async_std::test]
+async fn test_load_without_scene_graph() {
+    let mut app = App::new();
+    let handle = setup_and_load_voxel_scene(&mut app, "no-scene.vox").await;
+    app.update();
+    app.world_mut().spawn(SceneRoot(handle));
+    app.update();
+    let mut query = app.world_mut().query::<&VoxelModelInstance>();
+    assert_eq!(
+        query.iter(app.world()).count(),
+        2,
+        "An instance is spawned for each model"
+    );
+}
+
+#[async_std::test]
+async fn test_invalid_scene_node_reference() {
+    let mut app = App::new();
+    setup_app(&mut app);
+    let error = load_error(&app, "dangling-child.vox").await;
+    assert!(error.contains("scene node 1 references invalid child node 5"));
+}
+
+#[async_std::test]
+async fn test_parse_error_offset() {
+    let mut app = App::new();
+    setup_app(&mut app);
+    let error = load_error(&app, "malformed-chunk.vox").await;
+    assert!(
+        error.contains("failed to parse the chunk at byte 44: malformed XYZI chunk"),
+        "{error}"
+    );
+}
+
+#[async_std::test]
+async fn test_malformed_qubicle() {
+    let mut app = App::new();
+    setup_app(&mut app);
+    let error = load_error(&app, "malformed-size.qb").await;
+    assert!(
+        error.contains("failed to parse the Qubicle file at byte 53: matrix of size"),
+        "{error}"
+    );
+    let error = load_error(&app, "malformed-run.qb").await;
+    assert!(
+        error.contains("at byte 64: run extends past the end of the slice"),
+        "{error}"
+    );
+    let error = load_error(&app, "oversized.qb").await;
+    assert!(
+        error.contains("at byte 914: matrix of size [100, 100, 100] is too large"),
+        "The matrices of a file share a limit on their size: {error}"
+    );
+}
+
+#[async_std::test]
+async fn test_model_index_out_of_range() {
+    let mut app = App::new();
+    setup_app(&mut app);
+    let error = load_error(&app, "missing-model.vox").await;
+    assert!(
+        error.contains("shape node 1 references model 3, but the file only has 1 models"),
+        "{error}"
+    );
+}
+
+#[async_std::test]
+async fn test_strict_loading() {
+    let mut app = App::new();
+    setup_app(&mut app);
+    let assets = app.world().resource::<AssetServer>();
+    assert!(
+        assets
+            .load_untyped_async("nested-transform.vox")
+            .await
+            .is_ok(),
+        "Nested transforms are a warning by default"
+    );
+
+    let mut app = App::new();
+    setup_app_with_plugin(
+        &mut app,
+        VoxScenePlugin {
+            global_settings: Some(VoxLoaderSettings {
+                strict: true,
+                ..Default::default()
+            }),
+        },
+    );
+    let error = load_error(&app, "nested-transform.vox").await;
+    assert!(
+        error.contains("transform node 1 is the child of another transform node"),
+        "{error}"
+    );
+}
+
+#[cfg(feature = "generate_voxels")]
+#[test]
+fn test_palette_animation() {
+    use bevy::{asset::AssetEvent, ecs::event::Events, time::TimeUpdateStrategy};
+
+    let mut app = App::new();
+    setup_app(&mut app);
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+        100,
+    )));
+    let red = Color::srgb(1.0, 0.0, 0.0);
+    let blue = Color::srgb(0.0, 0.0, 1.0);
+    let lamp = VoxelElement {
+        color: Color::WHITE,
+        emission: 1.0,
+        ..default()
+    };
+    let palette = VoxelPalette::new(
+        vec![
+            VoxelElement {
+                color: red,
+                ..default()
+            },
+            VoxelElement {
+                color: blue,
+                ..default()
+            },
+            lamp,
+        ],
+        true,
+    );
+    let world = app.world_mut();
+    let context = VoxelContext::new(world, palette).expect("Context has been created");
+    world.spawn(VoxelPaletteAnimation::new(
+        context.clone(),
+        vec![
+            PaletteEffect::Cycle {
+                first: Voxel(1),
+                last: Voxel(2),
+                step: Duration::from_millis(100),
+            },
+            PaletteEffect::Blink {
+                voxels: vec![Voxel(3)],
+                period: Duration::from_millis(200),
+                duty: 0.5,
+            },
+        ],
+    ));
+    let texels = |app: &App| {
+        let context = app
+            .world()
+            .resource::<Assets<VoxelContext>>()
+            .get(&context)
+            .expect("context");
+        let material = app
+            .world()
+            .resource::<Assets<StandardMaterial>>()
+            .get(&context.opaque_material)
+            .expect("material");
+        let images = app.world().resource::<Assets<Image>>();
+        let color = images
+            .get(material.base_color_texture.as_ref().expect("color texture"))
+            .expect("color image");
+        let emission = images
+            .get(
+                material
+                    .emissive_texture
+                    .as_ref()
+                    .expect("emissive texture"),
+            )
+            .expect("emission image");
+        (
+            color.data[0..4].to_vec(),
+            f32::from_le_bytes(emission.data[32..36].try_into().expect("4 bytes")),
+        )
+    };
+    // the first frame has no elapsed time
+    app.update();
+    assert_eq!(
+        texels(&app),
+        (vec![255, 0, 0, 255], 1.0),
+        "Starts unchanged"
+    );
+    app.update();
+    assert_eq!(
+        texels(&app),
+        (vec![0, 0, 255, 255], 0.0),
+        "Colors are cycled, and the lamp is switched off"
+    );
+    app.update();
+    assert_eq!(
+        texels(&app),
+        (vec![255, 0, 0, 255], 1.0),
+        "The cycle wraps around, and the lamp is lit again"
+    );
+
+    let image = app
+        .world_mut()
+        .resource_mut::<Assets<Image>>()
+        .add(Image::default());
+    let unrelated = app
+        .world_mut()
+        .resource_mut::<Assets<StandardMaterial>>()
+        .add(StandardMaterial {
+            base_color_texture: Some(image),
+            ..default()
+        });
+    app.update();
+    let mut cursor = app
+        .world()
+        .resource::<Events<AssetEvent<StandardMaterial>>>()
+        .get_cursor_current();
+    app.update();
+    app.update();
+    let modified: HashSet<_> = cursor
+        .read(
+            app.world()
+                .resource::<Events<AssetEvent<StandardMaterial>>>(),
+        )
+        .filter_map(|event| match event {
+            AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+    let opaque_material = app
+        .world()
+        .resource::<Assets<VoxelContext>>()
+        .get(&context)
+        .expect("context")
+        .opaque_material
+        .id();
+    assert!(
+        modified.contains(&opaque_material),
+        "The materials using the palette are rebound to its images"
+    );
+    assert!(
+        !modified.contains(&unrelated.id()),
+        "Other materials are left alone"
+    );
+}
+
+#[test]
+fn test_load_palette_files() {
+    let mut app = App::new();
+    setup_app(&mut app);
+    let assets = app.world().resource::<AssetServer>();
+    let handles: Vec<(&str, Handle<VoxelPalette>)> = [
+        "palette.gpl",
+        "test.palette.txt",
+        "test.palette.hex",
+        "palette.pal",
+        #[cfg(feature = "palette_images")]
+        "test.palette.png",
+    ]
+    .into_iter()
+    .map(|path| (path, assets.load(path)))
+    .collect();
+    let malformed: Handle<VoxelPalette> = assets.load("malformed-palette.pal");
+    for _ in 0..100 {
+        app.update();
+        let assets = app.world().resource::<AssetServer>();
+        let is_done = |id| {
+            matches!(
+                assets.load_state(id),
+                LoadState::Loaded | LoadState::Failed(_)
+            )
+        };
+        if handles
+            .iter()
+            .all(|(_, handle)| is_done(handle.id().untyped()))
+            && is_done(malformed.id().untyped())
+        {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let palettes = app.world().resource::<Assets<VoxelPalette>>();
+    let expected = [
+        Color::srgb_u8(255, 128, 0),
+        Color::srgb_u8(16, 32, 64),
+        Color::srgb_u8(0, 200, 100),
+    ];
+    for (path, handle) in handles.iter() {
+        let palette = palettes
+            .get(handle)
+            .unwrap_or_else(|| panic!("{path} is loaded"));
+        let colors: Vec<Color> = palette.elements[0..3].iter().map(|e| e.color).collect();
+        assert_eq!(colors, expected, "Colors of {path}");
+    }
+    let assets = app.world().resource::<AssetServer>();
+    let Some(LoadState::Failed(error)) = assets.get_load_state(&malformed) else {
+        panic!("Loading the malformed palette fails");
+    };
+    assert!(
+        error.to_string().contains("failed to parse line 5"),
+        "The error points to the malformed line: {error}"
+    );
+    let is_palette_loader = |extension| {
+        bevy::tasks::block_on(assets.get_asset_loader_with_extension(extension))
+            .is_ok_and(|loader| loader.asset_type_id() == std::any::TypeId::of::<VoxelPalette>())
+    };
+    for extension in [
+        "palette.txt",
+        "palette.hex",
+        #[cfg(feature = "palette_images")]
+        "palette.png",
+    ] {
+        assert!(
+            is_palette_loader(extension),
+            ".{extension} files are palettes"
+        );
+    }
+    for extension in ["txt", "hex"] {
+        assert!(
+            !is_palette_loader(extension),
+            "Plain .{extension} files aren't loaded as palettes"
+        );
+    }
+}
+
+#[async_std::test]
+async fn test_load_qubicle() {
+    let mut app = App::new();
+    let handle = setup_and_load_voxel_scene(&mut app, "test.qb").await;
+    app.update();
+    assert!(
+        app.world()
+            .resource::<Assets<Scene>>()
+            .contains(handle.id()),
+        "The scene is loaded"
+    );
+    let tower: Handle<Scene> = app
+        .world()
+        .resource::<AssetServer>()
+        .load_untyped_async("test.qb#tower")
+        .await
+        .expect("Named matrices are subassets")
+        .typed();
+    app.update();
+    assert!(app.world().resource::<Assets<Scene>>().contains(tower.id()));
+
+    let models = app.world().resource::<Assets<VoxelModel>>();
+    let model = |name: &str| {
+        models
+            .iter()
+            .find(|(_, model)| model.name == name)
+            .unwrap_or_else(|| panic!("model {name} is loaded"))
+            .1
+    };
+    let mut names: Vec<&str> = models
+        .iter()
+        .map(|(_, model)| model.name.as_str())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec!["gradient", "model-3", "tower", "wall/part-0", "wall/part-1"],
+        "The wall is split in two, as it is longer than 256 voxels"
+    );
+    let context = app
+        .world()
+        .resource::<Assets<VoxelContext>>()
+        .iter()
+        .next()
+        .expect("context")
+        .1;
+    let color_at = |model: &VoxelModel, point: IVec3| {
+        let voxel = model.get_voxel_at_point(point).expect("in bounds");
+        assert_ne!(voxel, Voxel::EMPTY);
+        context.palette.elements[RawVoxel::from(voxel).0 as usize].color
+    };
+    let tower = model("tower");
+    assert_eq!(tower.size(), IVec3::new(2, 3, 2));
+    assert_eq!(
+        color_at(tower, IVec3::new(0, 0, 0)),
+        Color::srgb_u8(255, 0, 0)
+    );
+    assert_eq!(
+        color_at(tower, IVec3::new(1, 2, 1)),
+        Color::srgb_u8(0, 0, 255)
+    );
+
+    let mut voxels = HashSet::new();
+    for (_, model) in models.iter() {
+        let size = model.size();
+        for x in 0..size.x {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    voxels.insert(
+                        model
+                            .get_voxel_at_point(IVec3::new(x, y, z))
+                            .expect("in bounds")
+                            .0,
+                    );
+                }
+            }
+        }
+    }
+    voxels.remove(&Voxel::EMPTY.0);
+    assert_eq!(
+        voxels.len(),
+        255,
+        "The 516 colors are reduced to a full palette"
+    );
+}
+
+#[cfg(feature = "goxel")]
+#[async_std::test]
+async fn test_load_goxel() {
+    let mut app = App::new();
+    let handle = setup_and_load_voxel_scene(&mut app, "test.gox").await;
+    app.update();
+    app.world_mut().spawn(SceneRoot(handle));
+    app.update();
+    let mut layers: Vec<(String, Option<String>, Visibility)> = app
+        .world_mut()
+        .query::<(&Name, &VoxelLayer, &Visibility)>()
+        .iter(app.world())
+        .map(|(name, layer, visibility)| (name.to_string(), layer.name.clone(), *visibility))
+        .collect();
+    layers.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        layers,
+        vec![
+            (
+                "floor".to_string(),
+                Some("floor".to_string()),
+                Visibility::Inherited
+            ),
+            (
+                "lamp".to_string(),
+                Some("lamp".to_string()),
+                Visibility::Hidden
+            ),
+        ],
+        "Each Goxel layer is a model in a voxel layer of the same name"
+    );
+
+    let context: Handle<VoxelContext> = app
+        .world()
+        .resource::<AssetServer>()
+        .load("test.gox#voxel-context");
+    let world = app.world();
+    let models = world.resource::<Assets<VoxelModel>>();
+    let palette = &world
+        .resource::<Assets<VoxelContext>>()
+        .get(&context)
+        .expect("context")
+        .palette;
+    let floor = models
+        .iter()
+        .find(|(_, model)| model.name == "floor")
+        .expect("floor")
+        .1;
+    assert_eq!(floor.size(), IVec3::new(4, 1, 3), "Goxel is Z-up");
+    let color_at = |point: IVec3| {
+        let voxel = floor.get_voxel_at_point(point).expect("in bounds");
+        assert_ne!(voxel, Voxel::EMPTY);
+        palette.elements[RawVoxel::from(voxel).0 as usize].color
+    };
+    assert_eq!(color_at(IVec3::new(0, 0, 2)), Color::srgb_u8(255, 0, 0));
+    assert_eq!(color_at(IVec3::new(3, 0, 0)), Color::srgb_u8(0, 0, 255));
+}
+
+#[async_std::test]
+async fn test_load_kvx() {
+    let mut app = App::new();
+    let handle = setup_and_load_voxel_scene(&mut app, "test.kvx").await;
+    app.update();
+    assert!(app
+        .world()
+        .resource::<Assets<Scene>>()
+        .contains(handle.id()));
+
+    let context: Handle<VoxelContext> = app
+        .world()
+        .resource::<AssetServer>()
+        .load("test.kvx#voxel-context");
+    let world = app.world();
+    let models = world.resource::<Assets<VoxelModel>>();
+    let palette = &world
+        .resource::<Assets<VoxelContext>>()
+        .get(&context)
+        .expect("context")
+        .palette;
+    let sprite = models
+        .iter()
+        .find(|(_, model)| model.name == "model-0")
+        .expect("sprite")
+        .1;
+    assert_eq!(sprite.size(), IVec3::new(3, 4, 2), "KVX is Z-down");
+    let color_at = |point: IVec3| {
+        let voxel = sprite.get_voxel_at_point(point).expect("in bounds");
+        assert_ne!(voxel, Voxel::EMPTY);
+        palette.elements[RawVoxel::from(voxel).0 as usize].color
+    };
+    assert_eq!(color_at(IVec3::new(0, 2, 0)), Color::srgb_u8(255, 0, 0));
+    assert_eq!(color_at(IVec3::new(2, 0, 1)), Color::srgb_u8(0, 255, 0));
+    assert_eq!(
+        color_at(IVec3::new(0, 3, 0)),
+        Color::srgb_u8(0, 0, 251),
+        "The last color of the palette is replaced by the closest one"
+    );
+}
+
+#[cfg(feature = "goxel")]
+#[async_std::test]
+async fn test_goxel_block_out_of_range() {
+    let mut app = App::new();
+    setup_app(&mut app);
+    let error = load_error(&app, "out-of-range.gox").await;
+    assert!(
+        error.contains("failed to parse the Goxel file at byte 207: block at [0, 0, 2147483647] is out of range"),
+        "{error}"
+    );
+}
+
+#[cfg(feature = "schematics")]
+#[async_std::test]
+async fn test_load_schematics() {
+    let blocks = SchematicBlocks::default();
+    assert!(
+        blocks.get("oak_planks[axis=y]").is_some(),
+        "Block states and the default namespace are ignored when looking up blocks"
+    );
+    let mut app = App::new();
+    let handle = setup_and_load_voxel_scene(&mut app, "test.schem").await;
+    app.update();
+    assert!(app
+        .world()
+        .resource::<Assets<Scene>>()
+        .contains(handle.id()));
+    let structure: Handle<Scene> = app
+        .world()
+        .resource::<AssetServer>()
+        .load_untyped_async("test.nbt")
+        .await
+        .expect("Structure block files are loaded")
+        .typed();
+    app.update();
+    assert!(app
+        .world()
+        .resource::<Assets<Scene>>()
+        .contains(structure.id()));
+
+    let asset_server = app.world().resource::<AssetServer>();
+    let schem_context: Handle<VoxelContext> = asset_server.load("test.schem#voxel-context");
+    let nbt_context: Handle<VoxelContext> = asset_server.load("test.nbt#voxel-context");
+    let world = app.world();
+    let models = world.resource::<Assets<VoxelModel>>();
+    let contexts = world.resource::<Assets<VoxelContext>>();
+    let model = |name: &str| {
+        models
+            .iter()
+            .find(|(_, model)| model.name == name)
+            .unwrap_or_else(|| panic!("model {name} is loaded"))
+            .1
+    };
+    let element_at = |name: &str, point: IVec3| {
+        let voxel = model(name).get_voxel_at_point(point).expect("in bounds");
+        assert_ne!(voxel, Voxel::EMPTY);
+        let context = if name == "model-0" {
+            &nbt_context
+        } else {
+            &schem_context
+        };
+        contexts.get(context).expect("context").palette.elements[RawVoxel::from(voxel).0 as usize]
+            .clone()
+    };
+    assert_eq!(
+        model("bridge/part-0").size(),
+        IVec3::new(256, 2, 1),
+        "Structures longer than 256 blocks are split"
+    );
+    assert_eq!(model("bridge/part-1").size(), IVec3::new(44, 2, 1));
+    assert_eq!(
+        element_at("bridge/part-0", IVec3::new(10, 0, 0)).color,
+        Color::srgb_u8(125, 125, 125)
+    );
+    assert!(element_at("bridge/part-1", IVec3::new(43, 1, 0)).emission > 0.0);
+    assert_eq!(
+        element_at("bridge/part-0", IVec3::new(0, 1, 0)).color,
+        Color::srgb_u8(160, 160, 160),
+        "Unknown blocks use the fallback element"
+    );
+    assert_eq!(
+        model("bridge/part-0")
+            .get_voxel_at_point(IVec3::new(2, 1, 0))
+            .expect("in bounds"),
+        Voxel::EMPTY,
+        "Air is empty"
+    );
+    assert_eq!(model("model-0").size(), IVec3::new(2, 2, 2));
+    assert_eq!(
+        element_at("model-0", IVec3::new(0, 0, 0)).color,
+        Color::srgb_u8(162, 130, 78)
+    );
+    assert_eq!(
+        element_at("model-0", IVec3::new(1, 1, 1)).color,
+        Color::srgb_u8(160, 160, 160)
+    );
+    assert_eq!(
+        model("model-0")
+            .get_voxel_at_point(IVec3::new(0, 1, 0))
+            .expect("in bounds"),
+        Voxel::EMPTY
+    );
+
+    let mut app = App::new();
+    setup_app(&mut app);
+    let mut blocks = SchematicBlocks::empty();
+    blocks.insert(
+        "oak_planks",
+        VoxelElement {
+            color: Color::WHITE,
+            ..default()
+        },
+    );
+    app.insert_resource(blocks);
+    app.update();
+    let asset_server = app.world().resource::<AssetServer>();
+    asset_server
+        .load_untyped_async("test.nbt")
+        .await
+        .expect("Structure block files are loaded");
+    let context: Handle<VoxelContext> = asset_server.load("test.nbt#voxel-context");
+    app.update();
+    let world = app.world();
+    let palette = &world
+        .resource::<Assets<VoxelContext>>()
+        .get(&context)
+        .expect("context")
+        .palette;
+    assert_eq!(
+        palette.elements[0].color,
+        Color::WHITE,
+        "Blocks are mapped with the SchematicBlocks resource"
+    );
+    let (_, model) = world
+        .resource::<Assets<VoxelModel>>()
+        .iter()
+        .find(|(_, model)| model.name == "model-0")
+        .expect("model is loaded");
+    assert_eq!(
+        model
+            .get_voxel_at_point(IVec3::new(1, 1, 1))
+            .expect("in bounds"),
+        Voxel::EMPTY,
+        "Unknown blocks are left out without a fallback"
+    );
+}
+
+#[cfg(feature = "schematics")]
+#[async_std::test]
+async fn test_oversized_schematic() {
+    let mut app = App::new();
+    setup_app(&mut app);
+    let error = load_error(&app, "oversized.schem").await;
+    assert!(
+        error.contains("the structure of size 200x200x200 has more than"),
+        "{error}"
+    );
+}
+
+#[cfg(feature = "export_gltf")]
+#[async_std::test]
+async fn test_export_glb() {
+    let glb_json = |bytes: &[u8]| -> serde_json::Value {
+        let word =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&bytes[0..4], b"glTF");
+        assert_eq!(word(4), 2, "glTF version 2");
+        assert_eq!(
+            word(8) as usize,
+            bytes.len(),
+            "Header gives the file length"
+        );
+        let json_length = word(12) as usize;
+        let json: serde_json::Value =
+            serde_json::from_slice(&bytes[20..20 + json_length]).expect("valid JSON");
+        let bin_length = word(20 + json_length) as usize;
+        assert_eq!(&bytes[24 + json_length..28 + json_length], b"BIN\0");
+        assert_eq!(
+            json["buffers"][0]["byteLength"].as_u64(),
+            Some(bin_length as u64)
+        );
+        json
+    };
+    let mut app = App::new();
+    let handle = setup_and_load_voxel_scene(&mut app, "test.vox#outer-group/inner-group").await;
+    app.update();
+    let mut bytes = Vec::new();
+    let world = app.world();
+    let scene = world
+        .resource::<Assets<Scene>>()
+        .get(&handle)
+        .expect("scene");
+    write_glb_scene(world, scene, &mut bytes).expect("export the scene");
+    let json = glb_json(&bytes);
+    let nodes = json["nodes"].as_array().expect("nodes");
+    let names: Vec<&str> = nodes
+        .iter()
+        .filter_map(|node| node["name"].as_str())
+        .collect();
+    for name in ["outer-group/inner-group", "dice", "walls"] {
+        assert!(names.contains(&name), "Node {name} is written");
+    }
+    let root = &json["nodes"][json["scenes"][0]["nodes"][0].as_u64().unwrap() as usize];
+    assert_eq!(root["name"], "outer-group/inner-group");
+    assert!(!root["children"].as_array().expect("children").is_empty());
+    let materials = json["materials"].as_array().expect("materials");
+    assert!(materials
+        .iter()
+        .any(|material| material["pbrMetallicRoughness"]["baseColorTexture"].is_object()));
+    assert!(
+        materials
+            .iter()
+            .any(|material| material["extensions"]["KHR_materials_transmission"].is_object()),
+        "The walls are translucent"
+    );
+    assert!(!json["images"].as_array().expect("images").is_empty());
+    assert!(json["meshes"]
+        .as_array()
+        .expect("meshes")
+        .iter()
+        .all(|mesh| mesh["primitives"][0]["attributes"]["POSITION"].is_u64()));
+
+    app.world_mut().spawn(SceneRoot(handle));
+    app.update();
+    let instances: Vec<Entity> = app
+        .world_mut()
+        .query_filtered::<Entity, With<VoxelModelInstance>>()
+        .iter(app.world())
+        .collect();
+    let mut bytes = Vec::new();
+    write_glb_instances(app.world(), &instances, &mut bytes).expect("export the instances");
+    let json = glb_json(&bytes);
+    assert_eq!(
+        json["scenes"][0]["nodes"].as_array().expect("nodes").len(),
+        instances.len(),
+        "Each instance is a node at the root of the scene"
+    );
+}
+
+async fn load_error(app: &App, filename: &'static str) -> String {
+    let assets = app.world().resource::<AssetServer>();
+    assets
+        .load_untyped_async(filename)
+        .await
+        .expect_err("Loading fails")
+        .to_string()
+}
+
+async fn setup_and_load_voxel_scene(app: &mut App, filename: &'static str) -> Handle<Scene> {
+    setup_app(app);
+    let assets = app.world().resource::<AssetServer>();
+    assets
+        .load_untyped_async(filename)
+        .await
+        .unwrap_or_else(|_| panic!("Loaded {filename}"))
+        .typed::<Scene>()
+}
+
+fn setup_app(app: &mut App) {
+    setup_app_with_plugin(app, VoxScenePlugin::default());
+}
+
+fn setup_app_with_plugin(app: &mut App, plugin: VoxScenePlugin) {
     app.add_plugins((
         MinimalPlugins,
         AssetPlugin::default(),
         ImagePlugin::default(),
         ScenePlugin,
         HierarchyPlugin,
-        VoxScenePlugin::default(),
+        plugin,
     ))
     .init_asset::<StandardMaterial>()
     .init_asset::<Mesh>()
     .init_asset::<Scene>()
+    .init_asset::<AnimationClip>()
+    .init_asset::<AnimationGraph>()
+    .register_type::<AnimationTarget>()
+    .register_type::<AnimationPlayer>()
+    .register_type::<AnimationGraphHandle>()
     .register_type::<Visibility>()
     .register_type::<ViewVisibility>()
     .register_type::<InheritedVisibility>()